@@ -0,0 +1,366 @@
+//! Module implementing offline ASN and country lookups from local database files.
+//!
+//! Nothing goes over the network, all the data comes from files you download yourself.  We
+//! support two formats:
+//!
+//! - MaxMind-format databases (`.mmdb`) like GeoLite2-ASN and GeoLite2-Country (or any database
+//!   using the same field names: `autonomous_system_number`, `autonomous_system_organization`
+//!   and `country.iso_code`),
+//! - the TSV dumps from [iptoasn] (`ip2asn-v4.tsv`, `ip2asn-v6.tsv`, `ip2asn-combined.tsv`,
+//!   possibly gzipped) with `range_start`, `range_end`, `AS_number`, `country_code` and
+//!   `AS_description` columns.
+//!
+//! Several files can be loaded in the same `AsnDb`, the first one having an answer for a given
+//! field wins.  That way you can combine an ASN database with a country one.
+//!
+//! Example:
+//! ```no_run
+//! # use dmarc_rs::asn::AsnDb;
+//! let mut db = AsnDb::new();
+//! db.add("GeoLite2-ASN.mmdb").unwrap();
+//! db.add("GeoLite2-Country.mmdb").unwrap();
+//!
+//! let info = db.lookup(&"1.1.1.1".parse().unwrap());
+//! ```
+//!
+//! [iptoasn]: https://iptoasn.com/
+
+// Std library
+//
+use std::fmt::{Debug, Formatter};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::Path;
+
+// External crates
+//
+use anyhow::{anyhow, Result};
+use flate2::read::GzDecoder;
+use maxminddb::Reader;
+use serde::{Deserialize, Serialize};
+
+/// What we know about the network an IP belongs to.
+///
+#[derive(Clone, Debug, Default, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+pub struct AsnInfo {
+    /// AS number, 0 if unknown
+    pub asn: u32,
+    /// AS name, possibly empty
+    pub name: String,
+    /// ISO country code, possibly empty
+    pub country: String,
+}
+
+impl AsnInfo {
+    /// Fill in the missing fields from another answer.
+    ///
+    fn merge(&mut self, other: AsnInfo) {
+        if self.asn == 0 {
+            self.asn = other.asn;
+            self.name = other.name;
+        }
+        if self.country.is_empty() {
+            self.country = other.country;
+        }
+    }
+
+    /// Do we know everything?
+    ///
+    fn is_complete(&self) -> bool {
+        self.asn != 0 && !self.country.is_empty()
+    }
+}
+
+/// Fields we look for in MaxMind-format databases.
+///
+#[derive(Debug, Deserialize)]
+struct MmdbRecord {
+    autonomous_system_number: Option<u32>,
+    autonomous_system_organization: Option<String>,
+    country: Option<MmdbCountry>,
+}
+
+/// Country part of a MaxMind record.
+///
+#[derive(Debug, Deserialize)]
+struct MmdbCountry {
+    iso_code: Option<String>,
+}
+
+/// One line of an iptoasn dump.
+///
+#[derive(Clone, Debug)]
+struct Range {
+    start: IpAddr,
+    end: IpAddr,
+    info: AsnInfo,
+}
+
+/// One of the loaded files.
+///
+enum Source {
+    /// MaxMind-format database
+    Mmdb(Reader<Vec<u8>>),
+    /// iptoasn ranges, sorted by start address
+    Tsv(Vec<Range>),
+}
+
+impl Source {
+    /// Lookup one IP in this source.
+    ///
+    fn lookup(&self, ip: &IpAddr) -> Option<AsnInfo> {
+        match self {
+            Source::Mmdb(r) => {
+                let rec: MmdbRecord = r.lookup(*ip).ok()?.decode().ok()??;
+                Some(AsnInfo {
+                    asn: rec.autonomous_system_number.unwrap_or(0),
+                    name: rec.autonomous_system_organization.unwrap_or_default(),
+                    country: rec.country.and_then(|c| c.iso_code).unwrap_or_default(),
+                })
+            }
+            Source::Tsv(l) => {
+                let i = l.partition_point(|r| r.start <= *ip);
+                if i == 0 {
+                    return None;
+                }
+                let r = &l[i - 1];
+                match r.start.is_ipv4() == ip.is_ipv4() && *ip <= r.end {
+                    true => Some(r.info.clone()),
+                    false => None,
+                }
+            }
+        }
+    }
+}
+
+/// A set of local databases to lookup IPs into.
+///
+#[derive(Default)]
+pub struct AsnDb {
+    sources: Vec<Source>,
+}
+
+impl AsnDb {
+    /// Create an empty set, every lookup will fail.
+    ///
+    #[inline]
+    pub fn new() -> Self {
+        AsnDb::default()
+    }
+
+    /// Create a set with one database.
+    ///
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut db = AsnDb::new();
+        db.add(path)?;
+        Ok(db)
+    }
+
+    /// Load one more database file, the format being selected on the extension: `.mmdb` is a
+    /// MaxMind database, anything else is an iptoasn dump (gzipped if ending in `.gz`).
+    ///
+    /// A dump without any usable line is an error, it is most likely not a dump at all.
+    ///
+    pub fn add<P: AsRef<Path>>(&mut self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let ext = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+
+        let src = match ext.as_str() {
+            "mmdb" => match Reader::open_readfile(path) {
+                Ok(r) => Source::Mmdb(r),
+                Err(e) => return Err(anyhow!("{}: {}", path.display(), e)),
+            },
+            ext => {
+                let f = File::open(path).map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+                let list = match ext {
+                    "gz" => read_tsv(GzDecoder::new(f)),
+                    _ => read_tsv(f),
+                }
+                .map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+                if list.is_empty() {
+                    return Err(anyhow!("{}: no AS range found", path.display()));
+                }
+                Source::Tsv(list)
+            }
+        };
+        self.sources.push(src);
+        Ok(())
+    }
+
+    /// Number of loaded databases.
+    ///
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.sources.len()
+    }
+
+    /// Is there any database loaded?
+    ///
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    /// Lookup one IP in all the databases, merging the answers.
+    ///
+    pub fn lookup(&self, ip: &IpAddr) -> Option<AsnInfo> {
+        let mut res: Option<AsnInfo> = None;
+
+        for src in self.sources.iter() {
+            if let Some(info) = src.lookup(ip) {
+                match res.as_mut() {
+                    Some(r) => r.merge(info),
+                    None => res = Some(info),
+                }
+            }
+            if res.as_ref().map(|r| r.is_complete()).unwrap_or(false) {
+                break;
+            }
+        }
+        res
+    }
+}
+
+impl Debug for AsnDb {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "asndb with {} source(s)", self.sources.len())
+    }
+}
+
+/// Parse an address from an iptoasn dump, either in the usual notation or as a 32-bit integer
+/// (`ip2asn-v4-u32.tsv`).
+///
+fn parse_addr(s: &str) -> Option<IpAddr> {
+    match s.parse::<IpAddr>() {
+        Ok(ip) => Some(ip),
+        Err(_) => s.parse::<u32>().ok().map(|n| IpAddr::V4(Ipv4Addr::from(n))),
+    }
+}
+
+/// Read an iptoasn dump, skipping unrouted ranges and invalid lines.
+///
+fn read_tsv<R: Read>(r: R) -> Result<Vec<Range>> {
+    let mut list = vec![];
+
+    for line in BufReader::new(r).lines() {
+        let line = line?;
+        let f: Vec<&str> = line.trim_end().split('\t').collect();
+        if f.len() < 5 {
+            continue;
+        }
+        let (start, end) = match (parse_addr(f[0]), parse_addr(f[1])) {
+            (Some(s), Some(e)) => (s, e),
+            _ => continue,
+        };
+        let asn = match f[2].parse::<u32>() {
+            Ok(0) | Err(_) => continue,
+            Ok(n) => n,
+        };
+        let country = match f[3] {
+            "None" | "Unknown" => "",
+            c => c,
+        };
+        list.push(Range {
+            start,
+            end,
+            info: AsnInfo {
+                asn,
+                name: f[4].to_owned(),
+                country: country.to_owned(),
+            },
+        });
+    }
+    list.sort_by_key(|r| r.start);
+    Ok(list)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+
+    const TSV: &str = "testdata/ip2asn-test.tsv";
+    const MMDB: &str = "testdata/asn-test.mmdb";
+
+    fn info(asn: u32, name: &str, country: &str) -> AsnInfo {
+        AsnInfo {
+            asn,
+            name: name.into(),
+            country: country.into(),
+        }
+    }
+
+    #[rstest]
+    #[case("192.0.2.10", Some(info(64496, "EXAMPLE-AS-1", "FR")))]
+    #[case("198.51.100.255", Some(info(64497, "EXAMPLE-AS-2", "")))]
+    #[case("2001:db8:1:2::25", Some(info(64498, "EXAMPLE-AS-3", "DE")))]
+    #[case("203.0.113.1", None)]
+    #[case("2001:db9::1", None)]
+    #[case("1.1.1.1", None)]
+    fn test_tsv_lookup(#[case] ip: &str, #[case] r: Option<AsnInfo>) {
+        let db = AsnDb::open(TSV).unwrap();
+        assert_eq!(r, db.lookup(&ip.parse().unwrap()));
+    }
+
+    #[rstest]
+    #[case("192.0.2.10", Some(info(64496, "EXAMPLE-AS-1", "FR")))]
+    #[case("192.0.2.255", Some(info(64496, "EXAMPLE-AS-1", "FR")))]
+    #[case("192.0.3.1", None)]
+    fn test_mmdb_lookup(#[case] ip: &str, #[case] r: Option<AsnInfo>) {
+        let db = AsnDb::open(MMDB).unwrap();
+        assert_eq!(r, db.lookup(&ip.parse().unwrap()));
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut db = AsnDb::new();
+        db.add(TSV).unwrap();
+        db.add(MMDB).unwrap();
+        assert_eq!(2, db.len());
+
+        // TSV has no country for this one and the mmdb file has nothing
+        let r = db.lookup(&"198.51.100.7".parse().unwrap()).unwrap();
+        assert_eq!(info(64497, "EXAMPLE-AS-2", ""), r);
+
+        let mut a = info(0, "", "FR");
+        a.merge(info(64496, "EXAMPLE-AS-1", "DE"));
+        assert_eq!(info(64496, "EXAMPLE-AS-1", "FR"), a);
+    }
+
+    #[test]
+    fn test_empty() {
+        let db = AsnDb::new();
+
+        assert!(db.is_empty());
+        assert!(db.lookup(&"192.0.2.10".parse().unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_open_nonexistent() {
+        assert!(AsnDb::open("/nonexistent.mmdb").is_err());
+        let e = AsnDb::open("/nonexistent.tsv").unwrap_err();
+        assert!(e.to_string().starts_with("/nonexistent.tsv: "), "{}", e);
+        let e = AsnDb::open("/nonexistent.tsv.gz").unwrap_err();
+        assert!(e.to_string().starts_with("/nonexistent.tsv.gz: "), "{}", e);
+    }
+
+    #[test]
+    fn test_open_no_range() {
+        let e = AsnDb::open("testdata/hosts").unwrap_err();
+        assert_eq!("testdata/hosts: no AS range found", e.to_string());
+    }
+
+    #[rstest]
+    #[case("192.0.2.1", Some("192.0.2.1"))]
+    #[case("3221225985", Some("192.0.2.1"))]
+    #[case("2001:db8::1", Some("2001:db8::1"))]
+    #[case("foo", None)]
+    fn test_parse_addr(#[case] s: &str, #[case] r: Option<&str>) {
+        assert_eq!(r.map(|r| r.parse::<IpAddr>().unwrap()), parse_addr(s));
+    }
+}
@@ -0,0 +1,401 @@
+//! Module implementing an async resolving engine, available with the `async` feature.
+//!
+//! Resolving tens of thousands of IPs with one thread per lookup does not scale well.  An
+//! `AsyncSolver` runs all the lookups of a list as tasks on a [tokio] runtime with:
+//!
+//! - a limit on the number of lookups in flight (a semaphore),
+//! - an optional global deadline after which the lookups not done yet are abandoned and marked as
+//!   timed out (`SolveError::Timeout`, a temporary failure),
+//! - an optional `Cancel` handle, lookups not started yet when it is cancelled being returned
+//!   without any name like the sync solvers do (lookups in progress are finished),
+//! - an optional `Metrics` handle recording the queries of our DNS client, any other `Solver`
+//!   being wrapped with `Metrics::wrap()` as usual.
+//!
+//! It works with any `Solver`, lookups being run on the blocking thread pool of the runtime (at
+//! most `limit` threads), so caching, FCrDNS and the other wrappers keep working.  With a
+//! `DnsResolver`, the queries are done natively without blocking any thread and thousands of
+//! them can be in flight.
+//!
+//! The result is in the order of the list, like the sync solvers.
+//!
+//! Example:
+//! ```
+//! use dmarc_rs::asyncres::AsyncSolver;
+//! use dmarc_rs::iplist::IpList;
+//! use dmarc_rs::resolver::{res_init, ResType};
+//! use std::time::Duration;
+//!
+//! let l = IpList::from(["192.0.2.1", "192.0.2.2"]);
+//! let engine = AsyncSolver::new(res_init(ResType::Null))
+//!     .with_limit(1000)
+//!     .with_deadline(Duration::from_secs(60));
+//!
+//! let r = engine.block_on(&l).unwrap();
+//! assert_eq!("192.0.2.2", r[1].name());
+//! ```
+//!
+//! [tokio]: https://tokio.rs/
+//!
+
+// Std library
+//
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+// Our crates
+//
+use crate::bulk::Cancel;
+use crate::dns::DnsResolver;
+use crate::ip::Ip;
+use crate::iplist::IpList;
+use crate::metrics::Metrics;
+use crate::resolver::{SolveError, Solver};
+
+// External crates
+//
+use tokio::runtime::Builder;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::{timeout_at, Instant};
+
+/// Default number of lookups in flight
+pub const DEF_LIMIT: usize = 256;
+
+/// How lookups are done.
+///
+#[derive(Clone)]
+enum Backend {
+    /// Any resolver, on the blocking thread pool
+    Blocking(Solver),
+    /// Our own DNS client, natively
+    Dns(Arc<DnsResolver>),
+}
+
+impl Backend {
+    /// Resolve one IP, recording the queries of our DNS client in `metrics`.
+    ///
+    async fn solve(&self, ip: &Ip, metrics: Option<&Metrics>) -> Result<Ip, SolveError> {
+        match self {
+            Backend::Blocking(res) => {
+                let (res, ip) = (res.clone(), ip.clone());
+                tokio::task::spawn_blocking(move || res.solve(&ip))
+                    .await
+                    .unwrap_or_else(|e| Err(SolveError::TempFail(e.to_string())))
+            }
+            Backend::Dns(dns) => {
+                let start = Instant::now();
+                let r = dns.solve_async(ip).await;
+                if let Some(m) = metrics {
+                    m.record(false, r.as_ref().err(), start.elapsed());
+                }
+                r
+            }
+        }
+    }
+}
+
+/// The async resolving engine.
+///
+#[derive(Clone)]
+pub struct AsyncSolver {
+    backend: Backend,
+    limit: usize,
+    deadline: Option<Duration>,
+    cancel: Cancel,
+    metrics: Option<Metrics>,
+}
+
+impl AsyncSolver {
+    /// Use any `Solver`, each lookup taking a thread from the blocking pool.
+    ///
+    pub fn new(res: Solver) -> Self {
+        AsyncSolver {
+            backend: Backend::Blocking(res),
+            limit: DEF_LIMIT,
+            deadline: None,
+            cancel: Cancel::new(),
+            metrics: None,
+        }
+    }
+
+    /// Use our DNS client without blocking any thread.
+    ///
+    pub fn dns(dns: DnsResolver) -> Self {
+        AsyncSolver {
+            backend: Backend::Dns(Arc::new(dns)),
+            limit: DEF_LIMIT,
+            deadline: None,
+            cancel: Cancel::new(),
+            metrics: None,
+        }
+    }
+
+    /// Set the maximum number of lookups in flight (at least 1).
+    ///
+    pub fn with_limit(self, limit: usize) -> Self {
+        AsyncSolver {
+            limit: limit.max(1),
+            ..self
+        }
+    }
+
+    /// Give up on the lookups not done after this time.
+    ///
+    pub fn with_deadline(self, deadline: Duration) -> Self {
+        AsyncSolver {
+            deadline: Some(deadline),
+            ..self
+        }
+    }
+
+    /// Stop resolving when this handle is cancelled.
+    ///
+    pub fn with_cancel(self, cancel: Cancel) -> Self {
+        AsyncSolver { cancel, ..self }
+    }
+
+    /// Record the queries of our DNS client in this handle, other resolvers should be wrapped
+    /// with `Metrics::wrap()` instead.
+    ///
+    pub fn with_metrics(self, metrics: &Metrics) -> Self {
+        AsyncSolver {
+            metrics: Some(metrics.clone()),
+            ..self
+        }
+    }
+
+    /// Resolve a list, failures being recorded in the `error` field of each `Ip`.
+    ///
+    pub async fn solve_all(&self, ipl: &IpList) -> IpList {
+        let sem = Arc::new(Semaphore::new(self.limit));
+        let deadline = self.deadline.map(|d| Instant::now() + d);
+        let mut full = ipl.clone();
+
+        let mut set = JoinSet::new();
+        for (i, ip) in ipl.clone().into_iter().enumerate() {
+            let (sem, backend) = (sem.clone(), self.backend.clone());
+            let (cancel, metrics) = (self.cancel.clone(), self.metrics.clone());
+
+            set.spawn(async move {
+                let work = async {
+                    if cancel.is_cancelled() {
+                        return None;
+                    }
+                    // The semaphore is never closed
+                    let _permit = sem.acquire().await.unwrap();
+                    match cancel.is_cancelled() {
+                        true => None,
+                        false => Some(backend.solve(&ip, metrics.as_ref()).await),
+                    }
+                };
+                let r = match deadline {
+                    Some(d) => timeout_at(d, work)
+                        .await
+                        .unwrap_or(Some(Err(SolveError::Timeout))),
+                    None => work.await,
+                };
+                match r {
+                    Some(r) => (i, mark(ip, r)),
+                    None => (i, ip),
+                }
+            });
+        }
+        while let Some(r) = set.join_next().await {
+            if let Ok((i, ip)) = r {
+                full[i] = ip;
+            }
+        }
+        full
+    }
+
+    /// Same as `solve_all()` from sync code, on a runtime of our own.
+    ///
+    pub fn block_on(&self, ipl: &IpList) -> io::Result<IpList> {
+        let rt = Builder::new_multi_thread()
+            .enable_all()
+            .max_blocking_threads(self.limit)
+            .build()?;
+        let r = rt.block_on(self.solve_all(ipl));
+        // Do not wait for abandoned lookups
+        rt.shutdown_background();
+        Ok(r)
+    }
+}
+
+impl Debug for AsyncSolver {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let backend = match self.backend {
+            Backend::Blocking(_) => "blocking",
+            Backend::Dns(_) => "dns",
+        };
+        write!(
+            f,
+            "asyncsolver {} limit={} deadline={:?} cancelled={} metrics={}",
+            backend,
+            self.limit,
+            self.deadline,
+            self.cancel.is_cancelled(),
+            self.metrics.is_some()
+        )
+    }
+}
+
+/// Record the outcome of a lookup like `Solver::solve_or_mark()`.
+///
+fn mark(ip: Ip, r: Result<Ip, SolveError>) -> Ip {
+    match r {
+        Ok(ip) => ip,
+        Err(e) => Ip {
+            names: vec![],
+            error: Some(e),
+            ..ip
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::{res_init, ResType, Resolver};
+
+    use std::net::IpAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread::sleep;
+
+    /// Slow resolver keeping track of the lookups in flight.
+    ///
+    #[derive(Clone, Default)]
+    struct Slow {
+        delay: Duration,
+        current: Arc<AtomicUsize>,
+        max: Arc<AtomicUsize>,
+    }
+
+    impl Resolver for Slow {
+        fn solve(&self, ip: &Ip) -> Result<Ip, SolveError> {
+            let n = self.current.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max.fetch_max(n, Ordering::SeqCst);
+            sleep(self.delay);
+            self.current.fetch_sub(1, Ordering::SeqCst);
+            Ok(Ip {
+                names: vec![ip.ip.to_string()],
+                ..ip.clone()
+            })
+        }
+
+        fn forward(&self, _name: &str) -> Result<Vec<IpAddr>, SolveError> {
+            Err(SolveError::NotFound)
+        }
+    }
+
+    fn list(n: usize) -> IpList {
+        (1..=n)
+            .map(|i| Ip::new(&format!("192.0.2.{}", i)))
+            .collect()
+    }
+
+    #[test]
+    fn test_block_on_order() {
+        let l = list(200);
+        let r = AsyncSolver::new(res_init(ResType::Null))
+            .block_on(&l)
+            .unwrap();
+
+        assert_eq!(200, r.len());
+        for (ip, r) in l.into_iter().zip(r) {
+            assert_eq!(ip.ip.to_string(), r.name());
+        }
+    }
+
+    #[test]
+    fn test_limit() {
+        let res = Slow {
+            delay: Duration::from_millis(20),
+            ..Slow::default()
+        };
+        let engine = AsyncSolver::new(Solver::new(res.clone())).with_limit(4);
+
+        let r = engine.block_on(&list(20)).unwrap();
+        assert!(r.into_iter().all(|ip| ip.error.is_none()));
+        assert!(res.max.load(Ordering::SeqCst) <= 4);
+    }
+
+    #[test]
+    fn test_deadline() {
+        let res = Slow {
+            delay: Duration::from_millis(300),
+            ..Slow::default()
+        };
+        let engine = AsyncSolver::new(Solver::new(res))
+            .with_limit(1)
+            .with_deadline(Duration::from_millis(100));
+
+        let start = std::time::Instant::now();
+        let r = engine.block_on(&list(5)).unwrap();
+        assert!(start.elapsed() < Duration::from_millis(300));
+        for ip in r {
+            assert_eq!(Some(SolveError::Timeout), ip.error);
+        }
+    }
+
+    #[test]
+    fn test_cancel() {
+        let res = Slow {
+            delay: Duration::from_millis(50),
+            ..Slow::default()
+        };
+        let cancel = Cancel::new();
+        let engine = AsyncSolver::new(Solver::new(res))
+            .with_limit(1)
+            .with_cancel(cancel.clone());
+
+        let c = cancel.clone();
+        let stop = std::thread::spawn(move || {
+            sleep(Duration::from_millis(75));
+            c.cancel();
+        });
+        let r = engine.block_on(&list(20)).unwrap();
+        stop.join().unwrap();
+
+        // The first lookups are done, the others are left alone
+        let done = r.iter().filter(|ip| !ip.names.is_empty()).count();
+        assert!(done > 0 && done < 20, "{}", done);
+        assert!(r.into_iter().all(|ip| ip.error.is_none()));
+    }
+
+    #[test]
+    fn test_metrics() {
+        let l = IpList::from(["192.0.2.1", "192.0.2.2"]);
+        let m = Metrics::new();
+
+        AsyncSolver::dns(DnsResolver::new(&[]))
+            .with_metrics(&m)
+            .block_on(&l)
+            .unwrap();
+        let stats = m.snapshot();
+        assert_eq!((2, 2), (stats.lookups, stats.failures));
+
+        // Only our DNS client, other resolvers are wrapped
+        AsyncSolver::new(res_init(ResType::Null))
+            .with_metrics(&m)
+            .block_on(&l)
+            .unwrap();
+        assert_eq!(2, m.snapshot().lookups);
+    }
+
+    #[test]
+    fn test_errors() {
+        let l = IpList::from(["192.0.2.1"]);
+        let r = AsyncSolver::dns(DnsResolver::new(&[]))
+            .block_on(&l)
+            .unwrap();
+
+        assert!(r[0].names.is_empty());
+        assert_eq!(
+            Some(SolveError::TempFail("no nameserver".into())),
+            r[0].error
+        );
+    }
+}
@@ -0,0 +1,197 @@
+//! Module implementing the attributes found about an IP or a record.
+//!
+//! `Attributes` is a set of named `Value`, filled by the enrichment stages (see `crate::enrich`)
+//! and carried by each `Ip` so that they can be displayed, grouped or exported with it.
+//!
+//! Example:
+//! ```
+//! use dmarc_rs::attrs::{Attributes, Value};
+//!
+//! let mut a = Attributes::new();
+//! a.set("dnsbl", vec!["bl.example".to_string()]);
+//! assert_eq!(Some(&Value::List(vec!["bl.example".into()])), a.get("dnsbl"));
+//! ```
+//!
+
+// Std library
+//
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+// External crates
+//
+use serde::{Deserialize, Serialize};
+
+/// Value of one attribute.
+///
+#[derive(Clone, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(untagged)]
+pub enum Value {
+    /// Yes or no
+    Flag(bool),
+    /// Counter or identifier
+    Number(u64),
+    /// Single value
+    Text(String),
+    /// Several values
+    List(Vec<String>),
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Flag(b) => write!(f, "{}", if *b { "yes" } else { "no" }),
+            Value::Number(n) => write!(f, "{}", n),
+            Value::Text(s) => write!(f, "{}", s),
+            Value::List(l) => write!(f, "{}", l.join(",")),
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Flag(b)
+    }
+}
+
+impl From<u32> for Value {
+    fn from(n: u32) -> Self {
+        Value::Number(u64::from(n))
+    }
+}
+
+impl From<u64> for Value {
+    fn from(n: u64) -> Self {
+        Value::Number(n)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Self {
+        Value::Text(s.to_owned())
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Self {
+        Value::Text(s)
+    }
+}
+
+impl From<Vec<String>> for Value {
+    fn from(l: Vec<String>) -> Self {
+        Value::List(l)
+    }
+}
+
+/// Attributes found by the stages, by name.
+///
+#[derive(Clone, Debug, Default, Deserialize, Eq, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(transparent)]
+pub struct Attributes(BTreeMap<String, Value>);
+
+impl Attributes {
+    /// No attribute.
+    ///
+    #[inline]
+    pub fn new() -> Self {
+        Attributes::default()
+    }
+
+    /// Set one attribute, replacing the previous value.
+    ///
+    /// Example:
+    /// ```
+    /// # use dmarc_rs::attrs::Attributes;
+    /// let mut a = Attributes::new();
+    /// a.set("asn", 64496u32);
+    /// a.set("country", "FR");
+    ///
+    /// assert_eq!("64496", a.text("asn"));
+    /// assert_eq!("", a.text("dnsbl"));
+    /// ```
+    ///
+    pub fn set<V: Into<Value>>(&mut self, name: &str, v: V) {
+        self.0.insert(name.to_owned(), v.into());
+    }
+
+    /// Value of one attribute, if set.
+    ///
+    #[inline]
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.0.get(name)
+    }
+
+    /// Value of one attribute as displayed, empty if not set.
+    ///
+    pub fn text(&self, name: &str) -> String {
+        self.0.get(name).map(|v| v.to_string()).unwrap_or_default()
+    }
+
+    /// Add all the attributes of `other`, replacing the ones already set.
+    ///
+    pub fn extend(&mut self, other: Attributes) {
+        self.0.extend(other.0);
+    }
+
+    /// Iterate over the attributes, by name.
+    ///
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &Value)> {
+        self.0.iter()
+    }
+
+    /// Number of attributes.
+    ///
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Is there no attribute at all?
+    ///
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(Value::Flag(true), "yes")]
+    #[case(Value::Number(64496), "64496")]
+    #[case(Value::from("FR"), "FR")]
+    #[case(Value::from(vec!["a".to_string(), "b".to_string()]), "a,b")]
+    fn test_value_display(#[case] v: Value, #[case] r: &str) {
+        assert_eq!(r, v.to_string());
+    }
+
+    #[test]
+    fn test_attributes() {
+        let mut a = Attributes::new();
+        assert!(a.is_empty());
+
+        a.set("asn", 64496u32);
+        a.set("country", "FR");
+        a.set("country", "BE");
+        assert_eq!(2, a.len());
+        assert_eq!("BE", a.text("country"));
+        assert_eq!(Some(&Value::Number(64496)), a.get("asn"));
+
+        let mut b = Attributes::new();
+        b.set("listed", false);
+        a.extend(b);
+        assert_eq!(
+            vec!["asn", "country", "listed"],
+            a.iter().map(|(k, _)| k.as_str()).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            r#"{"asn":64496,"country":"BE","listed":false}"#,
+            serde_json::to_string(&a).unwrap()
+        );
+    }
+}
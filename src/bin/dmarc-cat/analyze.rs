@@ -5,6 +5,7 @@
 // Std library
 //
 use std::fs::File;
+use std::io::BufReader;
 
 // Our crates
 //
@@ -12,8 +13,14 @@ use dmarc_rs::types::*;
 
 // External crates
 //
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use serde_xml_rs::from_reader;
 
-pub fn analyze_file(fh: &mut File) -> Result<String> {
-    Ok("".into())
+/// Parse the XML content of the given file into a list of reports.
+///
+pub fn analyze_file(fh: &mut File) -> Result<Feedback> {
+    match from_reader(BufReader::new(fh)) {
+        Ok(fb) => Ok(fb),
+        Err(e) => Err(anyhow!("{}", e.to_string())),
+    }
 }
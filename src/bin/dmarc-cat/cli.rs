@@ -10,7 +10,14 @@ use clap::{crate_authors, crate_description, crate_version, AppSettings, Parser}
 
 // Internal crates
 //
+use crate::group::{GroupBy, DEF_PREFIX4, DEF_PREFIX6};
+use crate::resolve::parse_chain;
+use crate::sort::{Order, SortKey};
+use crate::table::ColorMode;
 use crate::version::NAME;
+use dmarc_rs::cache::{DEF_NEGATIVE_TTL, DEF_TTL};
+use dmarc_rs::dns::{parse_nameserver, DEF_RETRIES, DEF_TIMEOUT};
+use dmarc_rs::ratelimit::parse_rate;
 
 /// All parsable options and arguments.
 #[derive(Parser, Debug)]
@@ -24,6 +31,66 @@ pub struct Opts {
     /// Do not resolve IP to names
     #[clap(short = 'N', long = "no-resolve")]
     pub noresolve: bool,
+    /// Resolvers to try in turn, comma-separated: system (the default), dns:ADDR, file:PATH,
+    /// hosts:PATH, cache or null
+    #[clap(long = "resolver", validator = |s: &str| parse_chain(s).map(|_| ()))]
+    pub resolver: Option<String>,
+    /// Ask this nameserver (IP with optional port) directly instead of the system resolver, can
+    /// be repeated
+    #[clap(
+        long = "nameserver",
+        conflicts_with = "resolver",
+        validator = |s: &str| parse_nameserver(s).map(|_| ())
+    )]
+    pub nameserver: Vec<String>,
+    /// How long to wait for each answer from the nameservers, in milliseconds
+    #[clap(long = "dns-timeout", default_value_t = DEF_TIMEOUT.as_millis() as u64)]
+    pub dns_timeout: u64,
+    /// How many times to try the nameservers again
+    #[clap(long = "dns-retries", default_value_t = DEF_RETRIES)]
+    pub dns_retries: u32,
+    /// Resolve with the async engine, -j being the number of lookups in flight
+    #[cfg(feature = "async")]
+    #[clap(long = "async")]
+    pub use_async: bool,
+    /// Abandon the lookups not done after this many seconds (async engine only)
+    #[cfg(feature = "async")]
+    #[clap(long = "deadline", requires = "use-async")]
+    pub deadline: Option<u64>,
+    /// Send at most this many DNS queries per second, across all jobs
+    #[clap(long = "dns-rate", validator = |s: &str| parse_rate(s).map(|_| ()))]
+    pub dns_rate: Option<f64>,
+    /// How many DNS queries can be sent at once with --dns-rate [default: the rate, rounded up]
+    #[clap(long = "dns-burst", requires = "dns-rate")]
+    pub dns_burst: Option<u32>,
+    /// Look up special-purpose source IPs (private, documentation, etc.) too
+    #[clap(long = "resolve-special")]
+    pub resolve_special: bool,
+    /// Check that names resolve back to the IP (forward-confirmed reverse DNS)
+    #[clap(long = "fcrdns")]
+    pub fcrdns: bool,
+    /// Check the source IPs against this DNS blocklist zone, can be repeated (asked with the
+    /// system resolver or --nameserver)
+    #[clap(long = "dnsbl", conflicts_with = "noresolve")]
+    pub dnsbl: Vec<String>,
+    /// Keep the names found in this file for the next runs
+    #[clap(long = "cache")]
+    pub cache: Option<PathBuf>,
+    /// How long names are kept in the cache, in seconds
+    #[clap(long = "cache-ttl", default_value_t = DEF_TTL)]
+    pub cache_ttl: u64,
+    /// How long missing names are kept in the cache, in seconds
+    #[clap(long = "cache-negative-ttl", default_value_t = DEF_NEGATIVE_TTL)]
+    pub cache_negative_ttl: u64,
+    /// Look everything up again, updating the cache
+    #[clap(long = "cache-bypass")]
+    pub cache_bypass: bool,
+    /// Empty the cache before starting
+    #[clap(long = "cache-clear")]
+    pub cache_clear: bool,
+    /// Display authentication statistics instead of the reports
+    #[clap(short = 'S', long = "stats")]
+    pub stats: bool,
     /// Verbose mode
     #[clap(short = 'v', long)]
     pub verbose: bool,
@@ -33,9 +100,60 @@ pub struct Opts {
     /// Use this many parallel jobs for resolving IP
     #[clap(short = 'j', long = "jobs", default_value_t = num_cpus::get_physical())]
     pub jobs: usize,
+    /// When to use colors
+    #[clap(long = "color", arg_enum, default_value = "auto")]
+    pub color: ColorMode,
+    /// Group source IPs by network prefix (same as --group-by prefix)
+    #[clap(short = 'g', long = "group")]
+    pub group: bool,
+    /// Group source IPs by network prefix, AS number, country or known sender
+    #[clap(long = "group-by", arg_enum)]
+    pub group_by: Option<GroupBy>,
+    /// ASN/country database (.mmdb or iptoasn TSV), can be repeated
+    #[clap(long = "asn-db")]
+    pub asn_db: Vec<PathBuf>,
+    /// Known-sender rules file (TOML)
+    #[clap(long = "sender-rules")]
+    pub sender_rules: Option<PathBuf>,
+    /// Only display lines from this known sender (or unknown), can be repeated
+    #[clap(long = "sender", requires = "sender-rules")]
+    pub sender: Vec<String>,
+    /// Leave out the lines from the IPs in this file (JSON, CSV or ip[,name] lines)
+    #[clap(long = "allowlist")]
+    pub allowlist: Option<PathBuf>,
+    /// Write the source IPs with their names to this file (.json, .csv or ip[,name] lines)
+    #[clap(long = "export-ips")]
+    pub export_ips: Option<PathBuf>,
+    /// Prefix length for grouping IPv4 addresses
+    #[clap(long = "prefix4", default_value_t = DEF_PREFIX4, validator = |s: &str| valid_prefix(s, 32))]
+    pub prefix4: u8,
+    /// Prefix length for grouping IPv6 addresses
+    #[clap(long = "prefix6", default_value_t = DEF_PREFIX6, validator = |s: &str| valid_prefix(s, 128))]
+    pub prefix6: u8,
+    /// Sort lines on this column
+    #[clap(long = "sort", arg_enum)]
+    pub sort: Option<SortKey>,
+    /// Sorting order
+    #[clap(long = "order", arg_enum, default_value = "asc")]
+    pub order: Order,
+    /// Only display the N largest senders, summing the others
+    #[clap(long = "top")]
+    pub top: Option<usize>,
+    /// Template to display reports with, a file or one of report, oneline, ticket, customer
+    #[clap(long = "template")]
+    pub template: Option<String>,
     /// Specify the type of input data
     #[clap(short = 't', long = "input-type")]
     pub itype: Option<String>,
     /// Filenames (possibly none or -)
     pub files: Vec<PathBuf>,
 }
+
+/// Check that a prefix length is valid for the address family.
+///
+fn valid_prefix(s: &str, max: u8) -> Result<(), String> {
+    match s.parse::<u8>() {
+        Ok(n) if n <= max => Ok(()),
+        _ => Err(format!("must be between 0 and {}", max)),
+    }
+}
@@ -12,12 +12,13 @@ use std::path::PathBuf;
 use crate::analyze::analyze_file;
 use dmarc_rs::entry::Entry;
 use dmarc_rs::filetype::*;
+use dmarc_rs::types::Feedback;
 
 // External crates
 //
 use anyhow::{anyhow, Result};
 
-pub fn handle_stream(fin: &dyn io::BufRead, ftype: Input) {
+pub fn handle_stream(_fin: &dyn io::BufRead, _ftype: Input) {
     unimplemented!()
 }
 
@@ -29,11 +30,11 @@ pub fn check_for_files(lfn: &[PathBuf]) -> Vec<Entry> {
     // Check for various files.
     //
     for f in lfn.iter() {
-        if f.exists() || *f == PathBuf::from("-") {
+        if f.exists() || f.as_os_str() == "-" {
             res.push(Entry::new(f));
-            println!("file: {:?}", f);
+            log::debug!("file: {:?}", f);
         } else {
-            println!("Unknown file {:?}", f);
+            log::warn!("Unknown file {:?}", f);
             continue;
         }
     }
@@ -41,9 +42,9 @@ pub fn check_for_files(lfn: &[PathBuf]) -> Vec<Entry> {
 }
 
 /// Scan the list of files and run `analyze_file()`  on each of them
-/// accumulating results.
+/// accumulating reports.
 ///
-pub fn scan_list(lfn: &Vec<Entry>) -> Result<String> {
+pub fn scan_list(lfn: &[Entry]) -> Result<Feedback> {
     let mut r = vec![];
     let mut failed = vec![];
 
@@ -56,10 +57,16 @@ pub fn scan_list(lfn: &Vec<Entry>) -> Result<String> {
                 continue;
             }
         };
-        r.push(analyze_file(&mut fh).unwrap());
+        match analyze_file(&mut fh) {
+            Ok(fb) => r.extend(fb),
+            Err(e) => {
+                log::warn!("Warning: can't parse {:?}: {}", fp, e.to_string());
+                failed.push(fp.p.to_str().unwrap());
+            }
+        }
     }
     if failed.is_empty() {
-        return Ok(r.join("/"));
+        return Ok(r);
     }
     Err(anyhow!("{:?}", failed))
 }
@@ -69,7 +76,7 @@ mod tests {
     use super::*;
     #[test]
     fn test_scan_list_empty() {
-        let r = scan_list(&vec![Entry {
+        let r = scan_list(&[Entry {
             p: PathBuf::from(""),
             ft: Input::Plain,
         }]);
@@ -78,13 +85,28 @@ mod tests {
 
     #[test]
     fn test_scan_list_nonexistent() {
-        let r = scan_list(&vec![Entry {
+        let r = scan_list(&[Entry {
             p: PathBuf::from("/nonexistent"),
             ft: Input::Plain,
         }]);
         assert!(r.is_err())
     }
 
+    #[test]
+    fn test_scan_list_ok() {
+        let r = scan_list(&[Entry::from(
+            "testdata/google.com!keltia.net!1538438400!1538524799.xml",
+        )]);
+        assert!(r.is_ok());
+        assert_eq!(1, r.unwrap().len())
+    }
+
+    #[test]
+    fn test_scan_list_not_xml() {
+        let r = scan_list(&[Entry::from("Cargo.toml")]);
+        assert!(r.is_err())
+    }
+
     #[test]
     fn test_check_for_unknown_files() {
         let l = vec![PathBuf::from("foo"), PathBuf::from("bar")];
@@ -0,0 +1,314 @@
+//! Aggregation of lines by network prefix, ASN, country or known sender.
+//!
+//! With `--group-by prefix` (or `-g`), all the lines whose source IP belong to the same network
+//! (by default a /24 for IPv4 and a /48 for IPv6) are merged into a single line.  With
+//! `--group-by asn` or `--group-by country`, lines are merged by AS number or country as found in
+//! the databases given with `--asn-db`, lines without information being grouped as `unknown`.
+//! `--group-by sender` does the same with the labels from the `--sender-rules` file.
+//!
+//! When merging:
+//!
+//! - the IP column displays the network, AS number, country or sender,
+//! - counts are summed,
+//! - all the names are kept, the first one being displayed followed by the number of other ones,
+//! - other columns display the list of different values found, separated by commas.
+//!
+//! Groups are displayed in order (numerical for networks and AS numbers).
+//!
+
+// Std library
+//
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+// Our crates
+//
+use crate::render::{Context, Line};
+use dmarc_rs::cidr::Cidr;
+use dmarc_rs::enrich::{Attributes, Value};
+use dmarc_rs::ip::Ip;
+
+// External crates
+//
+use clap::ArgEnum;
+
+/// Default prefix length for IPv4
+pub const DEF_PREFIX4: u8 = 24;
+/// Default prefix length for IPv6
+pub const DEF_PREFIX6: u8 = 48;
+
+/// What do we group lines by.
+///
+#[derive(ArgEnum, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GroupBy {
+    /// Network prefix
+    Prefix,
+    /// AS number
+    Asn,
+    /// Country
+    Country,
+    /// Known sender
+    Sender,
+}
+
+/// Actual key for one group, unknown values sort last.
+///
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+enum Key {
+    Net(Cidr),
+    Asn(u32),
+    Country(String),
+    Sender(String),
+    Unknown,
+}
+
+impl Display for Key {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Key::Net(n) => write!(f, "{}", n),
+            Key::Asn(n) => write!(f, "AS{}", n),
+            Key::Country(c) | Key::Sender(c) => write!(f, "{}", c),
+            Key::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/// Compute the key of one line.
+///
+fn key(l: &Line, by: GroupBy, v4: u8, v6: u8) -> Key {
+    match by {
+        GroupBy::Prefix => Key::Net(Ip::from(l.addr).network(v4, v6)),
+        GroupBy::Asn => match l.asn.trim_start_matches("AS").parse::<u32>() {
+            Ok(n) => Key::Asn(n),
+            Err(_) => Key::Unknown,
+        },
+        GroupBy::Country => match l.country.is_empty() {
+            true => Key::Unknown,
+            false => Key::Country(l.country.to_owned()),
+        },
+        GroupBy::Sender => match l.sender.is_empty() {
+            true => Key::Unknown,
+            false => Key::Sender(l.sender.to_owned()),
+        },
+    }
+}
+
+/// Merge the lines of a report, `v4` and `v6` being the prefix lengths used for networks.
+///
+pub fn group(ctx: &mut Context, by: GroupBy, v4: u8, v6: u8) {
+    let mut groups: BTreeMap<Key, Vec<Line>> = BTreeMap::new();
+
+    for l in ctx.lines.drain(..) {
+        groups.entry(key(&l, by, v4, v6)).or_default().push(l);
+    }
+    ctx.lines = groups.into_iter().map(|(k, l)| merge(k, l)).collect();
+}
+
+/// Merge all the lines of one group.
+///
+fn merge<'a>(key: Key, lines: Vec<Line<'a>>) -> Line<'a> {
+    let names: Vec<String> = distinct(
+        lines
+            .iter()
+            .flat_map(|l| l.names.iter().map(|n| n.as_str())),
+    )
+    .into_iter()
+    .map(|n| n.to_owned())
+    .collect();
+    let join = |f: for<'b> fn(&'b Line<'a>) -> &'b str| distinct(lines.iter().map(f)).join(",");
+    let addr = match key {
+        Key::Net(n) => n.addr(),
+        _ => lines[0].addr,
+    };
+
+    Line {
+        ip: key.to_string(),
+        addr,
+        name: names.first().cloned().unwrap_or_default(),
+        names,
+        ptr: join(|l| &l.ptr),
+        special: join(|l| &l.special),
+        fcrdns: join(|l| &l.fcrdns),
+        asn: join(|l| &l.asn),
+        asname: join(|l| &l.asname),
+        country: join(|l| &l.country),
+        sender: join(|l| &l.sender),
+        count: lines.iter().map(|l| l.count).sum(),
+        from: join(|l| &l.from),
+        rfrom: join(|l| &l.rfrom),
+        dkim: join(|l| &l.dkim),
+        spf: join(|l| &l.spf),
+        disposition: join(|l| &l.disposition),
+        attrs: merge_attrs(&lines),
+        record: lines[0].record,
+    }
+}
+
+/// Merge the attributes of all the lines of one group, keeping values shared by all of them and
+/// joining the distinct ones like the other columns.
+///
+fn merge_attrs(lines: &[Line]) -> Attributes {
+    let mut keys: Vec<&str> = vec![];
+    for l in lines.iter() {
+        keys.extend(l.attrs.iter().map(|(k, _)| k.as_str()));
+    }
+    keys.sort_unstable();
+    keys.dedup();
+
+    let mut attrs = Attributes::new();
+    for k in keys {
+        let values: Vec<&Value> = lines.iter().filter_map(|l| l.attrs.get(k)).collect();
+        match values.len() == lines.len() && values.iter().all(|v| *v == values[0]) {
+            true => attrs.set(k, values[0].clone()),
+            false => {
+                let text: Vec<String> = lines.iter().map(|l| l.attrs.text(k)).collect();
+                attrs.set(k, distinct(text.iter().map(|t| t.as_str())).join(","));
+            }
+        }
+    }
+    attrs
+}
+
+/// Non-empty distinct values, in order of appearance.
+///
+fn distinct<'a>(iter: impl Iterator<Item = &'a str>) -> Vec<&'a str> {
+    let mut v: Vec<&str> = vec![];
+    for s in iter {
+        if !s.is_empty() && !v.contains(&s) {
+            v.push(s);
+        }
+    }
+    v
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{load, EXAMPLE};
+    use dmarc_rs::asn::AsnDb;
+    use dmarc_rs::enrich::{AsnStage, Pipeline};
+    use dmarc_rs::types::Feedback;
+
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_distinct() {
+        let v = distinct(["a", "", "b", "a", "c", "b"].into_iter());
+        assert_eq!(vec!["a", "b", "c"], v);
+    }
+
+    #[test]
+    fn test_group() {
+        let fb: Feedback = load(EXAMPLE);
+        let mut names = HashMap::new();
+        for ip in [
+            Ip::try_from(("192.0.2.10", "mx1.example.net")).unwrap(),
+            Ip::try_from(("192.0.2.20", "mx2.example.net")).unwrap(),
+        ] {
+            names.insert(ip.ip, ip);
+        }
+        let mut ctx = Context::new(&fb[0], &names);
+
+        group(&mut ctx, GroupBy::Prefix, DEF_PREFIX4, DEF_PREFIX6);
+        let nets: Vec<&str> = ctx.lines.iter().map(|l| l.ip.as_str()).collect();
+        assert_eq!(
+            vec!["192.0.2.0/24", "198.51.100.0/24", "2001:db8:1::/48"],
+            nets
+        );
+
+        let l = &ctx.lines[0];
+        assert_eq!(15, l.count);
+        assert_eq!("mx1.example.net (+1)", l.hostname());
+        assert_eq!("pass", l.dkim);
+        assert_eq!("pass,fail", l.spf);
+        assert_eq!("none", l.disposition);
+
+        let l = &ctx.lines[2];
+        assert_eq!(6, l.count);
+        assert_eq!("none,reject", l.disposition);
+
+        // Total is unchanged
+        assert_eq!(24, ctx.count);
+    }
+
+    #[test]
+    fn test_group_attrs() {
+        let fb: Feedback = load(EXAMPLE);
+        let mut names = HashMap::new();
+        for (ip, zones) in [("192.0.2.10", "bl.example"), ("192.0.2.20", "pbl.example")] {
+            let mut ip = Ip::new(ip);
+            ip.attrs.set("dnsbl", vec![zones.to_string()]);
+            ip.attrs.set("country", "FR");
+            names.insert(ip.ip, ip);
+        }
+        let mut ctx = Context::new(&fb[0], &names);
+
+        group(&mut ctx, GroupBy::Prefix, DEF_PREFIX4, DEF_PREFIX6);
+        let attrs = &ctx.lines[0].attrs;
+        assert_eq!(Some(&Value::from("FR")), attrs.get("country"));
+        assert_eq!("bl.example,pbl.example", attrs.text("dnsbl"));
+        assert!(ctx.lines[1].attrs.is_empty());
+    }
+
+    #[test]
+    fn test_group_asn() {
+        let fb: Feedback = load(EXAMPLE);
+        let db = AsnDb::open("testdata/ip2asn-test.tsv").unwrap();
+        let p = Pipeline::new().with_stage(AsnStage::new(Arc::new(db)));
+        let names: HashMap<_, _> = fb[0]
+            .record
+            .iter()
+            .map(|r| (r.row.source_ip, p.enrich(&Ip::from(r.row.source_ip))))
+            .collect();
+        let mut ctx = Context::new(&fb[0], &names);
+
+        group(&mut ctx, GroupBy::Asn, DEF_PREFIX4, DEF_PREFIX6);
+        let asns: Vec<&str> = ctx.lines.iter().map(|l| l.ip.as_str()).collect();
+        assert_eq!(vec!["AS64496", "AS64497", "AS64498"], asns);
+        assert_eq!(15, ctx.lines[0].count);
+        assert_eq!("EXAMPLE-AS-1", ctx.lines[0].asname);
+    }
+
+    #[test]
+    fn test_group_country() {
+        let fb: Feedback = load(EXAMPLE);
+        let db = AsnDb::open("testdata/ip2asn-test.tsv").unwrap();
+        let p = Pipeline::new().with_stage(AsnStage::new(Arc::new(db)));
+        let names: HashMap<_, _> = fb[0]
+            .record
+            .iter()
+            .map(|r| (r.row.source_ip, p.enrich(&Ip::from(r.row.source_ip))))
+            .collect();
+        let mut ctx = Context::new(&fb[0], &names);
+
+        group(&mut ctx, GroupBy::Country, DEF_PREFIX4, DEF_PREFIX6);
+        let cc: Vec<&str> = ctx.lines.iter().map(|l| l.ip.as_str()).collect();
+        assert_eq!(vec!["DE", "FR", "unknown"], cc);
+        assert_eq!(3, ctx.lines[2].count);
+    }
+
+    #[test]
+    fn test_group_host() {
+        let fb: Feedback = load(EXAMPLE);
+        let mut ctx = Context::new(&fb[0], &HashMap::new());
+
+        group(&mut ctx, GroupBy::Prefix, 32, 128);
+        assert_eq!(5, ctx.lines.len());
+        assert_eq!("192.0.2.10/32", ctx.lines[0].ip);
+    }
+
+    #[test]
+    fn test_group_sender() {
+        let fb: Feedback = load(EXAMPLE);
+        let mut ctx = Context::new(&fb[0], &HashMap::new());
+        ctx.lines[0].sender = "relays".into();
+        ctx.lines[2].sender = "relays".into();
+        ctx.lines[3].sender = "other".into();
+
+        group(&mut ctx, GroupBy::Sender, DEF_PREFIX4, DEF_PREFIX6);
+        let l: Vec<(&str, u64)> = ctx.lines.iter().map(|l| (l.ip.as_str(), l.count)).collect();
+        assert_eq!(vec![("other", 4), ("relays", 15), ("unknown", 5)], l);
+        assert_eq!("relays", ctx.lines[1].sender);
+    }
+}
@@ -18,13 +18,73 @@
 //!     <FILES>...    Filenames (possibly none or -)
 //!
 //! OPTIONS:
-//!     -D, --debug                 debug mode
-//!     -h, --help                  Print help information
-//!     -j, --jobs <JOBS>           Use this many parallel jobs for resolving IP [default: 6]
-//!     -N, --no-resolve            Do not resolve IP to names
-//!     -t, --input-type <ITYPE>    Specify the type of input data
-//!     -v, --verbose               Verbose mode
-//!     -V, --version               Display version and exit
+//!         --allowlist <ALLOWLIST>                      Leave out the lines from the IPs in this file
+//!                                                      (JSON, CSV or ip[,name] lines)
+//!         --asn-db <ASN_DB>                            ASN/country database (.mmdb or iptoasn TSV),
+//!                                                      can be repeated
+//!         --cache <CACHE>                              Keep the names found in this file for the next
+//!                                                      runs
+//!         --cache-bypass                               Look everything up again, updating the cache
+//!         --cache-clear                                Empty the cache before starting
+//!         --cache-negative-ttl <CACHE_NEGATIVE_TTL>    How long missing names are kept in the cache,
+//!                                                      in seconds [default: 3600]
+//!         --cache-ttl <CACHE_TTL>                      How long names are kept in the cache, in
+//!                                                      seconds [default: 86400]
+//!         --color <COLOR>                              When to use colors [default: auto] [possible
+//!                                                      values: auto, always, never]
+//!     -D, --debug                                      debug mode
+//!         --dns-burst <DNS_BURST>                      How many DNS queries can be sent at once with
+//!                                                      --dns-rate [default: the rate, rounded up]
+//!         --dns-rate <DNS_RATE>                        Send at most this many DNS queries per second,
+//!                                                      across all jobs
+//!         --dns-retries <DNS_RETRIES>                  How many times to try the nameservers again
+//!                                                      [default: 2]
+//!         --dns-timeout <DNS_TIMEOUT>                  How long to wait for each answer from the
+//!                                                      nameservers, in milliseconds [default: 2000]
+//!         --dnsbl <DNSBL>                              Check the source IPs against this DNS blocklist
+//!                                                      zone, can be repeated (asked with the system
+//!                                                      resolver or --nameserver)
+//!         --export-ips <EXPORT_IPS>                    Write the source IPs with their names to this
+//!                                                      file (.json, .csv or ip[,name] lines)
+//!         --fcrdns                                     Check that names resolve back to the IP
+//!                                                      (forward-confirmed reverse DNS)
+//!     -g, --group                                      Group source IPs by network prefix (same as
+//!                                                      --group-by prefix)
+//!         --group-by <GROUP_BY>                        Group source IPs by network prefix, AS number,
+//!                                                      country or known sender [possible values:
+//!                                                      prefix, asn, country, sender]
+//!     -h, --help                                       Print help information
+//!     -j, --jobs <JOBS>                                Use this many parallel jobs for resolving IP
+//!                                                      [default: 6]
+//!     -N, --no-resolve                                 Do not resolve IP to names
+//!         --nameserver <NAMESERVER>                    Ask this nameserver (IP with optional port)
+//!                                                      directly instead of the system resolver, can be
+//!                                                      repeated
+//!         --order <ORDER>                              Sorting order [default: asc] [possible values:
+//!                                                      asc, desc]
+//!         --prefix4 <PREFIX4>                          Prefix length for grouping IPv4 addresses
+//!                                                      [default: 24]
+//!         --prefix6 <PREFIX6>                          Prefix length for grouping IPv6 addresses
+//!                                                      [default: 48]
+//!         --resolve-special                            Look up special-purpose source IPs (private,
+//!                                                      documentation, etc.) too
+//!         --resolver <RESOLVER>                        Resolvers to try in turn, comma-separated:
+//!                                                      system (the default), dns:ADDR, file:PATH,
+//!                                                      hosts:PATH, cache or null
+//!     -S, --stats                                      Display authentication statistics instead of
+//!                                                      the reports
+//!         --sender <SENDER>                            Only display lines from this known sender (or
+//!                                                      unknown), can be repeated
+//!         --sender-rules <SENDER_RULES>                Known-sender rules file (TOML)
+//!         --sort <SORT>                                Sort lines on this column [possible values:
+//!                                                      count, ip, name, sender, from, dkim, spf]
+//!     -t, --input-type <ITYPE>                         Specify the type of input data
+//!         --template <TEMPLATE>                        Template to display reports with, a file or one
+//!                                                      of report, oneline, ticket, customer
+//!         --top <TOP>                                  Only display the N largest senders, summing the
+//!                                                      others
+//!     -v, --verbose                                    Verbose mode
+//!     -V, --version                                    Display version and exit
 //! ```
 //!
 //! ## Columns
@@ -35,6 +95,7 @@
 //! The report has several columns:
 //!
 //! - `IP` is matching IP address
+//! - `Name` is the resolved name for the IP address
 //! - `Count` is the number of times this IP was present
 //! - `From` is the `From:` header value
 //! - `RFrom` is the envelope `From` value
@@ -54,24 +115,58 @@
 pub mod analyze;
 pub mod cli;
 pub mod file;
+pub mod group;
+pub mod render;
 pub mod resolve;
+pub mod sort;
+pub mod table;
+pub mod template;
 pub mod version;
 
+#[cfg(test)]
+#[path = "../../testutil.rs"]
+mod testutil;
+
 // Std library
 //
+use std::collections::{HashMap, HashSet};
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
 
 // Our crates
 //
 use cli::Opts;
+use dmarc_rs::asn::AsnDb;
+#[cfg(feature = "async")]
+use dmarc_rs::asyncres::AsyncSolver;
+use dmarc_rs::bulk::Cancel;
+use dmarc_rs::cache::Cache;
+use dmarc_rs::dns::{parse_nameserver, DnsResolver};
+use dmarc_rs::enrich::{
+    AsnStage, DnsblStage, GeoStage, Pipeline, PtrStage, SenderStage, SpecialStage,
+};
 use dmarc_rs::filetype::*;
+#[cfg(feature = "async")]
+use dmarc_rs::ip::Ip;
+use dmarc_rs::iplist::{CountedIpList, IpList};
+use dmarc_rs::metrics::Metrics;
+use dmarc_rs::ratelimit::RateLimit;
+use dmarc_rs::resolver::{fcrdns, res_init, ResType, Solver};
+use dmarc_rs::sender::Senders;
+use dmarc_rs::stats::Summary;
 use file::{check_for_files, scan_list};
+use group::GroupBy;
+use render::Context;
+use resolve::{net, parse_chain, res_from_chain, ChainOpts, Stage};
+use table::Table;
+use template::Template;
 use version::version;
 
 // External crates
 //
 use anyhow::{anyhow, Result};
 use clap::Parser;
-use dmarc_rs::resolver::{res_init, ResType};
 
 /// Main entry point
 ///
@@ -84,31 +179,202 @@ fn main() -> Result<()> {
         return Ok(());
     }
 
+    // Setup logging, warnings are always displayed
+    //
+    let verbosity = match (opts.debug, opts.verbose) {
+        (true, _) => 3,
+        (false, true) => 2,
+        _ => 1,
+    };
+    stderrlog::new().verbosity(verbosity).init()?;
+
     let mut flist = opts.files.to_owned();
-    let mut ftype = Input::Plain;
 
-    // Handle --no-resolv flag
+    // Resolver metrics are only collected to be displayed.
+    //
+    let metrics = (opts.verbose || opts.debug).then(Metrics::new);
+
+    // Open the cache, if any, first as it can be part of a resolver chain.
+    //
+    let cache = match &opts.cache {
+        Some(f) => {
+            let cache = Cache::open(f)?
+                .with_ttl(opts.cache_ttl, opts.cache_negative_ttl)
+                .with_bypass(opts.cache_bypass);
+            let cache = match &metrics {
+                Some(m) => cache.with_metrics(m),
+                None => cache,
+            };
+            if opts.cache_clear {
+                cache.clear()?;
+                if flist.is_empty() {
+                    return Ok(());
+                }
+            }
+            Some(cache)
+        }
+        None => None,
+    };
+
+    // One limit for all the DNS queries, if asked for.
+    //
+    let rate = match opts.dns_rate {
+        Some(qps) => {
+            let rl = RateLimit::new(qps)?;
+            Some(match opts.dns_burst {
+                Some(burst) => rl.with_burst(burst),
+                None => rl,
+            })
+        }
+        None => None,
+    };
+
+    // Handle --no-resolv flag, --resolver & --nameserver
+    //
+    let mut stages = match &opts.resolver {
+        Some(spec) => parse_chain(spec)?,
+        None => vec![],
+    };
+
+    // Put the cache, if any, in front of a chain which does not say where, keeping a trailing null
+    // out of it like an explicit cache stage does. A chain of only null has nothing to cache.
+    //
+    let only_null = !stages.is_empty() && stages.iter().all(|s| *s == Stage::Null);
+    if cache.is_some() && only_null {
+        log::warn!("--cache is not used with the null resolver");
+    }
+    if cache.is_some() && !only_null && !stages.is_empty() && !stages.contains(&Stage::Cache) {
+        stages.insert(0, Stage::Cache);
+    }
+    let dns = match opts.nameserver.is_empty() {
+        true => None,
+        false => {
+            let servers = opts
+                .nameserver
+                .iter()
+                .map(|s| parse_nameserver(s))
+                .collect::<Result<Vec<_>>>()?;
+            Some(
+                DnsResolver::new(&servers)
+                    .with_timeout(Duration::from_millis(opts.dns_timeout))
+                    .with_retries(opts.dns_retries),
+            )
+        }
+    };
+    let copts = ChainOpts {
+        cache: cache.as_ref(),
+        dns_timeout: Duration::from_millis(opts.dns_timeout),
+        dns_retries: opts.dns_retries,
+        rate: rate.as_ref(),
+        metrics: metrics.as_ref(),
+    };
+    let res = match (opts.noresolve, stages.is_empty(), &dns) {
+        (true, _, _) => res_init(ResType::Null),
+        (false, false, _) => res_from_chain(&stages, &copts)?,
+        (false, true, Some(dns)) => net(Solver::new(dns.clone()), &copts),
+        (false, true, None) => net(res_init(ResType::Real), &copts),
+    };
+
+    // Put the cache, if any, in front of the real resolver when there is no chain.
+    //
+    let res = match (&cache, opts.noresolve || !stages.is_empty()) {
+        (Some(cache), false) => cache.wrap(res),
+        _ => res,
+    };
+    let res = match opts.fcrdns && !opts.noresolve {
+        true => fcrdns(res),
+        false => res,
+    };
+
+    // The blocklists are asked directly, their answers are not names to look for in the chain or
+    // to keep in the cache.
+    //
+    let dnsbl = match &dns {
+        Some(dns) => net(Solver::new(dns.clone()), &copts),
+        None => net(res_init(ResType::Real), &copts),
+    };
+
+    // With --async, our DNS client is used natively unless the cache, FCrDNS or the rate limit are
+    // in the way, any other resolver runs on the blocking pool of the engine.
+    //
+    #[cfg(feature = "async")]
+    let engine = {
+        let engine = match (dns, &cache, opts.fcrdns, &rate) {
+            (Some(dns), None, false, None) if !opts.noresolve => AsyncSolver::dns(dns),
+            _ => AsyncSolver::new(res.clone()),
+        }
+        .with_limit(opts.jobs);
+        let engine = match &metrics {
+            Some(m) => engine.with_metrics(m),
+            None => engine,
+        };
+        match opts.deadline {
+            Some(secs) => engine.with_deadline(Duration::from_secs(secs)),
+            None => engine,
+        }
+    };
+
+    // Load the template early to catch errors before doing any work.
     //
-    let mut res = res_init(ResType::Real);
-    if opts.noresolve {
-        res = res_init(ResType::Null);
+    let tmpl = match &opts.template {
+        Some(t) => Some(Template::load(t)?),
+        None => None,
+    };
+
+    // Load the ASN databases, if any.
+    //
+    let mut asndb = AsnDb::new();
+    for f in opts.asn_db.iter() {
+        asndb.add(f)?;
     }
 
-    // If no arguments, we assume stdin and we enforece the presence of `-t`.
+    // Load the known senders, if any.
+    //
+    let senders = match &opts.sender_rules {
+        Some(f) => Senders::load(f)?,
+        None => Senders::new(),
+    };
+    let (asndb, senders) = (Arc::new(asndb), Arc::new(senders));
+
+    // Load the allowlist, if any.
+    //
+    let allowed: HashSet<IpAddr> = match &opts.allowlist {
+        Some(f) => IpList::load(f)?.iter().map(|ip| ip.ip).collect(),
+        None => HashSet::new(),
+    };
+    let table = Table::new(opts.color)
+        .with_asn(!asndb.is_empty())
+        .with_sender(opts.sender_rules.is_some())
+        .with_fcrdns(opts.fcrdns)
+        .with_attrs(match opts.dnsbl.is_empty() {
+            true => vec![],
+            false => vec!["dnsbl".into()],
+        });
+
+    // `-g` is a shortcut for `--group-by prefix`
+    //
+    let group_by = match (opts.group_by, opts.group) {
+        (Some(by), _) => Some(by),
+        (None, true) => Some(GroupBy::Prefix),
+        (None, false) => None,
+    };
+
+    // If no arguments, we assume stdin and we enforce the presence of `-t`.
     //
     if flist.is_empty() {
         // Assume stdin
-        ftype = match opts.itype {
-            Some(it) => match valid_input(&it) {
-                Ok(it) => it,
-                _ => return Err(anyhow!("Invalid type for -t")),
-            },
+        match opts.itype {
+            Some(it) => {
+                if valid_input(&it).is_err() {
+                    return Err(anyhow!("Invalid type for -t"));
+                }
+            }
             None => return Err(anyhow!("-t MUST be provided")),
         };
         flist.push("-".into())
     }
 
-    println!("{:?}", flist);
+    log::debug!("{:?}", flist);
 
     // Check each file in the list and returns only the valid ones
     //
@@ -119,12 +385,156 @@ fn main() -> Result<()> {
 
     // Do the thing.
     //
-    let output = match scan_list(&flist) {
+    let reports = match scan_list(&flist) {
         Ok(res) => res,
         Err(e) => {
-            format!("Error: {:?}", e)
+            println!("Error: {:?}", e);
+            return Ok(());
+        }
+    };
+
+    if opts.stats {
+        let sum: Summary = reports.iter().collect();
+        print!("{}", sum);
+        return Ok(());
+    }
+
+    // Resolve every IP once for all the reports.
+    //
+    // The first Ctrl-C stops the lookups, what is already resolved is displayed (and cached), the
+    // second one exits.
+    //
+    let cancel = Cancel::new();
+    let c = cancel.clone();
+    ctrlc::set_handler(move || {
+        if c.is_cancelled() {
+            std::process::exit(130);
         }
+        log::warn!("interrupted, stopping DNS lookups");
+        c.cancel();
+    })?;
+    let counted: CountedIpList = reports.iter().collect();
+    log::info!(
+        "resolving {} unique IPs out of {} records",
+        counted.len(),
+        counted.iter().map(|(_, c)| c.records).sum::<usize>()
+    );
+    let ipl = counted.ips();
+    if !opts.resolve_special {
+        let n = ipl.iter().filter(|ip| ip.special().is_some()).count();
+        if n > 0 {
+            log::info!("skipping {} special-purpose IPs", n);
+        }
+    }
+
+    // With --async, the names are looked up by the engine first, the PTR stage only recording
+    // them.
+    //
+    #[cfg(feature = "async")]
+    let ipl = match opts.use_async {
+        true => async_solve(
+            &engine.with_cancel(cancel.clone()),
+            ipl,
+            !opts.resolve_special,
+        )?,
+        false => ipl,
     };
-    println!("{:?}", output);
+
+    // Then run the IPs through all the enrichment stages.
+    //
+    let mut pipeline = Pipeline::new()
+        .with_jobs(opts.jobs)
+        .with_cancel(cancel)
+        .with_progress(|done, total, ip| {
+            log::debug!("{}/{} {} {}", done, total, ip.ip, ip.name());
+            // About every 10%
+            if done == total || done % (total / 10).max(1) == 0 {
+                log::info!("resolved {}/{} unique IPs", done, total);
+            }
+        })
+        .with_stage(PtrStage::new(res.clone()).with_skip_special(!opts.resolve_special))
+        .with_stage(SpecialStage);
+    if !asndb.is_empty() {
+        pipeline = pipeline
+            .with_stage(AsnStage::new(asndb.clone()))
+            .with_stage(GeoStage::new(asndb.clone()));
+    }
+    if !opts.dnsbl.is_empty() {
+        pipeline = pipeline.with_stage(
+            DnsblStage::new(dnsbl, &opts.dnsbl).with_skip_special(!opts.resolve_special),
+        );
+    }
+    if !senders.is_empty() {
+        pipeline = pipeline.with_record_stage(SenderStage::new(senders.clone()));
+    }
+    log::debug!("{:?}", pipeline);
+    let names: HashMap<_, _> = pipeline
+        .run(&ipl)?
+        .into_iter()
+        .map(|ip| (ip.ip, ip))
+        .collect();
+
+    if let Some(m) = &metrics {
+        for line in m.snapshot().to_string().lines() {
+            log::info!("{}", line);
+        }
+    }
+    if let Some(rl) = &rate {
+        log::info!(
+            "--dns-rate: {} queries throttled, {:.1}s spent waiting",
+            rl.throttled(),
+            rl.delay().as_secs_f64()
+        );
+    }
+    if let Some(f) = &opts.export_ips {
+        let mut ipl: IpList = names.values().cloned().collect();
+        ipl.sort();
+        ipl.save(f)?;
+        log::info!("{} source IPs written to {}", ipl.len(), f.display());
+    }
+
+    for rep in reports.iter() {
+        let mut ctx = Context::new(rep, &names);
+
+        ctx.enrich(&pipeline, &names);
+        if !opts.sender.is_empty() {
+            sort::select(&mut ctx, &opts.sender);
+        }
+        if !allowed.is_empty() {
+            sort::skip_allowed(&mut ctx, &allowed);
+        }
+        if let Some(by) = group_by {
+            group::group(&mut ctx, by, opts.prefix4, opts.prefix6);
+        }
+        if let Some(n) = opts.top {
+            sort::top(&mut ctx, n);
+        }
+        if let Some(key) = opts.sort {
+            sort::sort(&mut ctx, key, opts.order);
+        }
+
+        match &tmpl {
+            Some(tmpl) => print!("{}", tmpl.render(&ctx)?),
+            None => print!("{}", table.render(&ctx)),
+        }
+    }
+
+    if let Some(cache) = cache {
+        cache.save()?;
+    }
     Ok(())
 }
+
+/// Resolve the IPs with the async engine, special-purpose ones being left alone if asked to.
+///
+#[cfg(feature = "async")]
+fn async_solve(engine: &AsyncSolver, ipl: IpList, skip_special: bool) -> Result<IpList> {
+    let (special, todo): (Vec<Ip>, Vec<Ip>) = ipl
+        .into_iter()
+        .partition(|ip| skip_special && ip.special().is_some());
+    let mut ipl = engine.block_on(&todo.into_iter().collect())?;
+    for ip in special {
+        ipl.push(ip);
+    }
+    Ok(ipl)
+}
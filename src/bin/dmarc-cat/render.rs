@@ -0,0 +1,380 @@
+//! Data model used to display reports.
+//!
+//! Each `Record` from a report is flattened into a `Line` carrying the resolved name of the
+//! source IP, its ASN & country if known, its known sender label and the most useful fields as
+//! plain strings, with all the attributes found by the enrichment stages (see `dmarc_rs::enrich`).
+//! A `Context` groups the lines of one report with its metadata and published policy; this is
+//! what the templates get to see.
+//!
+//! When only the top senders are displayed, the rest is summed up in `others`.
+//!
+
+// Std library
+//
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+// Our crates
+//
+use dmarc_rs::enrich::{Attributes, Pipeline};
+use dmarc_rs::ip::Ip;
+use dmarc_rs::resolver::SolveError;
+use dmarc_rs::types::*;
+
+// External crates
+//
+use chrono::{TimeZone, Utc};
+use serde::Serialize;
+
+/// One displayable line, built from a `Record`.
+///
+#[derive(Clone, Debug, Serialize)]
+pub struct Line<'a> {
+    /// Source IP (or network when grouped)
+    pub ip: String,
+    /// Source IP (or network address) used for sorting
+    pub addr: IpAddr,
+    /// Primary name for the source IP (empty if not resolved)
+    pub name: String,
+    /// All the names for the source IP, primary one first
+    pub names: Vec<String>,
+    /// Outcome of the name lookup: `ok`, `nxdomain`, `tempfail` or empty if not resolved
+    pub ptr: String,
+    /// Special-purpose block of the source IP like `private` or `documentation`, empty for regular
+    /// addresses.  These are not looked up and make the report suspicious.
+    pub special: String,
+    /// Forward-confirmed reverse DNS: `pass`, `fail` or empty if not checked
+    pub fcrdns: String,
+    /// AS number like `AS64496` (empty if unknown)
+    pub asn: String,
+    /// AS name (empty if unknown)
+    pub asname: String,
+    /// Country code (empty if unknown)
+    pub country: String,
+    /// Known sender (empty if unknown)
+    pub sender: String,
+    /// Number of messages
+    pub count: u64,
+    /// `From:` header domain
+    pub from: String,
+    /// Envelope `From` domain
+    pub rfrom: String,
+    /// First DKIM result (or `none`)
+    pub dkim: String,
+    /// First SPF result (or `none`)
+    pub spf: String,
+    /// Disposition applied to the messages
+    pub disposition: String,
+    /// Attributes of the source IP and the record, like `dnsbl`
+    pub attrs: Attributes,
+    /// The original record (the first one when grouped)
+    pub record: &'a Record,
+}
+
+impl<'a> Line<'a> {
+    /// Flatten a record, looking up the name & ASN of its source IP in `names`.
+    ///
+    pub fn new(r: &'a Record, names: &HashMap<IpAddr, Ip>) -> Self {
+        let dkim = r
+            .auth_results
+            .iter()
+            .filter_map(|a| a.dkim.as_ref())
+            .flatten()
+            .map(|d| to_str(&d.result))
+            .next()
+            .unwrap_or_else(|| "none".into());
+        let spf = r
+            .auth_results
+            .iter()
+            .flat_map(|a| a.spf.iter())
+            .map(|s| to_str(&s.result))
+            .next()
+            .unwrap_or_else(|| "none".into());
+
+        let ip = names.get(&r.row.source_ip);
+        let asn = ip.and_then(|ip| ip.asn.as_ref());
+
+        Line {
+            ip: r.row.source_ip.to_string(),
+            addr: r.row.source_ip,
+            name: ip.map(|ip| ip.name().to_owned()).unwrap_or_default(),
+            names: ip.map(|ip| ip.names.to_owned()).unwrap_or_default(),
+            ptr: ptr_status(ip).into(),
+            special: ip.map(|ip| ip.attrs.text("special")).unwrap_or_default(),
+            fcrdns: match ip.and_then(|ip| ip.verified) {
+                Some(true) => "pass".into(),
+                Some(false) => "fail".into(),
+                None => "".into(),
+            },
+            asn: match asn {
+                Some(a) if a.asn != 0 => format!("AS{}", a.asn),
+                _ => "".into(),
+            },
+            asname: asn.map(|a| a.name.to_owned()).unwrap_or_default(),
+            country: asn.map(|a| a.country.to_owned()).unwrap_or_default(),
+            sender: "".into(),
+            count: u64::from(r.row.count),
+            from: r.identifiers.header_from.to_owned(),
+            rfrom: r.identifiers.envelope_from.to_owned(),
+            dkim,
+            spf,
+            disposition: to_str(&r.row.policy_evaluated.disposition),
+            attrs: ip.map(|ip| ip.attrs.to_owned()).unwrap_or_default(),
+            record: r,
+        }
+    }
+
+    /// Text displayed for the name, see `hostname()`, or the special-purpose block of an IP which
+    /// was not looked up.
+    ///
+    pub fn hostname(&self) -> String {
+        match (self.ptr.is_empty(), self.special.is_empty()) {
+            (true, false) if self.name.is_empty() => format!("({})", self.special),
+            _ => hostname(&self.name, self.names.len(), &self.ptr),
+        }
+    }
+}
+
+/// Summary of the lines left out by `--top`.
+///
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+pub struct Others {
+    /// Number of lines
+    pub lines: usize,
+    /// Sum of their message count
+    pub count: u64,
+}
+
+/// Everything about one report.
+///
+#[derive(Debug, Serialize)]
+pub struct Context<'a> {
+    /// Reporter, contact & date range
+    pub metadata: &'a ReportMetadata,
+    /// Published DMARC policy
+    pub policy: &'a PolicyPublished,
+    /// Total number of messages
+    pub count: u64,
+    /// All records with their names
+    pub lines: Vec<Line<'a>>,
+    /// Lines left out, if any
+    pub others: Option<Others>,
+    /// The original report
+    pub report: &'a Report,
+}
+
+impl<'a> Context<'a> {
+    /// Build the context of one report.
+    ///
+    pub fn new(rep: &'a Report, names: &HashMap<IpAddr, Ip>) -> Self {
+        let lines: Vec<Line> = rep.record.iter().map(|r| Line::new(r, names)).collect();
+
+        Context {
+            metadata: &rep.report_metadata,
+            policy: &rep.policy_published,
+            count: lines.iter().map(|l| l.count).sum(),
+            lines,
+            others: None,
+            report: rep,
+        }
+    }
+
+    /// Run the record stages of the pipeline on every line, `names` giving the enriched source
+    /// IPs.  The known sender, if any, goes into the `sender` column.
+    ///
+    pub fn enrich(&mut self, p: &Pipeline, names: &HashMap<IpAddr, Ip>) {
+        for l in self.lines.iter_mut() {
+            let ip = match names.get(&l.addr) {
+                Some(ip) => ip.clone(),
+                None => Ip::from(l.addr),
+            };
+            l.attrs = p.enrich_record(&ip, l.record);
+            l.sender = l.attrs.text("sender");
+        }
+    }
+}
+
+/// Text displayed for a name: the primary `name` followed by the number of other ones (out of
+/// `count` names) or why there is none, `ptr` being the outcome of the lookup (or a list of them
+/// for grouped lines).
+///
+pub fn hostname(name: &str, count: usize, ptr: &str) -> String {
+    if name.is_empty() {
+        let l: Vec<&str> = ptr.split(',').collect();
+        return if l.contains(&"tempfail") {
+            "(DNS failure)".into()
+        } else if l.contains(&"nxdomain") {
+            "(no PTR)".into()
+        } else {
+            "".into()
+        };
+    }
+    match count {
+        0 | 1 => name.to_owned(),
+        n => format!("{} (+{})", name, n - 1),
+    }
+}
+
+/// Outcome of the name lookup for one IP.
+///
+fn ptr_status(ip: Option<&Ip>) -> &'static str {
+    match ip.map(|ip| (ip.names.is_empty(), &ip.error)) {
+        Some((_, Some(SolveError::NotFound))) => "nxdomain",
+        Some((_, Some(SolveError::TempFail(_) | SolveError::Timeout))) => "tempfail",
+        Some((false, None)) => "ok",
+        _ => "",
+    }
+}
+
+/// Display a UNIX timestamp as a UTC date.
+///
+pub fn date(ts: i64) -> String {
+    match Utc.timestamp_opt(ts, 0).single() {
+        Some(d) => d.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+        None => ts.to_string(),
+    }
+}
+
+/// Get the serialized name of one of the result enums (`pass`, `fail`, etc.).
+///
+fn to_str<T: Serialize>(v: &T) -> String {
+    match serde_json::to_value(v) {
+        Ok(serde_json::Value::String(s)) => s,
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{load, EXAMPLE, GOOGLE};
+    use dmarc_rs::asn::AsnInfo;
+    use dmarc_rs::enrich::SenderStage;
+    use dmarc_rs::sender::Senders;
+
+    use std::sync::Arc;
+
+    use rstest::rstest;
+
+    #[test]
+    fn test_context_new() {
+        let fb: Feedback = load(GOOGLE);
+        let mut names = HashMap::new();
+        let mut ip = Ip::try_from(("195.154.227.159", "foo.example.net")).unwrap();
+        ip.asn = Some(AsnInfo {
+            asn: 12876,
+            name: "ONLINE S.A.S.".into(),
+            country: "FR".into(),
+        });
+        names.insert(ip.ip, ip);
+
+        let ctx = Context::new(&fb[0], &names);
+        assert_eq!(2, ctx.count);
+        assert_eq!(2, ctx.lines.len());
+        assert_eq!("foo.example.net", ctx.lines[0].name);
+        assert_eq!(vec!["foo.example.net"], ctx.lines[0].names);
+        assert_eq!("AS12876", ctx.lines[0].asn);
+        assert_eq!("FR", ctx.lines[0].country);
+        assert_eq!("", ctx.lines[1].name);
+        assert_eq!("", ctx.lines[1].asn);
+        assert_eq!("none", ctx.lines[0].dkim);
+        assert_eq!("pass", ctx.lines[0].spf);
+        assert_eq!("none", ctx.lines[0].disposition);
+    }
+
+    #[test]
+    fn test_context_enrich() {
+        let fb: Feedback = load(GOOGLE);
+        let senders: Senders = "[[sender]]\nname = \"Online\"\nptr = [\"example.net\"]\n"
+            .parse()
+            .unwrap();
+        let p = Pipeline::new().with_record_stage(SenderStage::new(Arc::new(senders)));
+        let mut names = HashMap::new();
+        let mut ip = Ip::try_from(("195.154.227.159", "foo.example.net")).unwrap();
+        ip.attrs.set("dnsbl", vec!["bl.example".to_string()]);
+        names.insert(ip.ip, ip);
+
+        let mut ctx = Context::new(&fb[0], &names);
+        assert_eq!("bl.example", ctx.lines[0].attrs.text("dnsbl"));
+        ctx.enrich(&p, &names);
+        assert_eq!("Online", ctx.lines[0].sender);
+        assert_eq!("Online", ctx.lines[0].attrs.text("sender"));
+        assert_eq!("bl.example", ctx.lines[0].attrs.text("dnsbl"));
+        assert_eq!("", ctx.lines[1].sender);
+        assert!(ctx.lines[1].attrs.is_empty());
+    }
+
+    #[test]
+    fn test_context_ptr() {
+        let fb: Feedback = load(GOOGLE);
+        let mut names = HashMap::new();
+        let mut ip = Ip::new("195.154.227.159");
+        ip.error = Some(SolveError::TempFail("SERVFAIL".into()));
+        names.insert(ip.ip, ip);
+        let mut ip = Ip::new("217.70.183.200");
+        ip.error = Some(SolveError::NotFound);
+        names.insert(ip.ip, ip);
+
+        let ctx = Context::new(&fb[0], &names);
+        assert_eq!("tempfail", ctx.lines[0].ptr);
+        assert_eq!("nxdomain", ctx.lines[1].ptr);
+    }
+
+    #[test]
+    fn test_context_fcrdns() {
+        let fb: Feedback = load(GOOGLE);
+        let mut names = HashMap::new();
+        let mut ip = Ip::try_from(("195.154.227.159", "foo.example.net")).unwrap();
+        ip.verified = Some(false);
+        names.insert(ip.ip, ip);
+
+        let ctx = Context::new(&fb[0], &names);
+        assert_eq!("fail", ctx.lines[0].fcrdns);
+        assert_eq!("", ctx.lines[1].fcrdns);
+    }
+
+    #[rstest]
+    #[case("foo.example.net", 1, "ok", "foo.example.net")]
+    #[case("mx1.example.net", 3, "ok", "mx1.example.net (+2)")]
+    #[case("", 0, "nxdomain", "(no PTR)")]
+    #[case("", 0, "tempfail", "(DNS failure)")]
+    #[case("", 0, "nxdomain,tempfail", "(DNS failure)")]
+    #[case("", 0, "", "")]
+    fn test_hostname(#[case] name: &str, #[case] n: usize, #[case] ptr: &str, #[case] r: &str) {
+        assert_eq!(r, hostname(name, n, ptr));
+    }
+
+    #[test]
+    fn test_context_names() {
+        let fb: Feedback = load(GOOGLE);
+        let mut names = HashMap::new();
+        let ip = Ip::try_from((
+            "195.154.227.159",
+            ["mx1.example.net", "www.example.net"].as_slice(),
+        ))
+        .unwrap();
+        names.insert(ip.ip, ip);
+
+        let ctx = Context::new(&fb[0], &names);
+        assert_eq!("mx1.example.net", ctx.lines[0].name);
+        assert_eq!(2, ctx.lines[0].names.len());
+        assert_eq!("mx1.example.net (+1)", ctx.lines[0].hostname());
+    }
+
+    #[test]
+    fn test_context_special() {
+        let fb: Feedback = load(EXAMPLE);
+        let mut names = HashMap::new();
+        let mut ip = Ip::new("192.0.2.10");
+        ip.attrs.set("special", "documentation");
+        names.insert(ip.ip, ip);
+        let mut ip = Ip::new("198.51.100.7");
+        ip.error = Some(SolveError::NotFound);
+        names.insert(ip.ip, ip);
+
+        let ctx = Context::new(&fb[0], &names);
+        assert_eq!("documentation", ctx.lines[0].special);
+        assert_eq!("(documentation)", ctx.lines[0].hostname());
+        assert_eq!("(no PTR)", ctx.lines[1].hostname());
+    }
+}
@@ -1,320 +1,281 @@
-//! Module handling the DNS resolving operations
+//! Module handling the choice of the resolver on the command line.
 //!
-//! We use `IpList` as container and `resolve()` is the main function to get all names.  As we have
-//! the choice between two solvers, you can select the simple single-threaded one by specifying
-//! that you want only 1 job.
+//! `--resolver` takes a chain of stages separated by commas, each IP going through them in order
+//! until one of them finds a name:
 //!
-//! When the crate is compiled, the number of CPU & CPU threads is read and that gives us the upper
-//! bound for the parallelism.  The `dmarc-cat` binary will default to number physical cores but the hard
-//! limit is the number of total core threads (which is higher if the CPU supports Hyperthreading).
+//! - `system`: the system resolver (the default), giving one name per IP,
+//! - `dns:ADDR`: our DNS client asking this nameserver (IP with optional port), giving every name,
+//! - `file:PATH`: a table of preset answers, hosts or TOML format depending on the extension,
+//! - `hosts:PATH`: the same in the `/etc/hosts` format whatever the extension,
+//! - `cache`: the `--cache` file, answers of the stages after it being saved there,
+//! - `null`: the IP itself, which always answers so it can only be the last stage.
 //!
-//! **NOTE** I have no idea how CPU with different cores types (Apple M1 family or others) are handled,
-//! not sure it would make any difference in this case.
+//! Example: `cache,hosts:/etc/dmarc-hosts,dns:127.0.0.1,null`.
 //!
-//! We define a list of IP tuples from the `dmarc_rs::ip` crate and implement two methods
-//! for resolving the IP into names.  One is `simple_solve()` which is a straightforward sequential
-//! solver, the other one is `parallel_solve()` which is using threads from a pool to implement a
-//! worker-based fan-out/fan-in scheme with channels to move data around.
-//!
-//! You can select the resolving module to be used from the three defined in `dmarc_rs::resolver`.
-//!
-//! Examples:
-//! ```rust
-//! # use dmarc_rs::iplist::IpList;
-//! # use dmarc_rs::resolver::*;
-//! let l = IpList::from(["1.1.1.1", "2606:4700:4700::1111", "192.0.2.1"]);
-//! let res = res_init(ResType::Real);
-//!
-//! // Use the simple solver
-//! let ptr = resolve(l, 1, &res);
-//! dbg!(&ptr);
-//! ```
-//! and with the parallel solver but with the default resolver:
-//! ```rust
-//! # use dmarc_rs::iplist::IpList;
-//! # use dmarc_rs::resolver::*;
-//! // Get the number of physical cores, I prefer to use this one instead of the potentially
-//! // larger total cores because Hyperthreading has some overhead.
-//! let njobs = num_cpus::get_physical();
-//!
-//! let l = IpList::from(["1.1.1.1", "2606:4700:4700::1111", "192.0.2.1"]);
-//! let res = res_init(ResType::default());
-//!
-//! // Use the parallel solver
-//! let ptr = parallel_solve(l, njobs, res);
-//! dbg!(&ptr);
-//! ```
+//! Bulk resolution itself lives in the library, see `dmarc_rs::bulk`.
 //!
 
-// Our crates
+// Std library
 //
-use dmarc_rs::ip::Ip;
-use dmarc_rs::iplist::IpList;
-use dmarc_rs::resolver::*;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
 
-// Std library
+// Our crates
 //
-use std::sync::mpsc::{channel, Receiver};
-use std::thread;
+use dmarc_rs::cache::Cache;
+use dmarc_rs::dns::{parse_nameserver, DnsResolver};
+use dmarc_rs::metrics::Metrics;
+use dmarc_rs::ratelimit::RateLimit;
+use dmarc_rs::resolver::*;
 
 // External crates
 //
 use anyhow::{anyhow, Result};
-use threadpool::ThreadPool;
 
-/// `resolve()` is the main function call to get all names from the list of `Ip` we get from the
-/// XML file.
-///
-/// Example:
-/// ```no_run
-/// # use dmarc_rs::iplist::IpList;
-/// let l = IpList::from(["1.1.1.1", "2606:4700:4700::1111", "192.0.2.1"]);
-///
-/// // Select a resolver
-/// let res = res_init(ResType::Real);
-///
-/// // Using the simple single threaded solver.
-/// let ptr = resolve(l, 1, res).unwrap();
-///
-/// // Use the parallel solver with as many threads as the CPU has.
-/// let ptr2 = resolve(l, num_cpus::get(), res).unwrap();
-/// ```
+/// One stage of a resolver chain.
 ///
-pub fn resolve(ipl: &IpList, njobs: usize, res: &Solver) -> Result<IpList> {
-    let max_threads = num_cpus::get();
-
-    // Return an error on empty list
-    // XXX maybe return the empty list?
-    if ipl.is_empty() {
-        return Err(anyhow!(("Empty list")));
-    }
-
-    // Put a hard limit on how many parallel thread to the max number of cores (incl.
-    // avoid overhead even on modern versions of Hyperthreading).
-    //
-    if njobs > max_threads {
-        return Err(anyhow!("Too many threads"));
-    }
-
-    // Bypass the more complex code is IpList has only one element
-    if ipl.len() == 1 {
-        let ip = res.solve(&ipl[0]);
-        return Ok(IpList::from(ip));
-    }
-
-    // Call the appropriate one
-    //
-    match njobs {
-        1 => Ok(simple_solve(&ipl, res)),
-        _ => Ok(parallel_solve(ipl, njobs, res)),
-    }
+#[derive(Clone, Debug, PartialEq)]
+pub enum Stage {
+    /// The resolver cache
+    Cache,
+    /// The system resolver
+    System,
+    /// Names are IPs
+    Null,
+    /// Preset answers, format from the extension
+    File(PathBuf),
+    /// Preset answers in the hosts format
+    Hosts(PathBuf),
+    /// Our DNS client with one nameserver
+    Dns(SocketAddr),
 }
 
-/// Simple and straightforward sequential solver
-///
-/// Example:
-/// ```
-/// # use dmarc_rs::iplist::IpList;
-/// # use dmarc_rs::ip::Ip;
-/// let l = IpList::from(["1.1.1.1", "2606:4700:4700::1111", "192.0.2.1"]);
-///
-/// // select a given resolver
-/// let res = res_init(ResType::Real);
-///
-/// let ptr = simple_solve(l, res);
-/// ```
-///
-fn simple_solve(ipl: &IpList, res: &Solver) -> IpList {
-    let mut r: IpList = ipl.clone().into_iter().map(|ip| res.solve(&ip)).collect();
-    r.sort();
-    r
+impl FromStr for Stage {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().split_once(':') {
+            Some(("file", path)) if !path.is_empty() => Ok(Stage::File(path.into())),
+            Some(("hosts", path)) if !path.is_empty() => Ok(Stage::Hosts(path.into())),
+            Some(("dns", ns)) => Ok(Stage::Dns(parse_nameserver(ns)?)),
+            None if s.trim() == "cache" => Ok(Stage::Cache),
+            None if s.trim() == "system" => Ok(Stage::System),
+            None if s.trim() == "null" => Ok(Stage::Null),
+            _ => Err(anyhow!("unknown resolver {}", s)),
+        }
+    }
 }
 
-/// Convert a list of IP into names with multiple threads
+/// Parse a resolver chain like `cache,hosts:/etc/dmarc-hosts,dns:127.0.0.1,null`.
 ///
-/// It uses a function to fill in the input channel then fan_out/fan_in to fill the result
-/// list.
+/// `null` can only be last and `cache` given once, with a resolver after it.
 ///
-/// Example:
-/// ```no_run
-/// # use dmarc_rs::ip::Ip;
-/// # use dmarc_rs::iplist::IpList;
-/// let l = IpList::from(["1.1.1.1", "2606:4700:4700::1111", "192.0.2.1"]);
-///
-/// // Select a resolver
-/// let res = res_init(ResType::Real);
-///
-/// let ptr = parallel_solve(l, 4, res);
-/// ```
-///
-fn parallel_solve(ipl: &IpList, njobs: usize, res: &Solver) -> IpList {
-    let mut full = IpList::new();
-    let s = ipl.len();
-
-    let pool = ThreadPool::new(njobs);
-    let rx_gen = queue(ipl).unwrap();
-    let rx_out = fan_out(rx_gen, pool, s, res).unwrap();
-    for ip in fan_in(rx_out).unwrap() {
-        full.push(ip);
+pub fn parse_chain(spec: &str) -> Result<Vec<Stage>> {
+    let stages = spec
+        .split(',')
+        .map(|s| s.parse::<Stage>())
+        .collect::<Result<Vec<_>>>()?;
+
+    if let Some(i) = stages.iter().position(|s| *s == Stage::Null) {
+        if i != stages.len() - 1 {
+            return Err(anyhow!("null can only be the last resolver"));
+        }
+    }
+    match stages.iter().position(|s| *s == Stage::Cache) {
+        Some(i) if stages[i + 1..].contains(&Stage::Cache) => {
+            Err(anyhow!("cache can only be given once"))
+        }
+        Some(i) if !stages[i + 1..].iter().any(|s| *s != Stage::Null) => {
+            Err(anyhow!("cache needs a resolver after it"))
+        }
+        _ => Ok(stages),
     }
-    full.sort();
-    dbg!(&full);
-    full
 }
 
-/// Take all values from the list and send them into a queue
+/// Settings shared by the stages of a chain.
 ///
-fn queue(ipl: &IpList) -> Result<Receiver<Ip>> {
-    let (tx, rx) = channel();
-
-    // construct a copy of the list
-    let all = ipl.clone();
-
-    // use that copy to send over
-    thread::spawn(move || {
-        for ip in all.into_iter() {
-            tx.send(ip.clone()).expect("can not queue")
-        }
-    });
-    Ok(rx)
+#[derive(Clone, Debug)]
+pub struct ChainOpts<'a> {
+    /// Cache for the `cache` stage
+    pub cache: Option<&'a Cache>,
+    /// Timeout of `dns:` stages
+    pub dns_timeout: Duration,
+    /// Retries of `dns:` stages
+    pub dns_retries: u32,
+    /// Limit shared by the `system` and `dns:` stages
+    pub rate: Option<&'a RateLimit>,
+    /// Metrics of the `system` and `dns:` stages
+    pub metrics: Option<&'a Metrics>,
 }
 
-/// Start enough workers to resolve IP into PTR.
+/// Build the resolver for a parsed chain, a single stage being used as-is.
 ///
-fn fan_out(
-    rx_gen: Receiver<Ip>,
-    pool: ThreadPool,
-    njobs: usize,
-    res: &Solver,
-) -> Result<Receiver<Ip>, Box<dyn std::error::Error>> {
-    let (tx, rx) = channel();
-
-    for _ in 0..njobs {
-        let tx = tx.clone();
-        let n = rx_gen.recv().unwrap();
+/// The cache wraps the stages after it (except `null`) so that their answers are recorded, the rate
+/// limit and the metrics only apply to the stages asking the DNS.
+///
+pub fn res_from_chain(stages: &[Stage], o: &ChainOpts) -> Result<Solver> {
+    let mut list = vec![];
+
+    for (i, stage) in stages.iter().enumerate() {
+        let res = match stage {
+            Stage::Cache => {
+                let cache = o
+                    .cache
+                    .ok_or_else(|| anyhow!("the cache resolver needs --cache"))?;
+                let (rest, null) = match stages.last() {
+                    Some(Stage::Null) => (&stages[i + 1..stages.len() - 1], true),
+                    _ => (&stages[i + 1..], false),
+                };
+                list.push(cache.wrap(res_from_chain(rest, o)?));
+                if null {
+                    list.push(res_init(ResType::Null));
+                }
+                break;
+            }
+            Stage::System => net(res_init(ResType::Real), o),
+            Stage::Null => res_init(ResType::Null),
+            Stage::File(path) => Solver::new(FakeResolver::load(path)?),
+            Stage::Hosts(path) => {
+                let s =
+                    fs::read_to_string(path).map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+                Solver::new(
+                    FakeResolver::from_hosts(&s)
+                        .map_err(|e| anyhow!("{}: {}", path.display(), e))?,
+                )
+            }
+            Stage::Dns(ns) => net(
+                Solver::new(
+                    DnsResolver::new(&[*ns])
+                        .with_timeout(o.dns_timeout)
+                        .with_retries(o.dns_retries),
+                ),
+                o,
+            ),
+        };
+        list.push(res);
+    }
 
-        let res = res.clone();
-        pool.execute(move || {
-            let name: Ip = res.solve(&n);
-            tx.send(Ip {
-                ip: name.ip,
-                name: name.name,
-            })
-            .expect("waiting channel");
-        });
+    match list.len() {
+        1 => Ok(list.remove(0)),
+        _ => Ok(chain(list)),
     }
-    Ok(rx)
 }
 
-/// Gather all results into an output channel
+/// Record the metrics of a resolver asking the DNS and put it behind the rate limit, if any.
 ///
-fn fan_in(rx_out: Receiver<Ip>) -> Result<Receiver<Ip>, Box<dyn std::error::Error>> {
-    let (tx, rx) = channel();
-    thread::spawn(move || {
-        for ip in rx_out.iter() {
-            tx.send(ip).expect("can not send");
-        }
-    });
-    Ok(rx)
+pub fn net(res: Solver, o: &ChainOpts) -> Solver {
+    let res = match o.metrics {
+        Some(m) => m.wrap(res),
+        None => res,
+    };
+    match o.rate {
+        Some(rl) => rl.wrap(res),
+        None => res,
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use dmarc_rs::ip::Ip;
 
-    #[test]
-    fn test_invalid_jobs() {
-        let l = IpList::from(["1.1.1.1", "2606:4700:4700::1111", "192.0.2.1"]);
-        let res = res_init(ResType::Fake);
-
-        assert!(resolve(&l, 1000, &res).is_err())
-    }
-
-    #[test]
-    fn test_resolve() {
-        let l = IpList::from(["1.1.1.1", "2606:4700:4700::1111", "192.0.2.1"]);
-        let res = res_init(ResType::Fake);
-
-        // Using the simple single threaded solver.
-        let ptr = resolve(&l, 1, &res).unwrap();
-
-        // Use the parallel solver with 4 threads.
-        let ptr2 = resolve(&l, num_cpus::get(), &res).unwrap();
-
-        assert_eq!(ptr, ptr2);
-    }
-
-    #[test]
-    fn test_parallel_solve_empty() {
-        let a = IpList::new();
-        let res = res_init(ResType::Fake);
-
-        let r = parallel_solve(&a, num_cpus::get_physical(), &res);
+    use rstest::rstest;
 
-        assert!(r.is_empty())
-    }
-
-    #[test]
-    fn test_simple_solve_empty() {
-        let a = IpList::new();
-        let res = res_init(ResType::Fake);
-
-        let r = simple_solve(&a, &res);
-
-        assert!(r.is_empty())
+    fn opts(cache: Option<&Cache>) -> ChainOpts<'_> {
+        ChainOpts {
+            cache,
+            dns_timeout: Duration::from_millis(100),
+            dns_retries: 0,
+            rate: None,
+            metrics: None,
+        }
     }
 
     #[test]
-    #[should_panic]
-    fn test_solve_empty() {
-        let a = IpList::new();
-        let res = res_init(ResType::Fake);
-
-        let r = resolve(&a, 1, &res);
-
-        assert!(r.is_err());
-        assert!(r.unwrap().is_empty());
+    fn test_parse_chain() {
+        assert_eq!(
+            vec![
+                Stage::Cache,
+                Stage::Hosts("/etc/dmarc-hosts".into()),
+                Stage::Dns("127.0.0.1:53".parse().unwrap()),
+                Stage::Dns("[::1]:5353".parse().unwrap()),
+                Stage::System,
+                Stage::Null
+            ],
+            parse_chain("cache,hosts:/etc/dmarc-hosts,dns:127.0.0.1, dns:[::1]:5353,system,null")
+                .unwrap()
+        );
+        assert_eq!(
+            vec![Stage::File("testdata/hosts".into())],
+            parse_chain("file:testdata/hosts").unwrap()
+        );
     }
 
-    #[test]
-    fn test_dumb_simple_solve_ok() {
-        let l = IpList::from(["1.1.1.1", "2606:4700:4700::1111", "192.0.2.1"]);
-        let res = res_init(ResType::Fake);
-
-        let ptr = simple_solve(&l, &res);
-
-        assert_eq!(l.len(), ptr.len());
-        for x in ptr {
-            assert_eq!("some.host.invalid", x.name);
-        }
+    #[rstest]
+    #[case("")]
+    #[case("foo")]
+    #[case("file:")]
+    #[case("dns:foo")]
+    #[case("system,,null")]
+    #[case("null,system")]
+    #[case("cache,system,cache")]
+    #[case("cache")]
+    #[case("cache,null")]
+    fn test_parse_chain_nok(#[case] spec: &str) {
+        assert!(parse_chain(spec).is_err());
     }
 
     #[test]
-    fn test_dumb_parallel_solve_ok() {
-        let l = IpList::from(["1.1.1.1", "2606:4700:4700::1111", "192.0.2.1"]);
-        let res = res_init(ResType::Fake);
-
-        let ptr = parallel_solve(&l, num_cpus::get_physical(), &res);
-
-        assert_eq!(l.len(), ptr.len());
-        // Order is not always preserved so check inside
-        //
-        for x in ptr {
-            assert_eq!("some.host.invalid", x.name);
-        }
+    fn test_res_from_chain() {
+        let stages = parse_chain("file:testdata/hosts").unwrap();
+        let res = res_from_chain(&stages, &opts(None)).unwrap();
+        assert_eq!(
+            "mx1.example.net",
+            res.solve(&Ip::new("192.0.2.10")).unwrap().name()
+        );
+        assert!(res.solve(&Ip::new("198.51.100.7")).is_err());
+
+        let stages = parse_chain("hosts:testdata/hosts,null").unwrap();
+        let res = res_from_chain(&stages, &opts(None)).unwrap();
+        assert_eq!(
+            "mx1.example.net",
+            res.solve(&Ip::new("192.0.2.10")).unwrap().name()
+        );
+        assert_eq!(
+            "198.51.100.7",
+            res.solve(&Ip::new("198.51.100.7")).unwrap().name()
+        );
+
+        let stages = parse_chain("file:/nonexistent").unwrap();
+        assert!(res_from_chain(&stages, &opts(None)).is_err());
+
+        let stages = parse_chain("hosts:/nonexistent").unwrap();
+        let e = res_from_chain(&stages, &opts(None)).err().unwrap();
+        assert!(e.to_string().starts_with("/nonexistent: "), "{}", e);
     }
 
     #[test]
-    fn test_fakesolver_resolve() {
-        let l = IpList::from(["1.1.1.1", "2606:4700:4700::1111", "192.0.2.1"]);
-        let res = res_init(ResType::Fake);
-
-        let ptr = resolve(&l, 1, &res);
-        assert!(ptr.is_ok());
-
-        let ptr = ptr.unwrap();
-        for x in ptr {
-            assert_eq!("some.host.invalid", x.name);
-        }
+    fn test_res_from_chain_cache() {
+        let stages = parse_chain("cache,file:testdata/hosts,null").unwrap();
+        assert!(res_from_chain(&stages, &opts(None)).is_err());
+
+        let dir = std::env::temp_dir().join(format!("dmarc-chain-{}", std::process::id()));
+        let cache = Cache::open(dir.join("cache.json")).unwrap();
+        let res = res_from_chain(&stages, &opts(Some(&cache))).unwrap();
+
+        assert_eq!(
+            "mx1.example.net",
+            res.solve(&Ip::new("192.0.2.10")).unwrap().name()
+        );
+        assert_eq!(
+            "198.51.100.7",
+            res.solve(&Ip::new("198.51.100.7")).unwrap().name()
+        );
+        // The name from the file and the missing one, not the IP given by null
+        assert_eq!(2, cache.len());
+        let _ = fs::remove_dir_all(dir);
     }
 }
@@ -0,0 +1,243 @@
+//! Sorting and selection of the lines of a report.
+//!
+//! By default, lines are displayed in the order of the XML file.  With `--sort`, they can be
+//! sorted on one of the columns, IP addresses being sorted numerically (IPv4 before IPv6) like
+//! `IpList::sort()` does and not lexically.
+//!
+//! With `--top N`, only the N largest senders (by message count) are kept, the remaining ones
+//! being summed up into an "others" line.
+//!
+//! With `--sender NAME`, only the lines labelled with one of the given known senders are kept,
+//! `unknown` selecting the lines no rule matched.
+//!
+//! With `--allowlist FILE`, the lines from the IPs in the file are left out.
+//!
+
+// Std library
+//
+use std::cmp::Ordering;
+use std::collections::HashSet;
+use std::net::IpAddr;
+
+// Our crates
+//
+use crate::render::{Context, Line, Others};
+
+// External crates
+//
+use clap::ArgEnum;
+
+/// Column to sort on.
+///
+#[derive(ArgEnum, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SortKey {
+    /// Number of messages
+    Count,
+    /// Source IP, numerically
+    Ip,
+    /// Resolved name
+    Name,
+    /// Known sender
+    Sender,
+    /// `From:` header domain
+    From,
+    /// DKIM result
+    Dkim,
+    /// SPF result
+    Spf,
+}
+
+/// Sorting order.
+///
+#[derive(ArgEnum, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Order {
+    /// Ascending
+    Asc,
+    /// Descending
+    Desc,
+}
+
+/// Compare two lines on the given key.
+///
+fn compare(a: &Line, b: &Line, key: SortKey) -> Ordering {
+    match key {
+        SortKey::Count => a.count.cmp(&b.count),
+        SortKey::Ip => a.addr.cmp(&b.addr),
+        SortKey::Name => a.name.cmp(&b.name),
+        SortKey::Sender => a.sender.cmp(&b.sender),
+        SortKey::From => a.from.cmp(&b.from),
+        SortKey::Dkim => a.dkim.cmp(&b.dkim),
+        SortKey::Spf => a.spf.cmp(&b.spf),
+    }
+}
+
+/// Sort the lines of a report, keeping the XML order for equal keys.
+///
+pub fn sort(ctx: &mut Context, key: SortKey, order: Order) {
+    ctx.lines.sort_by(|a, b| match order {
+        Order::Asc => compare(a, b, key),
+        Order::Desc => compare(b, a, key),
+    });
+}
+
+/// Keep only the lines whose known sender is in `senders` (case-insensitive).
+///
+pub fn select(ctx: &mut Context, senders: &[String]) {
+    ctx.lines.retain(|l| {
+        let s = match l.sender.is_empty() {
+            true => "unknown",
+            false => l.sender.as_str(),
+        };
+        senders.iter().any(|n| n.eq_ignore_ascii_case(s))
+    });
+}
+
+/// Leave out the lines whose source IP is allowed.
+///
+pub fn skip_allowed(ctx: &mut Context, allowed: &HashSet<IpAddr>) {
+    ctx.lines.retain(|l| !allowed.contains(&l.addr));
+}
+
+/// Keep only the `n` largest senders, summing the others.
+///
+/// The remaining lines are sorted by decreasing count.
+///
+pub fn top(ctx: &mut Context, n: usize) {
+    sort(ctx, SortKey::Count, Order::Desc);
+    if ctx.lines.len() <= n {
+        return;
+    }
+
+    let rest = ctx.lines.split_off(n);
+    ctx.others = Some(Others {
+        lines: rest.len(),
+        count: rest.iter().map(|l| l.count).sum(),
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dmarc_rs::types::Feedback;
+
+    use std::collections::HashMap;
+
+    use serde_xml_rs::from_str;
+
+    const XML: &str = r#"<feedback>
+  <version>1.0</version>
+  <report_metadata>
+    <org_name>example.com</org_name>
+    <email>dmarc@example.com</email>
+    <report_id>1</report_id>
+    <date_range><begin>0</begin><end>1</end></date_range>
+  </report_metadata>
+  <policy_published>
+    <domain>example.net</domain><p>none</p><sp>none</sp><pct>100</pct>
+  </policy_published>
+  <record>
+    <row><source_ip>10.0.0.1</source_ip><count>5</count>
+      <policy_evaluated><disposition>none</disposition><dkim>pass</dkim><spf>pass</spf></policy_evaluated>
+    </row>
+    <identifiers><header_from>b.example.net</header_from><envelope_from>example.net</envelope_from></identifiers>
+    <auth_results><spf><domain>example.net</domain><scope>mfrom</scope><result>pass</result></spf></auth_results>
+  </record>
+  <record>
+    <row><source_ip>9.0.0.1</source_ip><count>2</count>
+      <policy_evaluated><disposition>none</disposition><dkim>pass</dkim><spf>pass</spf></policy_evaluated>
+    </row>
+    <identifiers><header_from>a.example.net</header_from><envelope_from>example.net</envelope_from></identifiers>
+    <auth_results><spf><domain>example.net</domain><scope>mfrom</scope><result>fail</result></spf></auth_results>
+  </record>
+  <record>
+    <row><source_ip>100.0.0.1</source_ip><count>7</count>
+      <policy_evaluated><disposition>none</disposition><dkim>pass</dkim><spf>pass</spf></policy_evaluated>
+    </row>
+    <identifiers><header_from>c.example.net</header_from><envelope_from>example.net</envelope_from></identifiers>
+    <auth_results><spf><domain>example.net</domain><scope>mfrom</scope><result>pass</result></spf></auth_results>
+  </record>
+</feedback>"#;
+
+    fn ips(ctx: &Context) -> Vec<String> {
+        ctx.lines.iter().map(|l| l.ip.clone()).collect()
+    }
+
+    #[test]
+    fn test_sort_ip_numeric() {
+        let fb: Feedback = from_str(XML).unwrap();
+        let mut ctx = Context::new(&fb[0], &HashMap::new());
+
+        sort(&mut ctx, SortKey::Ip, Order::Asc);
+        assert_eq!(vec!["9.0.0.1", "10.0.0.1", "100.0.0.1"], ips(&ctx));
+
+        sort(&mut ctx, SortKey::Ip, Order::Desc);
+        assert_eq!(vec!["100.0.0.1", "10.0.0.1", "9.0.0.1"], ips(&ctx));
+    }
+
+    #[test]
+    fn test_sort_from() {
+        let fb: Feedback = from_str(XML).unwrap();
+        let mut ctx = Context::new(&fb[0], &HashMap::new());
+
+        sort(&mut ctx, SortKey::From, Order::Asc);
+        assert_eq!(vec!["9.0.0.1", "10.0.0.1", "100.0.0.1"], ips(&ctx));
+    }
+
+    #[test]
+    fn test_sort_spf_stable() {
+        let fb: Feedback = from_str(XML).unwrap();
+        let mut ctx = Context::new(&fb[0], &HashMap::new());
+
+        sort(&mut ctx, SortKey::Spf, Order::Asc);
+        assert_eq!(vec!["9.0.0.1", "10.0.0.1", "100.0.0.1"], ips(&ctx));
+    }
+
+    #[test]
+    fn test_top() {
+        let fb: Feedback = from_str(XML).unwrap();
+        let mut ctx = Context::new(&fb[0], &HashMap::new());
+
+        top(&mut ctx, 1);
+        assert_eq!(vec!["100.0.0.1"], ips(&ctx));
+        assert_eq!(Some(Others { lines: 2, count: 7 }), ctx.others);
+        // Total is unchanged
+        assert_eq!(14, ctx.count);
+    }
+
+    #[test]
+    fn test_top_large() {
+        let fb: Feedback = from_str(XML).unwrap();
+        let mut ctx = Context::new(&fb[0], &HashMap::new());
+
+        top(&mut ctx, 10);
+        assert_eq!(3, ctx.lines.len());
+        assert!(ctx.others.is_none());
+    }
+
+    #[test]
+    fn test_select() {
+        let fb: Feedback = from_str(XML).unwrap();
+        let mut ctx = Context::new(&fb[0], &HashMap::new());
+        ctx.lines[0].sender = "Relays".into();
+        ctx.lines[1].sender = "Other".into();
+
+        select(&mut ctx, &["relays".into(), "unknown".into()]);
+        assert_eq!(vec!["10.0.0.1", "100.0.0.1"], ips(&ctx));
+        // Total is unchanged
+        assert_eq!(14, ctx.count);
+    }
+
+    #[test]
+    fn test_skip_allowed() {
+        let fb: Feedback = from_str(XML).unwrap();
+        let mut ctx = Context::new(&fb[0], &HashMap::new());
+        let allowed = ["9.0.0.1", "192.0.2.1"]
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect();
+
+        skip_allowed(&mut ctx, &allowed);
+        assert_eq!(vec!["10.0.0.1", "100.0.0.1"], ips(&ctx));
+        assert_eq!(14, ctx.count);
+    }
+}
@@ -0,0 +1,551 @@
+//! Terminal table output
+//!
+//! This is the default display when no template is given.  When the output is a terminal, the
+//! table adapts to its width by truncating the longest columns (names and identifiers) with an
+//! ellipsis and highlights the interesting parts in color:
+//!
+//! - failing DKIM/SPF results in red, other non-passing ones in yellow,
+//! - `quarantine` dispositions in yellow and `reject` ones in red,
+//! - unresolved names in yellow, DNS failures in red,
+//! - special-purpose source IPs (private, documentation, etc.) in red as the report is suspect,
+//! - names not confirmed by a forward lookup in red.
+//!
+//! With `--fcrdns`, the `FCrDNS` column is added after the name.  When a known-sender rules file
+//! is loaded, so is the `Sender` column and when ASN databases are loaded, so are the ASN, AS
+//! name and country columns.  Attributes found by the enrichment stages, like `dnsbl`, get their
+//! own column at the end of these, titled with their upper-cased name.
+//!
+//! Pipes and files get plain text with full-width columns unless `--color always` is used.
+//!
+
+// Std library
+//
+use std::io::{stdout, IsTerminal};
+
+// Our crates
+//
+use crate::render::{date, Context, Line};
+
+// External crates
+//
+use clap::ArgEnum;
+use terminal_size::{terminal_size, Width};
+
+/// When do we use colors.
+///
+#[derive(ArgEnum, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ColorMode {
+    /// Only when stdout is a terminal
+    Auto,
+    /// Always, even in pipes
+    Always,
+    /// Never
+    Never,
+}
+
+/// Escape sequences we use.
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+const BOLD: &str = "\x1b[1m";
+const RESET: &str = "\x1b[0m";
+
+/// Columns can not be shrunk below this.
+const MIN_WIDTH: usize = 8;
+
+/// Column titles, in display order.
+const TITLES: [&str; 8] = [
+    "IP", "Name", "Count", "From", "RFrom", "RDKIM", "RSPF", "Disp",
+];
+
+/// Optional ASN columns, displayed after the name (and sender).
+const ASN_TITLES: [&str; 3] = ["ASN", "AS Name", "CC"];
+
+/// Columns we are allowed to truncate, in order of preference.
+const SHRINKABLE: [&str; 5] = ["Name", "AS Name", "Sender", "RFrom", "From"];
+
+/// One table cell with its optional color.
+///
+#[derive(Clone, Debug)]
+struct Cell {
+    text: String,
+    color: Option<&'static str>,
+}
+
+impl Cell {
+    fn new(text: &str, color: Option<&'static str>) -> Self {
+        Cell {
+            text: text.to_owned(),
+            color,
+        }
+    }
+}
+
+/// Table renderer.
+///
+#[derive(Clone, Debug)]
+pub struct Table {
+    /// Use colors?
+    color: bool,
+    /// Maximum width, if any
+    width: Option<usize>,
+    /// Display ASN columns?
+    asn: bool,
+    /// Display the sender column?
+    sender: bool,
+    /// Display the FCrDNS column?
+    fcrdns: bool,
+    /// Attributes displayed as columns
+    attrs: Vec<String>,
+}
+
+impl Table {
+    /// Create a renderer for stdout, checking whether it is a terminal.
+    ///
+    pub fn new(mode: ColorMode) -> Self {
+        let tty = stdout().is_terminal();
+        let color = match mode {
+            ColorMode::Auto => tty,
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+        };
+        let width = match tty {
+            true => terminal_size().map(|(Width(w), _)| usize::from(w)),
+            false => None,
+        };
+        Table {
+            color,
+            width,
+            asn: false,
+            sender: false,
+            fcrdns: false,
+            attrs: vec![],
+        }
+    }
+
+    /// Create a renderer with explicit settings.
+    ///
+    pub fn with(color: bool, width: Option<usize>) -> Self {
+        Table {
+            color,
+            width,
+            asn: false,
+            sender: false,
+            fcrdns: false,
+            attrs: vec![],
+        }
+    }
+
+    /// Add the ASN columns.
+    ///
+    pub fn with_asn(self, asn: bool) -> Self {
+        Table { asn, ..self }
+    }
+
+    /// Add the sender column.
+    ///
+    pub fn with_sender(self, sender: bool) -> Self {
+        Table { sender, ..self }
+    }
+
+    /// Add the FCrDNS column.
+    ///
+    pub fn with_fcrdns(self, fcrdns: bool) -> Self {
+        Table { fcrdns, ..self }
+    }
+
+    /// Add one column for each of these attributes.
+    ///
+    pub fn with_attrs(self, attrs: Vec<String>) -> Self {
+        Table { attrs, ..self }
+    }
+
+    /// Column titles, in display order.
+    ///
+    fn titles(&self) -> Vec<String> {
+        let mut opt = vec![];
+        if self.fcrdns {
+            opt.push("FCrDNS".to_string());
+        }
+        if self.sender {
+            opt.push("Sender".to_string());
+        }
+        if self.asn {
+            opt.extend(ASN_TITLES.map(String::from));
+        }
+        opt.extend(self.attrs.iter().map(|a| a.to_uppercase()));
+
+        let mut t: Vec<String> = TITLES.map(String::from).to_vec();
+        t.splice(2..2, opt);
+        t
+    }
+
+    /// Render one report, with its header and the table of records.
+    ///
+    pub fn render(&self, ctx: &Context) -> String {
+        let md = ctx.metadata;
+        let pol = ctx.policy;
+        let opt = |v: &Option<_>| match v {
+            Some(v) => format!("{:?}", v),
+            None => "".into(),
+        };
+
+        let mut s = format!(
+            "Reporting by: {} — {}\nFrom {} to {}\n\nDomain: {}\nPolicy: p={:?}; dkim={}; spf={}\n\nReports({}):\n",
+            md.org_name,
+            md.email,
+            date(i64::from(md.date_range.begin)),
+            date(i64::from(md.date_range.end)),
+            pol.domain,
+            pol.p,
+            opt(&pol.adkim),
+            opt(&pol.aspf),
+            ctx.lines.len()
+        );
+
+        let titles = self.titles();
+        let mut rows: Vec<Vec<Cell>> = ctx.lines.iter().map(|l| self.cells(l)).collect();
+        if let Some(o) = ctx.others {
+            let mut others = vec![
+                Cell::new("others", None),
+                Cell::new(&format!("({} more)", o.lines), None),
+                Cell::new(&o.count.to_string(), None),
+            ];
+            others.splice(2..2, vec![Cell::new("", None); titles.len() - TITLES.len()]);
+            others.resize(titles.len(), Cell::new("", None));
+            rows.push(others);
+        }
+        let widths = self.widths(&titles, &rows);
+
+        let header: Vec<Cell> = titles.iter().map(|t| Cell::new(t, Some(BOLD))).collect();
+        s.push_str(&self.line(&header, &widths));
+        for row in rows.iter() {
+            s.push_str(&self.line(row, &widths));
+        }
+        s
+    }
+
+    /// Compute column widths, shrinking them to fit into the terminal if needed.
+    ///
+    fn widths(&self, titles: &[String], rows: &[Vec<Cell>]) -> Vec<usize> {
+        let mut widths: Vec<usize> = titles.iter().map(|t| t.chars().count()).collect();
+        for row in rows.iter() {
+            for (i, c) in row.iter().enumerate() {
+                widths[i] = widths[i].max(c.text.chars().count());
+            }
+        }
+
+        if let Some(max) = self.width {
+            // Columns are separated by one space
+            let total = widths.iter().sum::<usize>() + widths.len() - 1;
+            let mut excess = total.saturating_sub(max);
+
+            for t in SHRINKABLE {
+                let i = match titles.iter().position(|c| *c == t) {
+                    Some(i) if excess > 0 => i,
+                    _ => continue,
+                };
+                let floor = MIN_WIDTH.max(t.len());
+                let cut = widths[i].saturating_sub(floor).min(excess);
+                widths[i] -= cut;
+                excess -= cut;
+            }
+        }
+        widths
+    }
+
+    /// Display one line of cells, padded, truncated and colored.
+    ///
+    fn line(&self, row: &[Cell], widths: &[usize]) -> String {
+        let cols: Vec<String> = row
+            .iter()
+            .zip(widths.iter())
+            .enumerate()
+            .map(|(i, (c, w))| {
+                let t = truncate(&c.text, *w);
+                // Do not pad the last column
+                let t = match i == widths.len() - 1 {
+                    true => t,
+                    false => format!("{:<1$}", t, w),
+                };
+                match (self.color, c.color) {
+                    (true, Some(col)) => format!("{}{}{}", col, t, RESET),
+                    _ => t,
+                }
+            })
+            .collect();
+        cols.join(" ").trim_end().to_owned() + "\n"
+    }
+
+    /// Build the cells for one line.
+    ///
+    fn cells(&self, l: &Line) -> Vec<Cell> {
+        let name = match (l.name.is_empty(), l.name == l.ip) {
+            (true, _) if l.ptr.contains("tempfail") => Some(RED),
+            (true, _) | (_, true) => Some(YELLOW),
+            _ => None,
+        };
+
+        let ip = match l.special.is_empty() {
+            true => None,
+            false => Some(RED),
+        };
+
+        let mut c = vec![
+            Cell::new(&l.ip, ip),
+            Cell::new(&l.hostname(), name),
+            Cell::new(&l.count.to_string(), None),
+            Cell::new(&l.from, None),
+            Cell::new(&l.rfrom, None),
+            Cell::new(&l.dkim, result_color(&l.dkim)),
+            Cell::new(&l.spf, result_color(&l.spf)),
+            Cell::new(&l.disposition, disposition_color(&l.disposition)),
+        ];
+        let mut opt = vec![];
+        if self.fcrdns {
+            let color = match l.fcrdns.split(',').any(|v| v == "fail") {
+                true => Some(RED),
+                false => None,
+            };
+            opt.push(Cell::new(&l.fcrdns, color));
+        }
+        if self.sender {
+            opt.push(Cell::new(&l.sender, None));
+        }
+        if self.asn {
+            opt.push(Cell::new(&l.asn, None));
+            opt.push(Cell::new(&l.asname, None));
+            opt.push(Cell::new(&l.country, None));
+        }
+        for a in self.attrs.iter() {
+            opt.push(Cell::new(&l.attrs.text(a), None));
+        }
+        c.splice(2..2, opt);
+        c
+    }
+}
+
+/// Color for a DKIM or SPF result, lists of results (from grouped lines) get the worst one.
+///
+fn result_color(r: &str) -> Option<&'static str> {
+    let l: Vec<&str> = r.split(',').collect();
+
+    if l.iter()
+        .any(|r| matches!(*r, "fail" | "softfail" | "permerror"))
+    {
+        Some(RED)
+    } else if l.iter().any(|r| *r != "pass") {
+        Some(YELLOW)
+    } else {
+        None
+    }
+}
+
+/// Color for a disposition, lists of dispositions get the worst one.
+///
+fn disposition_color(d: &str) -> Option<&'static str> {
+    let l: Vec<&str> = d.split(',').collect();
+
+    if l.contains(&"reject") {
+        Some(RED)
+    } else if l.contains(&"quarantine") {
+        Some(YELLOW)
+    } else {
+        None
+    }
+}
+
+/// Truncate a string to `w` characters, ending with an ellipsis if it was too long.
+///
+fn truncate(s: &str, w: usize) -> String {
+    if s.chars().count() <= w {
+        return s.to_owned();
+    }
+    let mut t: String = s.chars().take(w.saturating_sub(1)).collect();
+    t.push('…');
+    t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::Others;
+    use crate::testutil::{load, GOOGLE};
+    use dmarc_rs::asn::AsnInfo;
+    use dmarc_rs::ip::Ip;
+    use dmarc_rs::types::Feedback;
+
+    use std::collections::HashMap;
+
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("foo", 5, "foo")]
+    #[case("foobar", 6, "foobar")]
+    #[case("foobar", 4, "foo…")]
+    #[case("foobar", 1, "…")]
+    fn test_truncate(#[case] s: &str, #[case] w: usize, #[case] r: &str) {
+        assert_eq!(r, truncate(s, w));
+    }
+
+    #[rstest]
+    #[case("pass", None)]
+    #[case("none", Some(YELLOW))]
+    #[case("fail", Some(RED))]
+    #[case("pass,neutral", Some(YELLOW))]
+    #[case("pass,softfail,none", Some(RED))]
+    fn test_result_color(#[case] r: &str, #[case] c: Option<&'static str>) {
+        assert_eq!(c, result_color(r));
+    }
+
+    #[rstest]
+    #[case("none", None)]
+    #[case("none,quarantine", Some(YELLOW))]
+    #[case("quarantine,reject", Some(RED))]
+    fn test_disposition_color(#[case] d: &str, #[case] c: Option<&'static str>) {
+        assert_eq!(c, disposition_color(d));
+    }
+
+    #[test]
+    fn test_render_plain() {
+        let fb: Feedback = load(GOOGLE);
+        let ctx = Context::new(&fb[0], &HashMap::new());
+
+        let s = Table::with(false, None).render(&ctx);
+        assert!(s.contains("Reports(2):"));
+        assert!(s.contains("195.154.227.159"));
+        assert!(!s.contains(RESET));
+    }
+
+    #[test]
+    fn test_render_color() {
+        let fb: Feedback = load(GOOGLE);
+        let ctx = Context::new(&fb[0], &HashMap::new());
+
+        let s = Table::with(true, None).render(&ctx);
+        assert!(s.contains(&format!("{}none", YELLOW)));
+        assert!(!s.contains(&format!("{}pass", RED)));
+    }
+
+    #[test]
+    fn test_render_others() {
+        let fb: Feedback = load(GOOGLE);
+        let mut ctx = Context::new(&fb[0], &HashMap::new());
+        ctx.lines.truncate(1);
+        ctx.others = Some(Others { lines: 1, count: 1 });
+
+        let s = Table::with(false, None).render(&ctx);
+        assert!(s.contains("others          (1 more)"));
+    }
+
+    #[test]
+    fn test_render_narrow() {
+        let fb: Feedback = load(GOOGLE);
+        let mut names = HashMap::new();
+        let ip = Ip::try_from((
+            "195.154.227.159",
+            "a.very.long.name.for.this.host.example.net",
+        ))
+        .unwrap();
+        names.insert(ip.ip, ip);
+        let ctx = Context::new(&fb[0], &names);
+
+        let s = Table::with(false, Some(80)).render(&ctx);
+        assert!(s.contains("a.very.long.name"));
+        assert!(s.contains('…'));
+        let table = s.lines().skip_while(|l| !l.starts_with("IP"));
+        for l in table {
+            assert!(l.chars().count() <= 80, "{}", l);
+        }
+    }
+
+    #[test]
+    fn test_render_asn() {
+        let fb: Feedback = load(GOOGLE);
+        let mut names = HashMap::new();
+        let mut ip = Ip::try_from(("195.154.227.159", "foo.example.net")).unwrap();
+        ip.asn = Some(AsnInfo {
+            asn: 12876,
+            name: "ONLINE S.A.S.".into(),
+            country: "FR".into(),
+        });
+        names.insert(ip.ip, ip);
+        let ctx = Context::new(&fb[0], &names);
+
+        let s = Table::with(false, None).render(&ctx);
+        assert!(!s.contains("AS12876"));
+
+        let s = Table::with(false, None).with_asn(true).render(&ctx);
+        assert!(s.contains("ASN"));
+        assert!(s.contains("AS Name"));
+        assert!(s.contains("foo.example.net AS12876 ONLINE S.A.S. FR"));
+    }
+
+    #[test]
+    fn test_render_sender() {
+        let fb: Feedback = load(GOOGLE);
+        let mut ctx = Context::new(&fb[0], &HashMap::new());
+        ctx.lines[0].sender = "Our relays".into();
+        ctx.others = Some(Others { lines: 1, count: 1 });
+
+        let s = Table::with(false, None)
+            .with_sender(true)
+            .with_asn(true)
+            .render(&ctx);
+        let header = s.lines().find(|l| l.starts_with("IP")).unwrap();
+        let titles: Vec<&str> = header.split_whitespace().take(5).collect();
+        assert_eq!(vec!["IP", "Name", "Sender", "ASN", "AS"], titles);
+        assert!(s.contains(" Our relays "));
+        assert!(s.contains("others          (1 more)"));
+    }
+
+    #[test]
+    fn test_render_failures() {
+        let fb: Feedback = load(GOOGLE);
+        let mut ctx = Context::new(&fb[0], &HashMap::new());
+        ctx.lines[0].ptr = "tempfail".into();
+        ctx.lines[1].ptr = "nxdomain".into();
+
+        let s = Table::with(true, None).render(&ctx);
+        assert!(s.contains(&format!("{}(DNS failure)", RED)));
+        assert!(s.contains(&format!("{}(no PTR)", YELLOW)));
+    }
+
+    #[test]
+    fn test_render_fcrdns() {
+        let fb: Feedback = load(GOOGLE);
+        let mut ctx = Context::new(&fb[0], &HashMap::new());
+        ctx.lines[0].fcrdns = "pass".into();
+        ctx.lines[1].fcrdns = "fail".into();
+
+        let s = Table::with(true, None).render(&ctx);
+        assert!(!s.contains("FCrDNS"));
+
+        let s = Table::with(true, None)
+            .with_fcrdns(true)
+            .with_sender(true)
+            .render(&ctx);
+        let header = s.lines().find(|l| l.contains("IP")).unwrap();
+        assert!(header.contains("FCrDNS"));
+        assert!(header.find("FCrDNS") < header.find("Sender"));
+        assert!(s.contains(&format!("{}fail", RED)));
+        assert!(!s.contains(&format!("{}pass", RED)));
+    }
+
+    #[test]
+    fn test_render_attrs() {
+        let fb: Feedback = load(GOOGLE);
+        let mut ctx = Context::new(&fb[0], &HashMap::new());
+        ctx.lines[0]
+            .attrs
+            .set("dnsbl", vec!["bl.example".to_string()]);
+
+        let s = Table::with(false, None)
+            .with_asn(true)
+            .with_attrs(vec!["dnsbl".into()])
+            .render(&ctx);
+        let header = s.lines().find(|l| l.starts_with("IP")).unwrap();
+        assert!(header.find("CC") < header.find("DNSBL"));
+        assert!(header.find("DNSBL") < header.find("Count"));
+        assert!(s.contains(" bl.example "));
+    }
+}
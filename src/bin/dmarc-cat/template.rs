@@ -0,0 +1,197 @@
+//! Output templates
+//!
+//! Reports can be displayed through [Handlebars] templates instead of the default table, rendered
+//! once per report with a `render::Context` as data.  You can either use one of the built-in
+//! templates by name or give the path to your own file with `--template`.
+//!
+//! Built-in templates:
+//!
+//! - `report`: the classic full report, the base columns of the default table in fixed widths (wide
+//!   enough for any IPv6 address) and without colors
+//! - `oneline`: one line per report, for ops
+//! - `ticket`: a short summary suitable for a ticket
+//! - `customer`: a more verbose report for people not familiar with DMARC
+//!
+//! On top of the standard helpers, we define the following:
+//!
+//! - `pad N value`: left-align `value` in a field of `N` characters
+//! - `rpad N value`: right-align `value` in a field of `N` characters
+//! - `date ts`: display a UNIX timestamp as a UTC date
+//! - `hostname line`: display the name of a line (with the number of other ones) or why there is
+//!   none, like the table does
+//!
+//! [Handlebars]: https://handlebarsjs.com/guide/
+//!
+
+// Std library
+//
+use std::fs;
+use std::path::Path;
+
+// Our crates
+//
+use crate::render::{date, hostname, Context};
+
+// External crates
+//
+use anyhow::{anyhow, Result};
+use handlebars::{handlebars_helper, no_escape, Handlebars};
+use serde_json::Value;
+
+/// All the templates we ship with.
+const BUILTINS: [(&str, &str); 4] = [
+    ("report", include_str!("templates/report.hbs")),
+    ("oneline", include_str!("templates/oneline.hbs")),
+    ("ticket", include_str!("templates/ticket.hbs")),
+    ("customer", include_str!("templates/customer.hbs")),
+];
+
+/// Name under which the template is registered.
+const NAME: &str = "main";
+
+/// Display a JSON value without quotes for strings.
+///
+fn as_text(v: &Value) -> String {
+    match v {
+        Value::String(s) => s.to_owned(),
+        Value::Null => String::new(),
+        _ => v.to_string(),
+    }
+}
+
+handlebars_helper!(pad: |w: u64, v: Json| format!("{:<1$}", as_text(v), w as usize));
+handlebars_helper!(rpad: |w: u64, v: Json| format!("{:>1$}", as_text(v), w as usize));
+handlebars_helper!(date_helper: |ts: i64| date(ts));
+handlebars_helper!(hostname_helper: |l: Json| line_hostname(l));
+
+/// Same as `Line::hostname()` but on a serialized line.
+///
+fn line_hostname(v: &Value) -> String {
+    let text = |k: &str| v.get(k).and_then(|s| s.as_str()).unwrap_or_default();
+    let count = v.get("names").and_then(|n| n.as_array()).map(|n| n.len());
+
+    match (text("ptr").is_empty(), text("special").is_empty()) {
+        (true, false) if text("name").is_empty() => format!("({})", text("special")),
+        _ => hostname(text("name"), count.unwrap_or(0), text("ptr")),
+    }
+}
+
+/// A compiled template ready for rendering.
+///
+#[derive(Debug)]
+pub struct Template {
+    hb: Handlebars<'static>,
+}
+
+impl Template {
+    /// Load the template, `spec` being either a path to a file or the name of a built-in one.
+    ///
+    pub fn load(spec: &str) -> Result<Self> {
+        let text = if Path::new(spec).is_file() {
+            fs::read_to_string(spec)?
+        } else {
+            match BUILTINS.iter().find(|(n, _)| *n == spec) {
+                Some((_, t)) => t.to_string(),
+                None => {
+                    return Err(anyhow!(
+                        "Unknown template {}, not a file nor one of {}",
+                        spec,
+                        builtins().join(", ")
+                    ))
+                }
+            }
+        };
+        Self::compile(&text)
+    }
+
+    /// Compile the given template text.
+    ///
+    pub fn compile(text: &str) -> Result<Self> {
+        let mut hb = Handlebars::new();
+
+        hb.register_escape_fn(no_escape);
+        hb.register_helper("pad", Box::new(pad));
+        hb.register_helper("rpad", Box::new(rpad));
+        hb.register_helper("date", Box::new(date_helper));
+        hb.register_helper("hostname", Box::new(hostname_helper));
+        hb.register_template_string(NAME, text)?;
+        Ok(Template { hb })
+    }
+
+    /// Render one report.
+    ///
+    pub fn render(&self, ctx: &Context) -> Result<String> {
+        Ok(self.hb.render(NAME, ctx)?)
+    }
+}
+
+/// List the names of the built-in templates.
+///
+pub fn builtins() -> Vec<&'static str> {
+    BUILTINS.iter().map(|(n, _)| *n).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{load, GOOGLE};
+    use dmarc_rs::types::Feedback;
+
+    use std::collections::HashMap;
+
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("report")]
+    #[case("oneline")]
+    #[case("ticket")]
+    #[case("customer")]
+    fn test_builtins(#[case] name: &str) {
+        let fb: Feedback = load(GOOGLE);
+        let ctx = Context::new(&fb[0], &HashMap::new());
+
+        let t = Template::load(name).unwrap();
+        let s = t.render(&ctx).unwrap();
+        assert!(s.contains("keltia.net"));
+    }
+
+    #[test]
+    fn test_load_unknown() {
+        assert!(Template::load("/nonexistent").is_err());
+    }
+
+    #[test]
+    fn test_helpers() {
+        let fb: Feedback = load(GOOGLE);
+        let ctx = Context::new(&fb[0], &HashMap::new());
+
+        let t = Template::compile(
+            "[{{pad 6 policy.domain}}][{{pad 12 policy.domain}}][{{rpad 4 count}}][{{date metadata.date_range.begin}}]",
+        )
+        .unwrap();
+        assert_eq!(
+            "[keltia.net][keltia.net  ][   2][2018-10-02 00:00:00 UTC]",
+            t.render(&ctx).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_hostname_helper() {
+        let fb: Feedback = load(GOOGLE);
+        let mut ctx = Context::new(&fb[0], &HashMap::new());
+        ctx.lines[0].ptr = "nxdomain".into();
+        ctx.lines[1].name = "mx1.example.net".into();
+        ctx.lines[1].names = vec!["mx1.example.net".into(), "www.example.net".into()];
+
+        let t = Template::compile("{{#each lines}}[{{pad 10 (hostname this)}}]{{/each}}").unwrap();
+        assert_eq!(
+            "[(no PTR)  ][mx1.example.net (+1)]",
+            t.render(&ctx).unwrap()
+        );
+
+        ctx.lines[1].name = String::new();
+        ctx.lines[1].names = vec![];
+        ctx.lines[1].special = "documentation".into();
+        assert_eq!("[(no PTR)  ][(documentation)]", t.render(&ctx).unwrap());
+    }
+}
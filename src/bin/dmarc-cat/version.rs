@@ -20,14 +20,14 @@ pub(crate) const AUTHORS: &str = crate_authors!();
 ///
 #[inline]
 pub fn version() -> String {
-    return format!(
+    format!(
         "{}/{}/j{} by {}\n{}",
         NAME,
         VERSION,
         num_cpus::get_physical(),
         AUTHORS,
         crate_description!()
-    );
+    )
 }
 
 #[cfg(test)]
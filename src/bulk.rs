@@ -0,0 +1,530 @@
+//! Module implementing bulk resolution of lists of IPs.
+//!
+//! We use `IpList` as container and `BulkSolver` resolves a whole list with any `Solver` (see
+//! `dmarc_rs::resolver`), returning the results in the order of the list.  Options are set with
+//! the `with_*()` methods:
+//!
+//! - `with_jobs()`: the number of workers; with 1 job the simple sequential solver is used,
+//!   otherwise a bounded pool of workers takes IPs from the list in turn.  Resolving is I/O-bound
+//!   so the number of jobs is not limited by the number of CPU,
+//! - `with_cancel()`: a `Cancel` handle to stop the resolution (this is what Ctrl-C does in
+//!   `dmarc-cat`), lookups in progress are finished and the remaining IPs are returned without
+//!   any name,
+//! - `with_progress()`: a callback called for every IP resolved, with the number done so far and
+//!   the total.
+//!
+//! `stream()` is the same pipeline as the parallel solver, without waiting for the end: results
+//! are sent as soon as they are available, tagged with their position in the list, through a
+//! channel of bounded size so that a slow consumer holds the workers back.
+//!
+//! A resolver panicking makes `solve()` panic too, whatever the number of jobs.  `stream()` sends
+//! the IP back as a temporary failure instead.
+//!
+//! `resolve()` is a shortcut for the most common case.
+//!
+//! Examples:
+//! ```
+//! use dmarc_rs::bulk::{resolve, BulkSolver, Cancel};
+//! use dmarc_rs::iplist::IpList;
+//! use dmarc_rs::resolver::{res_init, ResType};
+//!
+//! let l = IpList::from(["192.0.2.1", "2001:db8::1", "192.0.2.2"]);
+//! let res = res_init(ResType::Null);
+//!
+//! // Use the simple solver
+//! let ptr = resolve(&l, 1, &res, &Cancel::new()).unwrap();
+//! assert_eq!("2001:db8::1", ptr[1].name());
+//!
+//! // Use the parallel solver, lookups mostly wait for the network
+//! let ptr = BulkSolver::new(res)
+//!     .with_jobs(16)
+//!     .with_progress(|done, total, ip| println!("{}/{} {}", done, total, ip.name()))
+//!     .solve(&l)
+//!     .unwrap();
+//! assert_eq!("192.0.2.2", ptr[2].name());
+//! ```
+//!
+
+// Std library
+//
+use std::fmt::{Debug, Formatter};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::Arc;
+use std::thread;
+
+// Our crates
+//
+use crate::ip::Ip;
+use crate::iplist::IpList;
+use crate::resolver::{SolveError, Solver};
+
+// External crates
+//
+use anyhow::{anyhow, Result};
+
+/// Handle to stop a resolution in progress, shared by all its clones.
+///
+#[derive(Clone, Debug, Default)]
+pub struct Cancel(Arc<AtomicBool>);
+
+impl Cancel {
+    /// Create a new handle.
+    ///
+    pub fn new() -> Self {
+        Cancel::default()
+    }
+
+    /// Stop the resolutions using this handle.
+    ///
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Has it been stopped?
+    ///
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Progress callback, called with the number of IPs done so far, the total and the last one.
+///
+pub type Progress = Arc<dyn Fn(usize, usize, &Ip) + Send + Sync>;
+
+/// Resolve lists of IPs with a given resolver.
+///
+#[derive(Clone)]
+pub struct BulkSolver {
+    res: Solver,
+    jobs: usize,
+    cancel: Cancel,
+    progress: Option<Progress>,
+}
+
+impl BulkSolver {
+    /// Use this resolver with as many jobs as physical cores.
+    ///
+    pub fn new(res: Solver) -> Self {
+        BulkSolver {
+            res,
+            jobs: num_cpus::get_physical(),
+            cancel: Cancel::new(),
+            progress: None,
+        }
+    }
+
+    /// Set the number of jobs, 0 being an error when resolving.
+    ///
+    pub fn with_jobs(self, jobs: usize) -> Self {
+        BulkSolver { jobs, ..self }
+    }
+
+    /// Stop resolving when this handle is cancelled.
+    ///
+    pub fn with_cancel(self, cancel: Cancel) -> Self {
+        BulkSolver { cancel, ..self }
+    }
+
+    /// Call `f` after each IP is resolved, from the calling thread.
+    ///
+    pub fn with_progress<F>(self, f: F) -> Self
+    where
+        F: Fn(usize, usize, &Ip) + Send + Sync + 'static,
+    {
+        BulkSolver {
+            progress: Some(Arc::new(f)),
+            ..self
+        }
+    }
+
+    /// Resolve a list, in the same order.  Failures are recorded in the `error` field of each
+    /// `Ip`.
+    ///
+    /// An empty list or no job is an error.
+    ///
+    pub fn solve(&self, ipl: &IpList) -> Result<IpList> {
+        // Return an error on empty list
+        // XXX maybe return the empty list?
+        if ipl.is_empty() {
+            return Err(anyhow!("Empty list"));
+        }
+
+        if self.jobs == 0 {
+            return Err(anyhow!("At least one job is needed"));
+        }
+
+        // Call the appropriate one
+        //
+        match self.jobs.min(ipl.len()) {
+            1 => Ok(self.simple_solve(ipl)),
+            n => Ok(self.parallel_solve(ipl, n)),
+        }
+    }
+
+    /// Start the workers resolving the list and return the channel where each result is sent as
+    /// soon as it is available, with its position in the list.
+    ///
+    /// Workers take the next IP from the list until it is exhausted, the resolution is cancelled
+    /// or the receiving end is dropped.  The channel holds at most one result per job.  The
+    /// progress callback is not used.  There is nobody to pass a panic of the resolver to, so the
+    /// IP is sent back marked as a temporary failure instead.
+    ///
+    pub fn stream(&self, ipl: &IpList) -> Receiver<(usize, Ip)> {
+        let all: Arc<Vec<Ip>> = Arc::new(ipl.clone().into_iter().collect());
+        let next = Arc::new(AtomicUsize::new(0));
+        let (tx, rx) = sync_channel(self.jobs.max(1));
+
+        for _ in 0..self.jobs.min(all.len()) {
+            let (all, next, tx) = (all.clone(), next.clone(), tx.clone());
+            let (res, cancel) = (self.res.clone(), self.cancel.clone());
+
+            thread::spawn(move || {
+                let solve = |ip: &Ip| {
+                    panic::catch_unwind(AssertUnwindSafe(|| res.solve_or_mark(ip))).unwrap_or_else(
+                        |_| Ip {
+                            names: vec![],
+                            error: Some(SolveError::TempFail("resolver panicked".into())),
+                            ..ip.clone()
+                        },
+                    )
+                };
+                work(&all, &next, &cancel, solve, tx);
+            });
+        }
+        rx
+    }
+
+    /// Simple and straightforward sequential solver.
+    ///
+    fn simple_solve(&self, ipl: &IpList) -> IpList {
+        let total = ipl.len();
+
+        ipl.clone()
+            .into_iter()
+            .enumerate()
+            .map(|(n, ip)| match self.cancel.is_cancelled() {
+                true => ip,
+                false => {
+                    let ip = self.res.solve_or_mark(&ip);
+                    self.report(n + 1, total, &ip);
+                    ip
+                }
+            })
+            .collect()
+    }
+
+    /// Convert a list of IP into names with `njobs` workers, keeping the order of the list.
+    ///
+    /// A panic of the resolver in a worker makes the whole resolution panic, like with the
+    /// sequential solver.
+    ///
+    fn parallel_solve(&self, ipl: &IpList, njobs: usize) -> IpList {
+        let mut full = ipl.clone();
+        let total = ipl.len();
+        let all: Vec<Ip> = ipl.clone().into_iter().collect();
+        let next = AtomicUsize::new(0);
+        let (tx, rx) = sync_channel(njobs);
+
+        thread::scope(|s| {
+            let workers: Vec<_> = (0..njobs.min(total))
+                .map(|_| {
+                    let tx = tx.clone();
+                    s.spawn(|| {
+                        work(
+                            &all,
+                            &next,
+                            &self.cancel,
+                            |ip| self.res.solve_or_mark(ip),
+                            tx,
+                        )
+                    })
+                })
+                .collect();
+            drop(tx);
+
+            for (n, (i, ip)) in rx.iter().enumerate() {
+                self.report(n + 1, total, &ip);
+                full[i] = ip;
+            }
+            for w in workers {
+                w.join().unwrap_or_else(|e| panic::resume_unwind(e));
+            }
+        });
+        full
+    }
+
+    /// Call the progress callback, if any.
+    ///
+    fn report(&self, done: usize, total: usize, ip: &Ip) {
+        if let Some(f) = &self.progress {
+            f(done, total, ip);
+        }
+    }
+}
+
+impl Debug for BulkSolver {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "bulksolver jobs={} cancelled={} progress={}",
+            self.jobs,
+            self.cancel.is_cancelled(),
+            self.progress.is_some()
+        )
+    }
+}
+
+/// Take the next IP from the list and send it resolved with its position, until the list is
+/// exhausted, the resolution is cancelled or the receiving end is dropped.
+///
+fn work<F>(all: &[Ip], next: &AtomicUsize, cancel: &Cancel, solve: F, tx: SyncSender<(usize, Ip)>)
+where
+    F: Fn(&Ip) -> Ip,
+{
+    while !cancel.is_cancelled() {
+        let i = next.fetch_add(1, Ordering::Relaxed);
+        let ip = match all.get(i) {
+            Some(ip) => solve(ip),
+            None => break,
+        };
+        if tx.send((i, ip)).is_err() {
+            break;
+        }
+    }
+}
+
+/// `resolve()` is the main function call to get all names from the list of `Ip` we get from the
+/// XML file, in the same order.
+///
+/// Example:
+/// ```no_run
+/// # use dmarc_rs::bulk::{resolve, Cancel};
+/// # use dmarc_rs::iplist::IpList;
+/// # use dmarc_rs::resolver::{res_init, ResType};
+/// let l = IpList::from(["1.1.1.1", "2606:4700:4700::1111", "192.0.2.1"]);
+///
+/// // Select a resolver
+/// let res = res_init(ResType::Real);
+///
+/// // Using the simple single threaded solver.
+/// let ptr = resolve(&l, 1, &res, &Cancel::new()).unwrap();
+///
+/// // Use the parallel solver with 32 workers.
+/// let ptr2 = resolve(&l, 32, &res, &Cancel::new()).unwrap();
+/// ```
+///
+pub fn resolve(ipl: &IpList, njobs: usize, res: &Solver, cancel: &Cancel) -> Result<IpList> {
+    BulkSolver::new(res.clone())
+        .with_jobs(njobs)
+        .with_cancel(cancel.clone())
+        .solve(ipl)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::stubs::Panicking;
+    use crate::resolver::{res_init, ResType};
+
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_invalid_jobs() {
+        let l = IpList::from(["1.1.1.1", "2606:4700:4700::1111", "192.0.2.1"]);
+        let res = res_init(ResType::Fake);
+
+        assert!(resolve(&l, 0, &res, &Cancel::new()).is_err())
+    }
+
+    #[test]
+    fn test_many_jobs() {
+        let l = IpList::from(["1.1.1.1", "2606:4700:4700::1111", "192.0.2.1"]);
+        let res = res_init(ResType::Fake);
+
+        assert_eq!(3, resolve(&l, 1000, &res, &Cancel::new()).unwrap().len())
+    }
+
+    #[test]
+    fn test_resolve() {
+        let l = IpList::from(["1.1.1.1", "2606:4700:4700::1111", "192.0.2.1"]);
+        let res = res_init(ResType::Fake);
+
+        // Using the simple single threaded solver.
+        let ptr = resolve(&l, 1, &res, &Cancel::new()).unwrap();
+
+        // Use the parallel solver with 4 threads.
+        let ptr2 = resolve(&l, 4, &res, &Cancel::new()).unwrap();
+
+        assert_eq!(ptr, ptr2);
+    }
+
+    #[test]
+    fn test_parallel_solve_empty() {
+        let a = IpList::new();
+        let bulk = BulkSolver::new(res_init(ResType::Fake));
+
+        let r = bulk.parallel_solve(&a, 4);
+
+        assert!(r.is_empty())
+    }
+
+    #[test]
+    fn test_simple_solve_empty() {
+        let a = IpList::new();
+        let bulk = BulkSolver::new(res_init(ResType::Fake));
+
+        let r = bulk.simple_solve(&a);
+
+        assert!(r.is_empty())
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_solve_empty() {
+        let a = IpList::new();
+        let res = res_init(ResType::Fake);
+
+        let r = resolve(&a, 1, &res, &Cancel::new());
+
+        assert!(r.is_err());
+        assert!(r.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_dumb_simple_solve_ok() {
+        let l = IpList::from(["1.1.1.1", "2606:4700:4700::1111", "192.0.2.1"]);
+        let bulk = BulkSolver::new(res_init(ResType::Fake));
+
+        let ptr = bulk.simple_solve(&l);
+
+        assert_eq!(l.len(), ptr.len());
+        for x in ptr {
+            assert_eq!("some.host.invalid", x.name());
+        }
+    }
+
+    #[test]
+    fn test_dumb_parallel_solve_ok() {
+        let l = IpList::from(["1.1.1.1", "2606:4700:4700::1111", "192.0.2.1"]);
+        let bulk = BulkSolver::new(res_init(ResType::Fake));
+
+        let ptr = bulk.parallel_solve(&l, 4);
+
+        assert_eq!(l.len(), ptr.len());
+        for x in ptr {
+            assert_eq!("some.host.invalid", x.name());
+        }
+    }
+
+    #[test]
+    fn test_parallel_solve_order() {
+        let ips: Vec<String> = (1..=200).map(|i| format!("192.0.2.{}", i)).collect();
+        let l: IpList = ips.iter().map(|s| s.as_str()).collect();
+        let bulk = BulkSolver::new(res_init(ResType::Null));
+
+        let ptr = bulk.parallel_solve(&l, 16);
+        let names: Vec<String> = ptr.into_iter().map(|ip| ip.name().to_owned()).collect();
+        assert_eq!(ips, names);
+    }
+
+    #[test]
+    fn test_stream() {
+        let l = IpList::from(["1.1.1.1", "2606:4700:4700::1111", "192.0.2.1"]);
+        let bulk = BulkSolver::new(res_init(ResType::Null)).with_jobs(2);
+
+        let mut r: Vec<(usize, String)> = bulk
+            .stream(&l)
+            .iter()
+            .map(|(i, ip)| (i, ip.name().to_owned()))
+            .collect();
+        r.sort();
+        assert_eq!(
+            vec![
+                (0, "1.1.1.1".to_string()),
+                (1, "2606:4700:4700::1111".to_string()),
+                (2, "192.0.2.1".to_string())
+            ],
+            r
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "bad resolver")]
+    fn test_parallel_solve_panic() {
+        let l = IpList::from(["1.1.1.1", "192.0.2.1", "1.0.0.1", "2606:4700:4700::1111"]);
+        let bulk = BulkSolver::new(Solver::new(Panicking));
+
+        let _ = bulk.parallel_solve(&l, 2);
+    }
+
+    #[test]
+    fn test_stream_panic() {
+        let l = IpList::from(["1.1.1.1", "192.0.2.1", "1.0.0.1", "2606:4700:4700::1111"]);
+        let bulk = BulkSolver::new(Solver::new(Panicking)).with_jobs(2);
+
+        let mut r: Vec<(usize, Ip)> = bulk.stream(&l).iter().collect();
+        r.sort_by_key(|(i, _)| *i);
+        assert_eq!(
+            vec![0, 1, 2, 3],
+            r.iter().map(|(i, _)| *i).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            Some(SolveError::TempFail("resolver panicked".into())),
+            r[1].1.error
+        );
+        assert_eq!("1.0.0.1", r[2].1.name());
+    }
+
+    #[test]
+    fn test_cancel() {
+        let l = IpList::from(["1.1.1.1", "2606:4700:4700::1111", "192.0.2.1"]);
+        let res = res_init(ResType::Null);
+        let cancel = Cancel::new();
+        cancel.cancel();
+
+        for n in [1, 2] {
+            let ptr = resolve(&l, n, &res, &cancel).unwrap();
+            assert_eq!(l, ptr);
+        }
+    }
+
+    #[test]
+    fn test_progress() {
+        let l: IpList = (1..=20)
+            .map(|i| Ip::new(&format!("192.0.2.{}", i)))
+            .collect();
+
+        for n in [1, 4] {
+            let seen = Arc::new(Mutex::new(vec![]));
+            let s = seen.clone();
+            let bulk = BulkSolver::new(res_init(ResType::Null))
+                .with_jobs(n)
+                .with_progress(move |done, total, ip| {
+                    s.lock().unwrap().push((done, total, ip.name().to_owned()))
+                });
+
+            bulk.solve(&l).unwrap();
+            let seen = seen.lock().unwrap();
+            assert_eq!(20, seen.len());
+            for (i, (done, total, _)) in seen.iter().enumerate() {
+                assert_eq!((i + 1, 20), (*done, *total));
+            }
+        }
+    }
+
+    #[test]
+    fn test_fakesolver_resolve() {
+        let l = IpList::from(["1.1.1.1", "2606:4700:4700::1111", "192.0.2.1"]);
+        let res = res_init(ResType::Fake);
+
+        let ptr = resolve(&l, 1, &res, &Cancel::new());
+        assert!(ptr.is_ok());
+
+        let ptr = ptr.unwrap();
+        for x in ptr {
+            assert_eq!("some.host.invalid", x.name());
+        }
+    }
+}
@@ -0,0 +1,539 @@
+//! Module implementing a persistent cache for resolvers.
+//!
+//! Running over the same archive again and again means looking up the same IPs every time.  A
+//! `Cache` keeps the answers of any `Solver` in a local JSON file so that the next runs only ask
+//! for what they do not already know:
+//!
+//! - names found for an IP and addresses found for a name are kept for `ttl`,
+//! - negative answers (NXDOMAIN, no PTR record) are kept for `negative_ttl`, usually shorter,
+//! - temporary failures are never cached, they are tried again next time.
+//!
+//! Expired entries are ignored when reading and dropped when saving.  A file we can not read
+//! (corrupted or from another version) is ignored with a warning and replaced when saving.
+//!
+//! The cache is loaded by `open()` and written back by `save()`.  Several runs can share the same
+//! file: saving takes a lock file, merges our entries with whatever is on disk (the one expiring
+//! last wins) and replaces the file atomically, so readers never see a partial file.
+//!
+//! Example:
+//! ```no_run
+//! use dmarc_rs::cache::Cache;
+//! use dmarc_rs::ip::Ip;
+//! use dmarc_rs::resolver::{res_init, ResType};
+//!
+//! let cache = Cache::open("resolver.json").unwrap();
+//! let res = cache.wrap(res_init(ResType::Real));
+//!
+//! let ip = res.solve(&Ip::new("1.1.1.1"));
+//! cache.save().unwrap();
+//! ```
+//!
+
+// Std library
+//
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::hash::Hash;
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread::sleep;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Our crates
+//
+use crate::ip::Ip;
+use crate::metrics::Metrics;
+use crate::resolver::{Resolver, SolveError, Solver};
+
+// External crates
+//
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+/// Default TTL for names & addresses (1 day)
+pub const DEF_TTL: u64 = 86_400;
+/// Default TTL for negative answers (1 hour)
+pub const DEF_NEGATIVE_TTL: u64 = 3_600;
+
+/// Version of the file format.
+const VERSION: u32 = 1;
+
+/// How long do we wait for the lock file.
+const LOCK_WAIT: Duration = Duration::from_secs(5);
+/// A lock file older than this is left over by a dead run.
+const LOCK_STALE: Duration = Duration::from_secs(60);
+
+/// One cached answer, `None` being a negative one.
+///
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+struct Entry<T> {
+    value: Option<T>,
+    /// UNIX timestamp
+    expires: u64,
+}
+
+/// Content of the cache file.
+///
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+struct Entries {
+    version: u32,
+    #[serde(default)]
+    ptr: HashMap<IpAddr, Entry<Vec<String>>>,
+    #[serde(default)]
+    forward: HashMap<String, Entry<Vec<IpAddr>>>,
+}
+
+impl Entries {
+    /// Read a cache file, a missing one being empty.
+    ///
+    fn load(path: &Path) -> Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(s) => Entries::parse(&s).map_err(|e| anyhow!("{}: {}", path.display(), e)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Entries::default()),
+            Err(e) => Err(anyhow!("{}: {}", path.display(), e)),
+        }
+    }
+
+    /// Decode the content of a cache file.
+    ///
+    fn parse(s: &str) -> Result<Self> {
+        let e: Entries = serde_json::from_str(s)?;
+        match e.version {
+            VERSION => Ok(e),
+            v => Err(anyhow!("unknown version {}", v)),
+        }
+    }
+
+    /// Add the other entries, keeping the one expiring last, and drop the expired ones.
+    ///
+    fn merge(&mut self, other: Entries, now: u64) {
+        merge_map(&mut self.ptr, other.ptr, now);
+        merge_map(&mut self.forward, other.forward, now);
+    }
+}
+
+/// Merge two maps of entries, see `Entries::merge()`.
+///
+fn merge_map<K: Eq + Hash, T>(m: &mut HashMap<K, Entry<T>>, other: HashMap<K, Entry<T>>, now: u64) {
+    for (k, e) in other {
+        match m.get(&k) {
+            Some(old) if old.expires >= e.expires => (),
+            _ => {
+                m.insert(k, e);
+            }
+        }
+    }
+    m.retain(|_, e| e.expires > now);
+}
+
+/// A resolver cache backed by a file, shared by all the `Solver` it wraps.
+///
+#[derive(Clone, Debug)]
+pub struct Cache {
+    path: PathBuf,
+    ttl: u64,
+    negative_ttl: u64,
+    bypass: bool,
+    entries: Arc<Mutex<Entries>>,
+    metrics: Option<Metrics>,
+}
+
+impl Cache {
+    /// Load the cache file, creating an empty cache if it does not exist or can not be decoded.
+    ///
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let entries = match fs::read_to_string(path) {
+            Ok(s) => Entries::parse(&s).unwrap_or_else(|e| {
+                log::warn!("{}: {}, starting with an empty cache", path.display(), e);
+                Entries::default()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Entries::default(),
+            Err(e) => return Err(anyhow!("{}: {}", path.display(), e)),
+        };
+        log::info!(
+            "cache: {} names & {} addresses in {}",
+            entries.ptr.len(),
+            entries.forward.len(),
+            path.display()
+        );
+        Ok(Cache {
+            path: path.to_owned(),
+            ttl: DEF_TTL,
+            negative_ttl: DEF_NEGATIVE_TTL,
+            bypass: false,
+            entries: Arc::new(Mutex::new(entries)),
+            metrics: None,
+        })
+    }
+
+    /// Set the TTLs for answers & negative answers, in seconds.
+    ///
+    pub fn with_ttl(self, ttl: u64, negative_ttl: u64) -> Self {
+        Cache {
+            ttl,
+            negative_ttl,
+            ..self
+        }
+    }
+
+    /// Ignore cached answers, everything is looked up again and the cache updated.
+    ///
+    pub fn with_bypass(self, bypass: bool) -> Self {
+        Cache { bypass, ..self }
+    }
+
+    /// Count the answers found in the cache in these metrics.
+    ///
+    pub fn with_metrics(self, metrics: &Metrics) -> Self {
+        Cache {
+            metrics: Some(metrics.clone()),
+            ..self
+        }
+    }
+
+    /// Number of cached entries, expired ones included.
+    ///
+    pub fn len(&self) -> usize {
+        let e = self.entries.lock().unwrap();
+        e.ptr.len() + e.forward.len()
+    }
+
+    /// Is the cache empty?
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Forget every entry, in memory and on disk.
+    ///
+    /// The file is removed under the same lock as `save()` so that we do not race another run
+    /// replacing it.
+    ///
+    pub fn clear(&self) -> Result<()> {
+        *self.entries.lock().unwrap() = Entries::default();
+        if !self.path.exists() {
+            return Ok(());
+        }
+        let _lock = Lock::take(&self.path)?;
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                Err(anyhow!("{}: {}", self.path.display(), e))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Wrap a `Solver` to look in the cache first and record its answers.
+    ///
+    pub fn wrap(&self, inner: Solver) -> Solver {
+        Solver::new(CacheResolver {
+            cache: self.clone(),
+            inner,
+        })
+    }
+
+    /// Write the cache back, merging it with the current content of the file.
+    ///
+    pub fn save(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent().filter(|d| !d.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(|e| anyhow!("{}: {}", dir.display(), e))?;
+        }
+        let _lock = Lock::take(&self.path)?;
+
+        let mut entries = self.entries.lock().unwrap().clone();
+        entries.version = VERSION;
+        // Do not fail because another run left garbage, we are replacing it anyway.
+        let on_disk = Entries::load(&self.path).unwrap_or_default();
+        entries.merge(on_disk, now());
+
+        let tmp = self
+            .path
+            .with_extension(format!("tmp.{}", std::process::id()));
+        let write = || -> io::Result<()> {
+            let mut f = fs::File::create(&tmp)?;
+            f.write_all(serde_json::to_string(&entries)?.as_bytes())?;
+            f.sync_all()?;
+            fs::rename(&tmp, &self.path)
+        };
+        write().map_err(|e| {
+            let _ = fs::remove_file(&tmp);
+            anyhow!("{}: {}", self.path.display(), e)
+        })
+    }
+
+    /// Look for a fresh answer.
+    ///
+    fn get<T: Clone>(&self, m: impl Fn(&Entries) -> Option<&Entry<T>>) -> Option<Option<T>> {
+        if self.bypass {
+            return None;
+        }
+        let e = self.entries.lock().unwrap();
+        let r = m(&e).filter(|e| e.expires > now()).map(|e| e.value.clone());
+        if let (Some(_), Some(metrics)) = (&r, &self.metrics) {
+            metrics.cache_hit();
+        }
+        r
+    }
+
+    /// Compute the entry for an answer, temporary failures are not cached.
+    ///
+    fn entry<T: Clone>(&self, r: &Result<T, SolveError>) -> Option<Entry<T>> {
+        match r {
+            Ok(v) => Some(Entry {
+                value: Some(v.clone()),
+                expires: now() + self.ttl,
+            }),
+            Err(SolveError::NotFound) => Some(Entry {
+                value: None,
+                expires: now() + self.negative_ttl,
+            }),
+            Err(SolveError::TempFail(_) | SolveError::Timeout) => None,
+        }
+    }
+}
+
+/// The `Resolver` looking in a `Cache` before asking the inner one.
+///
+struct CacheResolver {
+    cache: Cache,
+    inner: Solver,
+}
+
+impl Resolver for CacheResolver {
+    /// Implement the `Resolver` trait.
+    ///
+    fn solve(&self, ip: &Ip) -> Result<Ip, SolveError> {
+        match self.cache.get(|e| e.ptr.get(&ip.ip)) {
+            Some(Some(names)) => {
+                return Ok(Ip {
+                    names,
+                    error: None,
+                    ..ip.clone()
+                })
+            }
+            Some(None) => return Err(SolveError::NotFound),
+            None => (),
+        }
+
+        let r = self.inner.solve(ip);
+        let names = r.as_ref().map(|ip| ip.names.clone()).map_err(|e| e.clone());
+        if let Some(e) = self.cache.entry(&names) {
+            self.cache.entries.lock().unwrap().ptr.insert(ip.ip, e);
+        }
+        r
+    }
+
+    /// Implement the `Resolver` trait.
+    ///
+    fn forward(&self, name: &str) -> Result<Vec<IpAddr>, SolveError> {
+        let key = name.to_lowercase();
+        match self.cache.get(|e| e.forward.get(&key)) {
+            Some(Some(l)) => return Ok(l),
+            Some(None) => return Err(SolveError::NotFound),
+            None => (),
+        }
+
+        let r = self.inner.forward(name);
+        if let Some(e) = self.cache.entry(&r) {
+            self.cache.entries.lock().unwrap().forward.insert(key, e);
+        }
+        r
+    }
+}
+
+/// Lock file next to the cache, removed when dropped.
+///
+struct Lock(PathBuf);
+
+impl Lock {
+    /// Create the lock file, waiting for other runs to be done.
+    ///
+    fn take(path: &Path) -> Result<Self> {
+        let lock = path.with_extension("lock");
+        let start = SystemTime::now();
+
+        loop {
+            match OpenOptions::new().write(true).create_new(true).open(&lock) {
+                Ok(_) => return Ok(Lock(lock)),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                    let stale = fs::metadata(&lock)
+                        .and_then(|m| m.modified())
+                        .map(|t| t.elapsed().unwrap_or_default() > LOCK_STALE)
+                        .unwrap_or(false);
+                    if stale {
+                        log::warn!("removing stale lock {}", lock.display());
+                        let _ = fs::remove_file(&lock);
+                        continue;
+                    }
+                    if start.elapsed().unwrap_or_default() > LOCK_WAIT {
+                        return Err(anyhow!("{}: locked by another run", lock.display()));
+                    }
+                    sleep(Duration::from_millis(50));
+                }
+                Err(e) => return Err(anyhow!("{}: {}", lock.display(), e)),
+            }
+        }
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Current UNIX timestamp.
+///
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::stubs::Counting;
+    use crate::resolver::{res_init, ResType};
+
+    use std::env::temp_dir;
+
+    fn path(name: &str) -> PathBuf {
+        let p = temp_dir().join(format!("dmarc-cache-{}-{}.json", name, std::process::id()));
+        let _ = fs::remove_file(&p);
+        p
+    }
+
+    #[test]
+    fn test_cache_hits() {
+        let p = path("hits");
+        let cnt = Counting::default();
+        let cache = Cache::open(&p).unwrap();
+        let res = cache.wrap(Solver::new(cnt.clone()));
+
+        for _ in 0..2 {
+            assert_eq!(
+                "mx.example.net",
+                res.solve(&Ip::new("203.0.113.1")).unwrap().name()
+            );
+            assert_eq!(Err(SolveError::NotFound), res.solve(&Ip::new("192.0.2.1")));
+            assert!(res.solve(&Ip::new("198.51.100.1")).is_err());
+            assert_eq!(1, res.forward("MX.example.net").unwrap().len());
+            assert_eq!(1, res.forward("mx.example.net").unwrap().len());
+        }
+        // Temporary failures are tried again
+        assert_eq!(5, cnt.count());
+        assert_eq!(3, cache.len());
+    }
+
+    #[test]
+    fn test_cache_metrics() {
+        let p = path("metrics");
+        let m = Metrics::new();
+        let cache = Cache::open(&p).unwrap().with_metrics(&m);
+        let res = cache.wrap(m.wrap(Solver::new(Counting::default())));
+
+        for _ in 0..2 {
+            let _ = res.solve(&Ip::new("203.0.113.1"));
+            let _ = res.solve(&Ip::new("192.0.2.1"));
+            let _ = res.solve(&Ip::new("198.51.100.1"));
+            let _ = res.forward("MX.example.net");
+            let _ = res.forward("mx.example.net");
+        }
+        let s = m.snapshot();
+        assert_eq!((4, 1, 5), (s.lookups, s.forwards, s.cache_hits));
+        assert_eq!((2, 1, 2), (s.found, s.nxdomain, s.failures));
+    }
+
+    #[test]
+    fn test_cache_save() {
+        let p = path("save");
+        let cache = Cache::open(&p).unwrap();
+        let res = cache.wrap(res_init(ResType::Fake));
+        res.solve(&Ip::new("203.0.113.1")).unwrap();
+        cache.save().unwrap();
+        assert!(!p.with_extension("lock").exists());
+
+        // Next run does not ask
+        let cnt = Counting::default();
+        let cache = Cache::open(&p).unwrap();
+        let res = cache.wrap(Solver::new(cnt.clone()));
+        let ip = res.solve(&Ip::new("203.0.113.1")).unwrap();
+        assert_eq!("some.host.invalid", ip.name());
+        assert_eq!(0, cnt.count());
+
+        // unless asked to
+        let res = cache
+            .clone()
+            .with_bypass(true)
+            .wrap(Solver::new(cnt.clone()));
+        let ip = res.solve(&Ip::new("203.0.113.1")).unwrap();
+        assert_eq!("mx.example.net", ip.name());
+        assert_eq!(1, cnt.count());
+
+        cache.clear().unwrap();
+        assert!(cache.is_empty());
+        assert!(!p.exists());
+        assert!(!p.with_extension("lock").exists());
+        cache.clear().unwrap();
+    }
+
+    #[test]
+    fn test_cache_ttl() {
+        let p = path("ttl");
+        let cnt = Counting::default();
+        let cache = Cache::open(&p).unwrap().with_ttl(DEF_TTL, 0);
+        let res = cache.wrap(Solver::new(cnt.clone()));
+
+        res.solve(&Ip::new("192.0.2.1")).unwrap_err();
+        res.solve(&Ip::new("192.0.2.1")).unwrap_err();
+        assert_eq!(2, cnt.count());
+
+        // Expired entries are not saved
+        cache.save().unwrap();
+        assert!(Cache::open(&p).unwrap().is_empty());
+        cache.clear().unwrap();
+    }
+
+    #[test]
+    fn test_cache_merge() {
+        let p = path("merge");
+        let one = Cache::open(&p).unwrap();
+        let two = Cache::open(&p).unwrap();
+
+        one.wrap(res_init(ResType::Fake))
+            .solve(&Ip::new("203.0.113.1"))
+            .unwrap();
+        two.wrap(res_init(ResType::Fake))
+            .solve(&Ip::new("203.0.113.2"))
+            .unwrap();
+        one.save().unwrap();
+        two.save().unwrap();
+
+        assert_eq!(2, Cache::open(&p).unwrap().len());
+        one.clear().unwrap();
+    }
+
+    #[test]
+    fn test_cache_invalid() {
+        let p = path("invalid");
+        fs::write(&p, "{\"version\": 42}").unwrap();
+        assert!(Cache::open(&p).unwrap().is_empty());
+
+        // Replaced on save
+        fs::write(&p, "garbage").unwrap();
+        let cache = Cache::open(&p).unwrap();
+        assert!(cache.is_empty());
+        cache
+            .wrap(res_init(ResType::Fake))
+            .solve(&Ip::new("203.0.113.1"))
+            .unwrap();
+        cache.save().unwrap();
+        assert_eq!(1, Cache::open(&p).unwrap().len());
+        fs::remove_file(&p).unwrap();
+    }
+}
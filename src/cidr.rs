@@ -0,0 +1,221 @@
+//! Module defining network prefixes in the CIDR notation.
+//!
+//! A `Cidr` is a network address with a prefix length, like `192.0.2.0/24` or `2001:db8::/32`.
+//! The address is always stored masked, i.e. `192.0.2.1/24` is the same as `192.0.2.0/24`.
+//!
+//! Example:
+//! ```
+//! use dmarc_rs::cidr::Cidr;
+//!
+//! let net: Cidr = "192.0.2.0/24".parse().unwrap();
+//!
+//! assert!(net.contains(&"192.0.2.42".parse().unwrap()));
+//! assert_eq!("192.0.2.0/24", net.to_string());
+//! ```
+//!
+
+// Std library
+//
+use std::fmt::{Display, Formatter};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+
+// External crates
+//
+use anyhow::{anyhow, Result};
+
+/// A network prefix.
+///
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Cidr {
+    /// Network address (masked)
+    addr: IpAddr,
+    /// Prefix length
+    len: u8,
+}
+
+impl Cidr {
+    /// Create a new prefix, masking the address with the prefix length.
+    ///
+    /// Returns an error if the length is larger than 32 for IPv4 or 128 for IPv6.
+    ///
+    /// Example:
+    /// ```
+    /// # use dmarc_rs::cidr::Cidr;
+    /// let net = Cidr::new("192.0.2.1".parse().unwrap(), 24).unwrap();
+    ///
+    /// assert_eq!("192.0.2.0/24", net.to_string());
+    /// ```
+    ///
+    pub fn new(addr: IpAddr, len: u8) -> Result<Self> {
+        let addr = match addr {
+            IpAddr::V4(a) => {
+                if len > 32 {
+                    return Err(anyhow!("Invalid IPv4 prefix length {}", len));
+                }
+                let mask = u32::MAX.checked_shl(32 - u32::from(len)).unwrap_or(0);
+                IpAddr::V4(Ipv4Addr::from(u32::from(a) & mask))
+            }
+            IpAddr::V6(a) => {
+                if len > 128 {
+                    return Err(anyhow!("Invalid IPv6 prefix length {}", len));
+                }
+                let mask = u128::MAX.checked_shl(128 - u32::from(len)).unwrap_or(0);
+                IpAddr::V6(Ipv6Addr::from(u128::from(a) & mask))
+            }
+        };
+        Ok(Cidr { addr, len })
+    }
+
+    /// Network address.
+    ///
+    #[inline]
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    /// Prefix length.
+    ///
+    #[inline]
+    pub fn prefix_len(&self) -> u8 {
+        self.len
+    }
+
+    /// Is this a host prefix (/32 or /128)?
+    ///
+    #[inline]
+    pub fn is_host(&self) -> bool {
+        match self.addr {
+            IpAddr::V4(_) => self.len == 32,
+            IpAddr::V6(_) => self.len == 128,
+        }
+    }
+
+    /// Check whether the given address is inside this prefix.
+    ///
+    /// Addresses from the other family are never inside.
+    ///
+    /// Example:
+    /// ```
+    /// # use dmarc_rs::cidr::Cidr;
+    /// let net: Cidr = "2001:db8::/32".parse().unwrap();
+    ///
+    /// assert!(net.contains(&"2001:db8::1".parse().unwrap()));
+    /// assert!(!net.contains(&"192.0.2.1".parse().unwrap()));
+    /// ```
+    ///
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(_), IpAddr::V4(_)) | (IpAddr::V6(_), IpAddr::V6(_)) => {
+                match Cidr::new(*ip, self.len) {
+                    Ok(c) => c.addr == self.addr,
+                    Err(_) => false,
+                }
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Parse `addr/len`, a plain address being a host prefix.
+///
+impl FromStr for Cidr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let (addr, len) = match s.split_once('/') {
+            Some((a, l)) => (a, Some(l)),
+            None => (s, None),
+        };
+        let addr = match addr.parse::<IpAddr>() {
+            Ok(a) => a,
+            Err(e) => return Err(anyhow!("Invalid prefix {}: {}", s, e)),
+        };
+        let len = match len {
+            Some(l) => match l.parse::<u8>() {
+                Ok(l) => l,
+                Err(e) => return Err(anyhow!("Invalid prefix length in {}: {}", s, e)),
+            },
+            None => match addr {
+                IpAddr::V4(_) => 32,
+                IpAddr::V6(_) => 128,
+            },
+        };
+        Cidr::new(addr, len).map_err(|e| anyhow!("{} in {}", e, s))
+    }
+}
+
+impl Display for Cidr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}/{}", self.addr, self.len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+
+    #[rstest]
+    #[case("192.0.2.1/24", "192.0.2.0/24")]
+    #[case("192.0.2.1", "192.0.2.1/32")]
+    #[case("10.1.2.3/8", "10.0.0.0/8")]
+    #[case("10.1.2.3/0", "0.0.0.0/0")]
+    #[case("2001:db8:1:2::1/48", "2001:db8:1::/48")]
+    #[case("2001:db8::1", "2001:db8::1/128")]
+    #[case("::1/0", "::/0")]
+    #[case(" 192.0.2.1/24 ", "192.0.2.0/24")]
+    fn test_parse_ok(#[case] s: &str, #[case] r: &str) {
+        let c: Cidr = s.parse().unwrap();
+        assert_eq!(r, c.to_string());
+    }
+
+    #[rstest]
+    #[case("192.0.2.1/33")]
+    #[case("2001:db8::/129")]
+    #[case("192.0.2.1/")]
+    #[case("192.0.2.1/x")]
+    #[case("foobar/24")]
+    #[case("")]
+    fn test_parse_nok(#[case] s: &str) {
+        assert!(s.parse::<Cidr>().is_err());
+    }
+
+    #[rstest]
+    #[case("192.0.2.0/24", "192.0.2.255", true)]
+    #[case("192.0.2.0/24", "192.0.3.0", false)]
+    #[case("0.0.0.0/0", "1.2.3.4", true)]
+    #[case("0.0.0.0/0", "::1", false)]
+    #[case("2001:db8::/32", "2001:db8:ffff::1", true)]
+    #[case("2001:db8::/32", "2001:db9::1", false)]
+    fn test_contains(#[case] net: &str, #[case] ip: &str, #[case] r: bool) {
+        let net: Cidr = net.parse().unwrap();
+        assert_eq!(r, net.contains(&ip.parse().unwrap()));
+    }
+
+    #[test]
+    fn test_accessors() {
+        let c: Cidr = "192.0.2.7/32".parse().unwrap();
+
+        assert!(c.is_host());
+        assert_eq!(32, c.prefix_len());
+        assert_eq!("192.0.2.7".parse::<IpAddr>().unwrap(), c.addr());
+    }
+
+    #[test]
+    fn test_ord() {
+        let mut l: Vec<Cidr> = ["10.0.0.0/8", "9.0.0.0/8", "2001:db8::/32", "9.0.0.0/16"]
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect();
+        l.sort();
+
+        let r: Vec<String> = l.iter().map(|c| c.to_string()).collect();
+        assert_eq!(
+            vec!["9.0.0.0/8", "9.0.0.0/16", "10.0.0.0/8", "2001:db8::/32"],
+            r
+        );
+    }
+}
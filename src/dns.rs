@@ -0,0 +1,849 @@
+//! Module implementing a small DNS client as a `Resolver`.
+//!
+//! `RealResolver` goes through the system resolver, so which servers are asked, how long we wait
+//! and how many times we try are out of our hands.  `DnsResolver` speaks DNS itself to a list of
+//! nameservers: queries go over UDP and are sent again over TCP when the answer is truncated.
+//!
+//! Each query waits at most `timeout` for an answer.  Servers are tried in order, a timeout, an
+//! error or a SERVFAIL/REFUSED answer moving on to the next one, and the whole list is tried
+//! again `retries` times before giving up with a temporary failure.  NXDOMAIN and empty answers
+//! are final and mean there is no name.
+//!
+//! Only what we need is implemented: PTR queries for `solve()`, A & AAAA ones for `forward()`,
+//! no EDNS and no DNSSEC.  All the PTR records found are returned, in the order of the answer.
+//!
+//! Example:
+//! ```no_run
+//! use dmarc_rs::dns::DnsResolver;
+//! use dmarc_rs::ip::Ip;
+//! use dmarc_rs::resolver::Solver;
+//! use std::time::Duration;
+//!
+//! let ns = "127.0.0.1:5353".parse().unwrap();
+//! let res = Solver::new(DnsResolver::new(&[ns]).with_timeout(Duration::from_millis(500)));
+//!
+//! let ip = res.solve(&Ip::new("1.1.1.1"));
+//! ```
+//!
+
+// Std library
+//
+use std::fmt::{Debug, Formatter};
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, UdpSocket};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Our crates
+//
+use crate::ip::Ip;
+use crate::resolver::{Resolver, SolveError};
+
+// External crates
+//
+use anyhow::{anyhow, Result};
+
+/// Default DNS port
+pub const DEF_PORT: u16 = 53;
+/// Default time to wait for each answer
+pub const DEF_TIMEOUT: Duration = Duration::from_secs(2);
+/// Default number of times the list of servers is tried again
+pub const DEF_RETRIES: u32 = 2;
+
+/// Record types & class we use.
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+
+/// Header flags.
+const FLAG_QR: u16 = 0x8000;
+const FLAG_TC: u16 = 0x0200;
+const FLAG_RD: u16 = 0x0100;
+
+/// Answer codes.
+const RCODE_NOERROR: u16 = 0;
+const RCODE_SERVFAIL: u16 = 2;
+const RCODE_NXDOMAIN: u16 = 3;
+const RCODE_REFUSED: u16 = 5;
+
+/// Largest UDP answer we accept without EDNS is 512 bytes, be lenient.
+const UDP_SIZE: usize = 4096;
+
+/// Query IDs only need to differ between concurrent queries, the answer is also checked against
+/// the question.
+static NEXT_ID: AtomicU16 = AtomicU16::new(0);
+
+/// The data of one answer record we are interested in.
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Data {
+    Name(String),
+    Addr(IpAddr),
+}
+
+/// A decoded answer.
+///
+#[derive(Debug)]
+struct Response {
+    rcode: u16,
+    truncated: bool,
+    answers: Vec<Data>,
+}
+
+/// A resolver talking DNS to its own list of nameservers.
+///
+#[derive(Clone)]
+pub struct DnsResolver {
+    servers: Vec<SocketAddr>,
+    timeout: Duration,
+    retries: u32,
+}
+
+impl DnsResolver {
+    /// Create a resolver asking these servers, in order.
+    ///
+    pub fn new(servers: &[SocketAddr]) -> Self {
+        DnsResolver {
+            servers: servers.to_vec(),
+            timeout: DEF_TIMEOUT,
+            retries: DEF_RETRIES,
+        }
+    }
+
+    /// Set how long we wait for each answer.
+    ///
+    pub fn with_timeout(self, timeout: Duration) -> Self {
+        DnsResolver { timeout, ..self }
+    }
+
+    /// Set how many times the list of servers is tried again.
+    ///
+    pub fn with_retries(self, retries: u32) -> Self {
+        DnsResolver { retries, ..self }
+    }
+
+    /// Ask all the servers in turn until one gives a final answer.
+    ///
+    fn query(&self, name: &str, qtype: u16) -> Result<Vec<Data>, SolveError> {
+        let id = query_id();
+        let q = encode_query(id, name, qtype).ok_or(SolveError::NotFound)?;
+
+        let mut last = SolveError::TempFail("no nameserver".into());
+        for _ in 0..=self.retries {
+            for server in self.servers.iter() {
+                match self.exchange(server, &q, id, qtype) {
+                    Ok(r) => return r,
+                    Err(e) => {
+                        log::debug!("{} {}: {}", server, name, e);
+                        last = e;
+                    }
+                }
+            }
+        }
+        Err(last)
+    }
+
+    /// Send a query to one server, over TCP if the UDP answer is truncated.  `Err` means we should
+    /// try elsewhere.
+    ///
+    fn exchange(
+        &self,
+        server: &SocketAddr,
+        q: &[u8],
+        id: u16,
+        qtype: u16,
+    ) -> Result<Result<Vec<Data>, SolveError>, SolveError> {
+        let buf = udp_exchange(server, q, id, self.timeout).map_err(net_error)?;
+        let mut r = decode_response(&buf, q, qtype).ok_or_else(malformed)?;
+        if r.truncated {
+            let buf = tcp_exchange(server, q, self.timeout).map_err(net_error)?;
+            r = decode_response(&buf, q, qtype).ok_or_else(malformed)?;
+        }
+        outcome(r)
+    }
+}
+
+/// The async versions, used by `crate::asyncres`.
+///
+#[cfg(feature = "async")]
+impl DnsResolver {
+    /// Same as `solve()` without blocking the thread.
+    ///
+    pub async fn solve_async(&self, ip: &Ip) -> Result<Ip, SolveError> {
+        let r = self.query_async(&reverse_name(&ip.ip), TYPE_PTR).await?;
+        Ok(Ip {
+            names: names(r),
+            error: None,
+            ..ip.clone()
+        })
+    }
+
+    /// See `query()`.
+    ///
+    async fn query_async(&self, name: &str, qtype: u16) -> Result<Vec<Data>, SolveError> {
+        let id = query_id();
+        let q = encode_query(id, name, qtype).ok_or(SolveError::NotFound)?;
+
+        let mut last = SolveError::TempFail("no nameserver".into());
+        for _ in 0..=self.retries {
+            for server in self.servers.iter() {
+                match self.exchange_async(server, &q, id, qtype).await {
+                    Ok(r) => return r,
+                    Err(e) => {
+                        log::debug!("{} {}: {}", server, name, e);
+                        last = e;
+                    }
+                }
+            }
+        }
+        Err(last)
+    }
+
+    /// See `exchange()`.
+    ///
+    async fn exchange_async(
+        &self,
+        server: &SocketAddr,
+        q: &[u8],
+        id: u16,
+        qtype: u16,
+    ) -> Result<Result<Vec<Data>, SolveError>, SolveError> {
+        let buf = udp_exchange_async(server, q, id, self.timeout)
+            .await
+            .map_err(net_error)?;
+        let mut r = decode_response(&buf, q, qtype).ok_or_else(malformed)?;
+        if r.truncated {
+            let buf = tcp_exchange_async(server, q, self.timeout)
+                .await
+                .map_err(net_error)?;
+            r = decode_response(&buf, q, qtype).ok_or_else(malformed)?;
+        }
+        outcome(r)
+    }
+}
+
+impl Resolver for DnsResolver {
+    /// Ask for the PTR records of the IP.
+    ///
+    fn solve(&self, ip: &Ip) -> Result<Ip, SolveError> {
+        let r = self.query(&reverse_name(&ip.ip), TYPE_PTR)?;
+        Ok(Ip {
+            names: names(r),
+            error: None,
+            ..ip.clone()
+        })
+    }
+
+    /// Ask for both the A & AAAA records of the name.
+    ///
+    fn forward(&self, name: &str) -> Result<Vec<IpAddr>, SolveError> {
+        let mut l = vec![];
+        let mut err = SolveError::NotFound;
+
+        for qtype in [TYPE_A, TYPE_AAAA] {
+            match self.query(name, qtype) {
+                Ok(r) => l.extend(r.into_iter().filter_map(|d| match d {
+                    Data::Addr(a) => Some(a),
+                    Data::Name(_) => None,
+                })),
+                Err(e @ (SolveError::TempFail(_) | SolveError::Timeout)) => err = e,
+                Err(SolveError::NotFound) => (),
+            }
+        }
+        match l.is_empty() {
+            true => Err(err),
+            false => Ok(l),
+        }
+    }
+}
+
+impl Debug for DnsResolver {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "dnsresolver with {:?}", self.servers)
+    }
+}
+
+/// Parse a nameserver address, with an optional port (`192.0.2.53`, `127.0.0.1:5353`, `::1` or
+/// `[::1]:5353`).
+///
+/// Example:
+/// ```
+/// # use dmarc_rs::dns::parse_nameserver;
+/// assert_eq!("127.0.0.1:53", parse_nameserver("127.0.0.1").unwrap().to_string());
+/// ```
+///
+pub fn parse_nameserver(s: &str) -> Result<SocketAddr> {
+    match s.parse::<SocketAddr>() {
+        Ok(sa) => Ok(sa),
+        Err(_) => match s.parse::<IpAddr>() {
+            Ok(ip) => Ok(SocketAddr::new(ip, DEF_PORT)),
+            Err(_) => Err(anyhow!("invalid nameserver {}", s)),
+        },
+    }
+}
+
+/// What to do with an answer: `Err` means we should try another server.
+///
+fn outcome(r: Response) -> Result<Result<Vec<Data>, SolveError>, SolveError> {
+    match r.rcode {
+        RCODE_NOERROR if r.answers.is_empty() => Ok(Err(SolveError::NotFound)),
+        RCODE_NOERROR => Ok(Ok(r.answers)),
+        RCODE_NXDOMAIN => Ok(Err(SolveError::NotFound)),
+        RCODE_SERVFAIL => Err(SolveError::TempFail("SERVFAIL".into())),
+        RCODE_REFUSED => Err(SolveError::TempFail("REFUSED".into())),
+        n => Err(SolveError::TempFail(format!("rcode {}", n))),
+    }
+}
+
+/// Turn a network error into a failure, telling when the server did not answer in time.
+///
+fn net_error(e: io::Error) -> SolveError {
+    match e.kind() {
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => SolveError::Timeout,
+        _ => SolveError::TempFail(e.to_string()),
+    }
+}
+
+/// Failure for an answer we can not decode.
+///
+fn malformed() -> SolveError {
+    SolveError::TempFail("malformed answer".into())
+}
+
+/// Keep the names of an answer.
+///
+fn names(r: Vec<Data>) -> Vec<String> {
+    r.into_iter()
+        .filter_map(|d| match d {
+            Data::Name(n) => Some(n),
+            Data::Addr(_) => None,
+        })
+        .collect()
+}
+
+/// Local address to send queries to `server` from.
+///
+fn local_addr(server: &SocketAddr) -> SocketAddr {
+    match server {
+        SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    }
+}
+
+/// Get a new query ID.
+///
+fn query_id() -> u16 {
+    let t = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or_default();
+    (t as u16) ^ NEXT_ID.fetch_add(0x9e37, Ordering::Relaxed)
+}
+
+/// Name to ask the PTR records of an IP for (`4.3.2.1.in-addr.arpa` or nibbles in `ip6.arpa`).
+///
+fn reverse_name(ip: &IpAddr) -> String {
+    match ip {
+        IpAddr::V4(_) => format!("{}.in-addr.arpa", reverse_labels(ip)),
+        IpAddr::V6(_) => format!("{}.ip6.arpa", reverse_labels(ip)),
+    }
+}
+
+/// Labels of an IP in reverse order (`4.3.2.1` or nibbles for IPv6), as used by the reverse
+/// zones and DNS blocklists.
+///
+pub(crate) fn reverse_labels(ip: &IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => {
+            let o = ip.octets();
+            format!("{}.{}.{}.{}", o[3], o[2], o[1], o[0])
+        }
+        IpAddr::V6(ip) => {
+            let l: Vec<String> = ip
+                .octets()
+                .iter()
+                .rev()
+                .map(|b| format!("{:x}.{:x}", b & 0xf, b >> 4))
+                .collect();
+            l.join(".")
+        }
+    }
+}
+
+/// Build a recursive query, `None` if the name can not be encoded.
+///
+fn encode_query(id: u16, name: &str, qtype: u16) -> Option<Vec<u8>> {
+    let mut q = Vec::with_capacity(512);
+    for v in [id, FLAG_RD, 1, 0, 0, 0] {
+        q.extend(v.to_be_bytes());
+    }
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return None;
+        }
+        q.push(label.len() as u8);
+        q.extend(label.as_bytes());
+    }
+    q.push(0);
+    if q.len() > 12 + 255 {
+        return None;
+    }
+    q.extend(qtype.to_be_bytes());
+    q.extend(CLASS_IN.to_be_bytes());
+    Some(q)
+}
+
+/// Send the query over UDP and wait for the matching answer.
+///
+fn udp_exchange(server: &SocketAddr, q: &[u8], id: u16, timeout: Duration) -> io::Result<Vec<u8>> {
+    let sock = UdpSocket::bind(local_addr(server))?;
+    sock.connect(server)?;
+    sock.send(q)?;
+
+    let deadline = Instant::now() + timeout;
+    let mut buf = vec![0u8; UDP_SIZE];
+    loop {
+        let left = deadline.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        sock.set_read_timeout(Some(left))?;
+        let n = match sock.recv(&mut buf) {
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                return Err(io::ErrorKind::TimedOut.into())
+            }
+            Err(e) => return Err(e),
+        };
+        // Ignore stray answers
+        if n >= 2 && buf[..2] == id.to_be_bytes() {
+            buf.truncate(n);
+            return Ok(buf);
+        }
+    }
+}
+
+/// Send the query over TCP, each message being prefixed by its length.
+///
+fn tcp_exchange(server: &SocketAddr, q: &[u8], timeout: Duration) -> io::Result<Vec<u8>> {
+    let mut s = TcpStream::connect_timeout(server, timeout)?;
+    s.set_read_timeout(Some(timeout))?;
+    s.set_write_timeout(Some(timeout))?;
+
+    let mut msg = (q.len() as u16).to_be_bytes().to_vec();
+    msg.extend(q);
+    s.write_all(&msg)?;
+
+    let mut len = [0u8; 2];
+    s.read_exact(&mut len)?;
+    let mut buf = vec![0u8; usize::from(u16::from_be_bytes(len))];
+    s.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// See `udp_exchange()`.
+///
+#[cfg(feature = "async")]
+async fn udp_exchange_async(
+    server: &SocketAddr,
+    q: &[u8],
+    id: u16,
+    timeout: Duration,
+) -> io::Result<Vec<u8>> {
+    let sock = tokio::net::UdpSocket::bind(local_addr(server)).await?;
+    sock.connect(server).await?;
+    sock.send(q).await?;
+
+    let recv = async {
+        let mut buf = vec![0u8; UDP_SIZE];
+        loop {
+            let n = sock.recv(&mut buf).await?;
+            // Ignore stray answers
+            if n >= 2 && buf[..2] == id.to_be_bytes() {
+                buf.truncate(n);
+                return Ok(buf);
+            }
+        }
+    };
+    tokio::time::timeout(timeout, recv)
+        .await
+        .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into()))
+}
+
+/// See `tcp_exchange()`.
+///
+#[cfg(feature = "async")]
+async fn tcp_exchange_async(
+    server: &SocketAddr,
+    q: &[u8],
+    timeout: Duration,
+) -> io::Result<Vec<u8>> {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let exchange = async {
+        let mut s = tokio::net::TcpStream::connect(server).await?;
+        let mut msg = (q.len() as u16).to_be_bytes().to_vec();
+        msg.extend(q);
+        s.write_all(&msg).await?;
+
+        let mut len = [0u8; 2];
+        s.read_exact(&mut len).await?;
+        let mut buf = vec![0u8; usize::from(u16::from_be_bytes(len))];
+        s.read_exact(&mut buf).await?;
+        Ok(buf)
+    };
+    tokio::time::timeout(timeout, exchange)
+        .await
+        .unwrap_or_else(|_| Err(io::ErrorKind::TimedOut.into()))
+}
+
+/// Read a big-endian `u16`.
+///
+fn get16(buf: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_be_bytes([*buf.get(pos)?, *buf.get(pos + 1)?]))
+}
+
+/// Read a possibly compressed name, returning it with the position right after it.
+///
+fn read_name(buf: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut labels: Vec<String> = vec![];
+    let mut end = None;
+
+    // Bound the number of pointers to avoid loops
+    for _ in 0..128 {
+        let len = *buf.get(pos)?;
+        match len {
+            0 => {
+                let name = labels.join(".");
+                return Some((name, end.unwrap_or(pos + 1)));
+            }
+            l if l & 0xc0 == 0xc0 => {
+                end.get_or_insert(pos + 2);
+                pos = usize::from(get16(buf, pos)? & 0x3fff);
+            }
+            l if l < 64 => {
+                let l = usize::from(l);
+                let label = buf.get(pos + 1..pos + 1 + l)?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                pos += 1 + l;
+            }
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// Decode an answer to our query `q`, keeping the records of type `qtype`.  `None` if it is
+/// malformed or not for us.
+///
+fn decode_response(buf: &[u8], q: &[u8], qtype: u16) -> Option<Response> {
+    let flags = get16(buf, 2)?;
+    // Same ID and same question
+    if buf.get(..2)? != &q[..2] || flags & FLAG_QR == 0 || buf.get(12..q.len())? != &q[12..] {
+        return None;
+    }
+    let rcode = flags & 0x000f;
+    let truncated = flags & FLAG_TC != 0;
+    let ancount = get16(buf, 6)?;
+
+    let mut answers = vec![];
+    let mut pos = q.len();
+    for _ in 0..ancount {
+        let (_, p) = read_name(buf, pos)?;
+        let rtype = get16(buf, p)?;
+        let class = get16(buf, p + 2)?;
+        let rdlen = usize::from(get16(buf, p + 8)?);
+        let rdata = p + 10;
+        let data = buf.get(rdata..rdata + rdlen)?;
+
+        match (rtype, class) {
+            (t, CLASS_IN) if t != qtype => (),
+            (TYPE_PTR, CLASS_IN) => {
+                answers.push(Data::Name(read_name(buf, rdata)?.0));
+            }
+            (TYPE_A, CLASS_IN) => {
+                let a: [u8; 4] = data.try_into().ok()?;
+                answers.push(Data::Addr(IpAddr::from(a)));
+            }
+            (TYPE_AAAA, CLASS_IN) => {
+                let a: [u8; 16] = data.try_into().ok()?;
+                answers.push(Data::Addr(IpAddr::from(a)));
+            }
+            _ => (),
+        }
+        pos = rdata + rdlen;
+    }
+    Some(Response {
+        rcode,
+        truncated,
+        answers,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::Solver;
+
+    use std::collections::HashMap;
+    use std::net::TcpListener;
+    use std::thread;
+
+    use rstest::rstest;
+
+    /// What the stub server answers for one name.
+    ///
+    #[derive(Clone)]
+    enum Stub {
+        /// These records
+        Records(Vec<Data>),
+        /// Truncated over UDP, these records over TCP
+        Big(Vec<Data>),
+        /// This answer code
+        Rcode(u16),
+        /// Nothing
+        Drop,
+    }
+
+    /// Build the answer of the stub server.
+    ///
+    fn stub_answer(q: &[u8], stub: &Stub, tcp: bool) -> Option<Vec<u8>> {
+        let (_, end) = read_name(q, 12)?;
+        let qtype = get16(q, end)?;
+        let qend = end + 4;
+
+        let (rcode, tc, records) = match stub {
+            Stub::Records(r) => (RCODE_NOERROR, false, r.clone()),
+            Stub::Big(_) if !tcp => (RCODE_NOERROR, true, vec![]),
+            Stub::Big(r) => (RCODE_NOERROR, false, r.clone()),
+            Stub::Rcode(c) => (*c, false, vec![]),
+            Stub::Drop => return None,
+        };
+        let records: Vec<(u16, Vec<u8>)> = records
+            .into_iter()
+            .map(|d| match d {
+                Data::Name(n) => {
+                    let mut rd = vec![];
+                    for l in n.split('.') {
+                        rd.push(l.len() as u8);
+                        rd.extend(l.as_bytes());
+                    }
+                    rd.push(0);
+                    (TYPE_PTR, rd)
+                }
+                Data::Addr(IpAddr::V4(a)) => (TYPE_A, a.octets().to_vec()),
+                Data::Addr(IpAddr::V6(a)) => (TYPE_AAAA, a.octets().to_vec()),
+            })
+            .filter(|(t, _)| *t == qtype)
+            .collect();
+
+        let mut r = q[..2].to_vec();
+        let flags = FLAG_QR | FLAG_RD | 0x0080 | rcode | if tc { FLAG_TC } else { 0 };
+        for v in [flags, 1, records.len() as u16, 0, 0] {
+            r.extend(v.to_be_bytes());
+        }
+        r.extend(&q[12..qend]);
+        for (t, rd) in records {
+            r.extend([0xc0, 12]);
+            for v in [t, CLASS_IN, 0, 3600, rd.len() as u16] {
+                r.extend(v.to_be_bytes());
+            }
+            r.extend(rd);
+        }
+        Some(r)
+    }
+
+    /// Start a stub DNS server on UDP & TCP, answering from `table` (NXDOMAIN for other names).
+    ///
+    fn stub_server(table: HashMap<String, Stub>) -> SocketAddr {
+        let lookup = move |q: &[u8], tcp: bool| {
+            let name = read_name(q, 12)?.0;
+            let stub = table
+                .get(&name)
+                .cloned()
+                .unwrap_or(Stub::Rcode(RCODE_NXDOMAIN));
+            stub_answer(q, &stub, tcp)
+        };
+
+        let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = udp.local_addr().unwrap();
+        let tcp = TcpListener::bind(addr).unwrap();
+
+        let l = lookup.clone();
+        thread::spawn(move || {
+            let mut buf = [0u8; 512];
+            while let Ok((n, from)) = udp.recv_from(&mut buf) {
+                if let Some(r) = l(&buf[..n], false) {
+                    udp.send_to(&r, from).unwrap();
+                }
+            }
+        });
+        thread::spawn(move || {
+            for mut s in tcp.incoming().flatten() {
+                let mut len = [0u8; 2];
+                s.read_exact(&mut len).unwrap();
+                let mut q = vec![0u8; usize::from(u16::from_be_bytes(len))];
+                s.read_exact(&mut q).unwrap();
+                if let Some(r) = lookup(&q, true) {
+                    s.write_all(&(r.len() as u16).to_be_bytes()).unwrap();
+                    s.write_all(&r).unwrap();
+                }
+            }
+        });
+        addr
+    }
+
+    fn name(s: &str) -> Data {
+        Data::Name(s.into())
+    }
+
+    fn addr(s: &str) -> Data {
+        Data::Addr(s.parse().unwrap())
+    }
+
+    fn res(servers: &[SocketAddr]) -> Solver {
+        Solver::new(
+            DnsResolver::new(servers)
+                .with_timeout(Duration::from_millis(200))
+                .with_retries(1),
+        )
+    }
+
+    fn rev(ip: &str) -> String {
+        reverse_name(&ip.parse().unwrap())
+    }
+
+    fn table() -> HashMap<String, Stub> {
+        HashMap::from([
+            (
+                rev("192.0.2.10"),
+                Stub::Records(vec![name("mx1.example.net"), name("www.example.net")]),
+            ),
+            (
+                rev("2001:db8:1:2::5"),
+                Stub::Big(vec![name("mx6.example.net")]),
+            ),
+            (rev("192.0.2.20"), Stub::Rcode(RCODE_SERVFAIL)),
+            (rev("192.0.2.30"), Stub::Drop),
+            (rev("192.0.2.40"), Stub::Records(vec![])),
+            (
+                "mx1.example.net".to_string(),
+                Stub::Records(vec![addr("192.0.2.10"), addr("2001:db8::10")]),
+            ),
+        ])
+    }
+
+    #[rstest]
+    #[case("192.0.2.10", Ok(vec!["mx1.example.net", "www.example.net"]))]
+    #[case("2001:db8:1:2::5", Ok(vec!["mx6.example.net"]))]
+    #[case("192.0.2.1", Err(SolveError::NotFound))]
+    #[case("192.0.2.40", Err(SolveError::NotFound))]
+    #[case("192.0.2.20", Err(SolveError::TempFail("SERVFAIL".into())))]
+    fn test_solve(#[case] ip: &str, #[case] r: Result<Vec<&str>, SolveError>) {
+        let r = r.map(|l| l.into_iter().map(String::from).collect::<Vec<_>>());
+        let res = res(&[stub_server(table())]);
+
+        assert_eq!(r, res.solve(&Ip::new(ip)).map(|ip| ip.names));
+    }
+
+    #[test]
+    fn test_solve_timeout() {
+        let res = res(&[stub_server(table())]);
+
+        let start = Instant::now();
+        assert_eq!(Err(SolveError::Timeout), res.solve(&Ip::new("192.0.2.30")));
+        // Two tries of 200ms
+        assert!(start.elapsed() >= Duration::from_millis(400));
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn test_solve_async() {
+        let res = DnsResolver::new(&[stub_server(table())])
+            .with_timeout(Duration::from_millis(200))
+            .with_retries(0);
+        let rt = tokio::runtime::Runtime::new().unwrap();
+
+        let ip = rt
+            .block_on(res.solve_async(&Ip::new("192.0.2.10")))
+            .unwrap();
+        assert_eq!(vec!["mx1.example.net", "www.example.net"], ip.names);
+        let ip = rt
+            .block_on(res.solve_async(&Ip::new("2001:db8:1:2::5")))
+            .unwrap();
+        assert_eq!("mx6.example.net", ip.name());
+        assert_eq!(
+            Err(SolveError::Timeout),
+            rt.block_on(res.solve_async(&Ip::new("192.0.2.30")))
+        );
+    }
+
+    #[test]
+    fn test_failover() {
+        let bad = stub_server(HashMap::from([(
+            rev("192.0.2.10"),
+            Stub::Rcode(RCODE_REFUSED),
+        )]));
+        let res = res(&[bad, stub_server(table())]);
+
+        let ip = res.solve(&Ip::new("192.0.2.10")).unwrap();
+        assert_eq!("mx1.example.net", ip.name());
+    }
+
+    #[test]
+    fn test_forward() {
+        let res = res(&[stub_server(table())]);
+
+        assert_eq!(
+            Ok(vec![
+                "192.0.2.10".parse::<IpAddr>().unwrap(),
+                "2001:db8::10".parse().unwrap()
+            ]),
+            res.forward("mx1.example.net.")
+        );
+        assert_eq!(Err(SolveError::NotFound), res.forward("www.example.net"));
+        assert_eq!(Err(SolveError::NotFound), res.forward("bad..name"));
+    }
+
+    #[test]
+    fn test_no_server() {
+        let res = res(&[]);
+
+        assert_eq!(
+            Err(SolveError::TempFail("no nameserver".into())),
+            res.solve(&Ip::new("192.0.2.10"))
+        );
+    }
+
+    #[rstest]
+    #[case("192.0.2.10", "10.2.0.192.in-addr.arpa")]
+    #[case(
+        "2001:db8::1",
+        "1.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa"
+    )]
+    fn test_reverse_name(#[case] ip: &str, #[case] r: &str) {
+        assert_eq!(r, reverse_name(&ip.parse().unwrap()));
+    }
+
+    #[rstest]
+    #[case("192.0.2.53", "192.0.2.53:53")]
+    #[case("127.0.0.1:5353", "127.0.0.1:5353")]
+    #[case("::1", "[::1]:53")]
+    #[case("[::1]:5353", "[::1]:5353")]
+    fn test_parse_nameserver(#[case] s: &str, #[case] r: &str) {
+        assert_eq!(r, parse_nameserver(s).unwrap().to_string());
+    }
+
+    #[test]
+    fn test_parse_nameserver_nok() {
+        assert!(parse_nameserver("ns.example.net").is_err());
+        assert!(parse_nameserver("127.0.0.1:99999").is_err());
+    }
+
+    #[test]
+    fn test_read_name_loop() {
+        let buf = [0xc0, 0x00];
+        assert_eq!(None, read_name(&buf, 0));
+    }
+}
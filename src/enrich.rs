@@ -0,0 +1,734 @@
+//! Module implementing the enrichment of source IPs and records.
+//!
+//! Names are not the only thing worth knowing about a source IP: its AS, its country, whether it
+//! is a special-purpose address or whether a blocklist has it are just as useful.  Each of these
+//! is a stage, working on its own and adding its findings to the `Attributes` of the `Ip` (see
+//! `crate::attrs`, re-exported here):
+//!
+//! - `PtrStage`: the names, from any `Solver` (`ptr`),
+//! - `AsnStage`: the AS number and name, from an `AsnDb` (`asn`, `asname`),
+//! - `GeoStage`: the country, from an `AsnDb` (`country`),
+//! - `SpecialStage`: the special-purpose block, see `crate::ip::special()` (`special`),
+//! - `DnsblStage`: the DNS blocklists listing the IP, a basic reputation (`dnsbl`).
+//!
+//! Some things depend on the record and not only on its source IP, like the known sender which
+//! also looks at DKIM signatures: these are `RecordStage` like `SenderStage` (`sender`).
+//!
+//! A `Pipeline` runs its stages in the order they were added, a stage seeing the attributes of
+//! the ones before it, and works on as many IPs in parallel as it has jobs.  The PTR and ASN
+//! stages also fill the `names` and `asn` fields of the `Ip`.  Special-purpose addresses are not
+//! looked up by the PTR and DNSBL stages unless `with_skip_special(false)` is used.
+//!
+//! The same senders show up in report after report, so `dmarc-cat` collects the unique source IPs
+//! of all the reports first (see `crate::iplist::CountedIpList`) and runs the pipeline on them, no
+//! job wasting time on duplicates.
+//!
+//! Example:
+//! ```
+//! use dmarc_rs::enrich::{Pipeline, PtrStage, SpecialStage, Value};
+//! use dmarc_rs::iplist::IpList;
+//! use dmarc_rs::resolver::{res_init, ResType};
+//!
+//! let p = Pipeline::new()
+//!     .with_stage(SpecialStage)
+//!     .with_stage(PtrStage::new(res_init(ResType::Null)))
+//!     .with_jobs(4);
+//!
+//! let l = p.run(&IpList::from(["192.0.2.1", "1.1.1.1"])).unwrap();
+//! assert_eq!(Some(&Value::from("documentation")), l[0].attrs.get("special"));
+//! assert_eq!("1.1.1.1", l[1].attrs.text("ptr"));
+//! ```
+//!
+
+// Std library
+//
+use std::fmt::{Debug, Formatter};
+use std::net::IpAddr;
+use std::panic;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+
+// Our crates
+//
+use crate::asn::{AsnDb, AsnInfo};
+use crate::bulk::{Cancel, Progress};
+use crate::dns::reverse_labels;
+use crate::ip::Ip;
+use crate::iplist::IpList;
+use crate::resolver::{SolveError, Solver};
+use crate::sender::Senders;
+use crate::types::Record;
+
+pub use crate::attrs::{Attributes, Value};
+
+// External crates
+//
+use anyhow::{anyhow, Result};
+
+/// A stage working on each IP.
+///
+pub trait Stage: Send + Sync {
+    /// Name of the stage, for the logs
+    fn name(&self) -> &str;
+    /// Add what this stage knows about the IP to its attributes.  Failures are recorded or
+    /// logged by the stage, they do not stop the pipeline.
+    fn enrich(&self, ip: &mut Ip);
+}
+
+/// A stage working on each record, with its source IP.
+///
+pub trait RecordStage: Send + Sync {
+    /// Name of the stage, for the logs
+    fn name(&self) -> &str;
+    /// Add what this stage knows about the record to `attrs`.
+    fn enrich(&self, ip: &Ip, r: &Record, attrs: &mut Attributes);
+}
+
+/// Ordered list of stages.
+///
+#[derive(Clone)]
+pub struct Pipeline {
+    stages: Vec<Arc<dyn Stage>>,
+    record_stages: Vec<Arc<dyn RecordStage>>,
+    jobs: usize,
+    cancel: Cancel,
+    progress: Option<Progress>,
+}
+
+impl Default for Pipeline {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Pipeline {
+    /// No stage, as many jobs as physical cores.
+    ///
+    pub fn new() -> Self {
+        Pipeline {
+            stages: vec![],
+            record_stages: vec![],
+            jobs: num_cpus::get_physical(),
+            cancel: Cancel::new(),
+            progress: None,
+        }
+    }
+
+    /// Add a stage working on IPs, after the ones already there.
+    ///
+    pub fn with_stage<S: Stage + 'static>(self, stage: S) -> Self {
+        let mut stages = self.stages;
+        stages.push(Arc::new(stage));
+        Pipeline { stages, ..self }
+    }
+
+    /// Add a stage working on records, after the ones already there.
+    ///
+    pub fn with_record_stage<S: RecordStage + 'static>(self, stage: S) -> Self {
+        let mut record_stages = self.record_stages;
+        record_stages.push(Arc::new(stage));
+        Pipeline {
+            record_stages,
+            ..self
+        }
+    }
+
+    /// Set the number of IPs worked on in parallel, 0 being an error when running.
+    ///
+    pub fn with_jobs(self, jobs: usize) -> Self {
+        Pipeline { jobs, ..self }
+    }
+
+    /// Stop when this handle is cancelled, the remaining IPs being returned as they are.
+    ///
+    pub fn with_cancel(self, cancel: Cancel) -> Self {
+        Pipeline { cancel, ..self }
+    }
+
+    /// Call `f` after each IP went through all the stages, from the job working on it.
+    ///
+    pub fn with_progress<F>(self, f: F) -> Self
+    where
+        F: Fn(usize, usize, &Ip) + Send + Sync + 'static,
+    {
+        Pipeline {
+            progress: Some(Arc::new(f)),
+            ..self
+        }
+    }
+
+    /// Names of the stages, IP ones first.
+    ///
+    pub fn names(&self) -> Vec<&str> {
+        self.stages
+            .iter()
+            .map(|s| s.name())
+            .chain(self.record_stages.iter().map(|s| s.name()))
+            .collect()
+    }
+
+    /// Is there no stage at all?
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty() && self.record_stages.is_empty()
+    }
+
+    /// Run all the IP stages on one IP, in order.
+    ///
+    pub fn enrich(&self, ip: &Ip) -> Ip {
+        let mut ip = ip.clone();
+        for s in self.stages.iter() {
+            s.enrich(&mut ip);
+        }
+        ip
+    }
+
+    /// Run all the record stages on one record, returning the attributes of its source IP with
+    /// theirs.
+    ///
+    pub fn enrich_record(&self, ip: &Ip, r: &Record) -> Attributes {
+        let mut attrs = ip.attrs.clone();
+        for s in self.record_stages.iter() {
+            s.enrich(ip, r, &mut attrs);
+        }
+        attrs
+    }
+
+    /// Run the IP stages on a list, in the same order.
+    ///
+    /// IPs are taken from the list in turn by the jobs, each one going through all the stages.  A
+    /// stage panicking in a job makes the whole run panic.
+    ///
+    pub fn run(&self, ipl: &IpList) -> Result<IpList> {
+        if self.jobs == 0 {
+            return Err(anyhow!("At least one job is needed"));
+        }
+        if self.stages.is_empty() || ipl.is_empty() {
+            return Ok(ipl.clone());
+        }
+
+        let (next, count) = (AtomicUsize::new(0), AtomicUsize::new(0));
+        let mut full = ipl.clone();
+        let done: Vec<(usize, Ip)> = thread::scope(|s| {
+            let workers: Vec<_> = (0..self.jobs.min(ipl.len()))
+                .map(|_| {
+                    s.spawn(|| {
+                        let mut done = vec![];
+                        while !self.cancel.is_cancelled() {
+                            let i = next.fetch_add(1, Ordering::Relaxed);
+                            if i >= ipl.len() {
+                                break;
+                            }
+                            let ip = self.enrich(&ipl[i]);
+                            if let Some(f) = &self.progress {
+                                f(count.fetch_add(1, Ordering::Relaxed) + 1, ipl.len(), &ip);
+                            }
+                            done.push((i, ip));
+                        }
+                        done
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .flat_map(|w| w.join().unwrap_or_else(|e| panic::resume_unwind(e)))
+                .collect()
+        });
+        for (i, ip) in done {
+            full[i] = ip;
+        }
+        Ok(full)
+    }
+}
+
+impl Debug for Pipeline {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "pipeline [{}] jobs={} progress={}",
+            self.names().join(","),
+            self.jobs,
+            self.progress.is_some()
+        )
+    }
+}
+
+/// Names of the IP, from a resolver.
+///
+/// Failures are recorded in the `error` field of the `Ip`, temporary ones being logged.
+/// Special-purpose IPs are not looked up unless `with_skip_special(false)` is used, and IPs
+/// already resolved (with names or an error, e.g. by `AsyncSolver`) are not looked up again.
+///
+pub struct PtrStage {
+    res: Solver,
+    skip_special: bool,
+}
+
+impl PtrStage {
+    /// Resolve with this `Solver`.
+    ///
+    pub fn new(res: Solver) -> Self {
+        PtrStage {
+            res,
+            skip_special: true,
+        }
+    }
+
+    /// Whether special-purpose IPs are left alone (the default).
+    ///
+    pub fn with_skip_special(self, skip_special: bool) -> Self {
+        PtrStage {
+            skip_special,
+            ..self
+        }
+    }
+}
+
+impl Stage for PtrStage {
+    fn name(&self) -> &str {
+        "ptr"
+    }
+
+    fn enrich(&self, ip: &mut Ip) {
+        if self.skip_special && ip.special().is_some() {
+            return;
+        }
+        if ip.names.is_empty() && ip.error.is_none() {
+            let attrs = std::mem::take(&mut ip.attrs);
+            *ip = Ip {
+                attrs,
+                ..self.res.solve_or_mark(ip)
+            };
+        }
+        if let Some(e @ (SolveError::TempFail(_) | SolveError::Timeout)) = &ip.error {
+            log::info!("{}: {}", ip.ip, e);
+        }
+        if !ip.names.is_empty() {
+            let names = ip.names.clone();
+            ip.attrs.set("ptr", names);
+        }
+    }
+}
+
+/// AS number and name of the IP.
+///
+pub struct AsnStage {
+    db: Arc<AsnDb>,
+}
+
+impl AsnStage {
+    /// Look the IPs up in these databases.
+    ///
+    pub fn new(db: Arc<AsnDb>) -> Self {
+        AsnStage { db }
+    }
+}
+
+impl Stage for AsnStage {
+    fn name(&self) -> &str {
+        "asn"
+    }
+
+    fn enrich(&self, ip: &mut Ip) {
+        if let Some(a) = asn_info(&self.db, ip) {
+            if a.asn != 0 {
+                ip.attrs.set("asn", a.asn);
+            }
+            if !a.name.is_empty() {
+                ip.attrs.set("asname", a.name.as_str());
+            }
+        }
+    }
+}
+
+/// Country of the IP.
+///
+/// The AS information found by an `AsnStage` before is used, the databases being only asked
+/// when there is none.
+///
+pub struct GeoStage {
+    db: Arc<AsnDb>,
+}
+
+impl GeoStage {
+    /// Look the IPs up in these databases.
+    ///
+    pub fn new(db: Arc<AsnDb>) -> Self {
+        GeoStage { db }
+    }
+}
+
+impl Stage for GeoStage {
+    fn name(&self) -> &str {
+        "geo"
+    }
+
+    fn enrich(&self, ip: &mut Ip) {
+        match asn_info(&self.db, ip) {
+            Some(a) if !a.country.is_empty() => ip.attrs.set("country", a.country),
+            _ => (),
+        }
+    }
+}
+
+/// AS information of the IP, looked up in `db` unless a stage before already did it.
+///
+fn asn_info(db: &AsnDb, ip: &mut Ip) -> Option<AsnInfo> {
+    if ip.asn.is_none() {
+        ip.asn = db.lookup(&ip.ip);
+    }
+    ip.asn.clone()
+}
+
+/// Special-purpose block of the IP, if any.
+///
+pub struct SpecialStage;
+
+impl Stage for SpecialStage {
+    fn name(&self) -> &str {
+        "special"
+    }
+
+    fn enrich(&self, ip: &mut Ip) {
+        if let Some(s) = ip.special() {
+            ip.attrs.set("special", s.to_string());
+        }
+    }
+}
+
+/// DNS blocklists (DNSBL) listing the IP.
+///
+/// The IP is listed by a zone when `<reversed IP>.<zone>` has an address in `127.0.0.0/8`.
+/// Special-purpose IPs are not checked unless `with_skip_special(false)` is used.
+///
+pub struct DnsblStage {
+    res: Solver,
+    zones: Vec<String>,
+    skip_special: bool,
+}
+
+impl DnsblStage {
+    /// Ask these zones (like `zen.spamhaus.org`) with this `Solver`.
+    ///
+    pub fn new(res: Solver, zones: &[String]) -> Self {
+        DnsblStage {
+            res,
+            zones: zones
+                .iter()
+                .map(|z| z.trim_end_matches('.').to_owned())
+                .collect(),
+            skip_special: true,
+        }
+    }
+
+    /// Whether special-purpose IPs are left alone (the default).
+    ///
+    pub fn with_skip_special(self, skip_special: bool) -> Self {
+        DnsblStage {
+            skip_special,
+            ..self
+        }
+    }
+
+    /// Is the IP listed in this zone?
+    ///
+    fn listed(&self, ip: &IpAddr, zone: &str) -> bool {
+        let name = format!("{}.{}", reverse_labels(ip), zone);
+        match self.res.forward(&name) {
+            Ok(l) => l
+                .iter()
+                .any(|a| matches!(a, IpAddr::V4(a) if a.octets()[0] == 127)),
+            Err(SolveError::NotFound) => false,
+            Err(e) => {
+                log::warn!("{}: {}", name, e);
+                false
+            }
+        }
+    }
+}
+
+impl Stage for DnsblStage {
+    fn name(&self) -> &str {
+        "dnsbl"
+    }
+
+    fn enrich(&self, ip: &mut Ip) {
+        if self.skip_special && ip.special().is_some() {
+            return;
+        }
+        let zones: Vec<String> = self
+            .zones
+            .iter()
+            .filter(|z| self.listed(&ip.ip, z))
+            .cloned()
+            .collect();
+        if !zones.is_empty() {
+            ip.attrs.set("dnsbl", zones);
+        }
+    }
+}
+
+/// Known sender of a record, see `crate::sender`.
+///
+pub struct SenderStage {
+    senders: Arc<Senders>,
+}
+
+impl SenderStage {
+    /// Label records with these rules.
+    ///
+    pub fn new(senders: Arc<Senders>) -> Self {
+        SenderStage { senders }
+    }
+}
+
+impl RecordStage for SenderStage {
+    fn name(&self) -> &str {
+        "sender"
+    }
+
+    fn enrich(&self, ip: &Ip, r: &Record, attrs: &mut Attributes) {
+        if let Some(s) = self.senders.classify(ip, r) {
+            attrs.set("sender", s);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::stubs::Counting;
+    use crate::resolver::{res_init, FakeResolver, ResType};
+    use crate::testutil::{load, EXAMPLE};
+    use crate::types::Feedback;
+
+    use std::sync::Mutex;
+
+    use rstest::rstest;
+
+    /// Stage recording the attributes it sees and adding its own.
+    ///
+    struct Spy(&'static str, Arc<Mutex<Vec<usize>>>);
+
+    impl Stage for Spy {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn enrich(&self, ip: &mut Ip) {
+            self.1.lock().unwrap().push(ip.attrs.len());
+            ip.attrs.set(self.0, true);
+        }
+    }
+
+    /// Stage failing on documentation addresses.
+    ///
+    struct Panicking;
+
+    impl Stage for Panicking {
+        fn name(&self) -> &str {
+            "panicking"
+        }
+
+        fn enrich(&self, ip: &mut Ip) {
+            if ip.special().is_some() {
+                panic!("bad stage");
+            }
+        }
+    }
+
+    fn dnsbl() -> Solver {
+        let hosts = "127.0.0.2 4.3.2.1.bl.example 10.2.0.192.bl.example\n\
+                     127.0.0.10 4.3.2.1.pbl.example\n\
+                     192.0.2.99 5.3.2.1.bl.example\n";
+        Solver::new(FakeResolver::from_hosts(hosts).unwrap())
+    }
+
+    #[test]
+    fn test_pipeline_order() {
+        let seen = Arc::new(Mutex::new(vec![]));
+        let p = Pipeline::new()
+            .with_stage(Spy("first", seen.clone()))
+            .with_stage(Spy("second", seen.clone()))
+            .with_jobs(1);
+
+        let ip = p.enrich(&Ip::new("192.0.2.1"));
+        assert_eq!(vec![0, 1], *seen.lock().unwrap());
+        assert_eq!("yes", ip.attrs.text("second"));
+        assert_eq!(vec!["first", "second"], p.names());
+    }
+
+    #[rstest]
+    #[case(1)]
+    #[case(4)]
+    #[case(64)]
+    fn test_pipeline_run(#[case] jobs: usize) {
+        let p = Pipeline::new()
+            .with_stage(SpecialStage)
+            .with_stage(
+                PtrStage::new(Solver::new(FakeResolver::load("testdata/hosts").unwrap()))
+                    .with_skip_special(false),
+            )
+            .with_jobs(jobs);
+        let ipl = IpList::from(["192.0.2.10", "1.1.1.1", "198.51.100.7", "192.0.2.20"]);
+
+        let l = p.run(&ipl).unwrap();
+        assert_eq!(4, l.len());
+        assert_eq!("mx1.example.net", l[0].name());
+        assert_eq!("mx1.example.net", l[0].attrs.text("ptr"));
+        assert_eq!("documentation", l[0].attrs.text("special"));
+        assert_eq!("", l[1].attrs.text("special"));
+        assert_eq!(Some(SolveError::NotFound), l[2].error);
+        assert!(l[2].attrs.get("ptr").is_none());
+        assert_eq!("mx2.example.net", l[3].name());
+    }
+
+    #[rstest]
+    #[case(true, 1)]
+    #[case(false, 2)]
+    fn test_ptr_skip_special(#[case] skip: bool, #[case] queries: usize) {
+        let count = Arc::new(AtomicUsize::new(0));
+        let st = PtrStage::new(Solver::new(Counting(count.clone()))).with_skip_special(skip);
+        let p = Pipeline::new().with_stage(st).with_jobs(1);
+
+        let l = p.run(&IpList::from(["10.0.0.1", "1.1.1.1"])).unwrap();
+        assert_eq!(queries, count.load(Ordering::Relaxed));
+        assert_eq!("mx.example.net", l[1].name());
+        assert_eq!(!skip, l[0].attrs.get("ptr").is_some());
+    }
+
+    #[test]
+    fn test_pipeline_progress() {
+        let seen = Arc::new(Mutex::new(vec![]));
+        let s = seen.clone();
+        let p = Pipeline::new()
+            .with_stage(SpecialStage)
+            .with_jobs(2)
+            .with_progress(move |done, total, _ip| s.lock().unwrap().push((done, total)));
+
+        let _ = p
+            .run(&IpList::from(["1.1.1.1", "192.0.2.1", "1.0.0.1"]))
+            .unwrap();
+        let mut seen = seen.lock().unwrap().clone();
+        seen.sort();
+        assert_eq!(vec![(1, 3), (2, 3), (3, 3)], seen);
+    }
+
+    #[test]
+    #[should_panic(expected = "bad stage")]
+    fn test_pipeline_panic() {
+        let p = Pipeline::new().with_stage(Panicking).with_jobs(2);
+
+        let _ = p.run(&IpList::from(["1.1.1.1", "192.0.2.1", "1.0.0.1"]));
+    }
+
+    #[test]
+    fn test_pipeline_nok() {
+        let ipl = IpList::from(["192.0.2.1"]);
+
+        assert!(Pipeline::new().with_jobs(0).run(&ipl).is_err());
+        assert_eq!(ipl, Pipeline::new().run(&ipl).unwrap());
+        assert!(Pipeline::new()
+            .with_stage(SpecialStage)
+            .run(&IpList::new())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_pipeline_cancel() {
+        let cancel = Cancel::new();
+        cancel.cancel();
+        let p = Pipeline::new().with_stage(SpecialStage).with_cancel(cancel);
+
+        let l = p.run(&IpList::from(["192.0.2.1"])).unwrap();
+        assert!(l[0].attrs.is_empty());
+    }
+
+    #[test]
+    fn test_asn_geo() {
+        let db = Arc::new(AsnDb::open("testdata/ip2asn-test.tsv").unwrap());
+        let p = Pipeline::new()
+            .with_stage(AsnStage::new(db.clone()))
+            .with_stage(GeoStage::new(db));
+
+        let ip = p.enrich(&Ip::new("192.0.2.10"));
+        assert_eq!(64496, ip.asn.unwrap().asn);
+        assert_eq!("64496", ip.attrs.text("asn"));
+        assert!(!ip.attrs.text("asname").is_empty());
+        assert!(!ip.attrs.text("country").is_empty());
+
+        assert!(p.enrich(&Ip::new("203.0.113.1")).attrs.is_empty());
+    }
+
+    #[test]
+    fn test_geo_reuse() {
+        // Nothing in the database, what the AsnStage found is used
+        let p = Pipeline::new().with_stage(GeoStage::new(Arc::new(AsnDb::new())));
+        let mut ip = Ip::new("192.0.2.10");
+        ip.asn = Some(AsnInfo {
+            asn: 64496,
+            name: "EXAMPLE-AS-1".into(),
+            country: "FR".into(),
+        });
+
+        assert_eq!("FR", p.enrich(&ip).attrs.text("country"));
+    }
+
+    #[test]
+    fn test_dnsbl() {
+        let zones = vec!["bl.example".to_string(), "pbl.example.".to_string()];
+        let s = DnsblStage::new(dnsbl(), &zones);
+        let p = Pipeline::new().with_stage(s);
+
+        let ip = p.enrich(&Ip::new("1.2.3.4"));
+        assert_eq!(
+            Some(&Value::List(vec![
+                "bl.example".into(),
+                "pbl.example".into()
+            ])),
+            ip.attrs.get("dnsbl")
+        );
+        // Not in 127.0.0.0/8
+        assert!(p.enrich(&Ip::new("1.2.3.5")).attrs.is_empty());
+        assert!(p.enrich(&Ip::new("1.2.3.6")).attrs.is_empty());
+
+        // Special-purpose IPs are skipped by default
+        assert!(p.enrich(&Ip::new("192.0.2.10")).attrs.is_empty());
+        let p =
+            Pipeline::new().with_stage(DnsblStage::new(dnsbl(), &zones).with_skip_special(false));
+        assert_eq!(
+            "bl.example",
+            p.enrich(&Ip::new("192.0.2.10")).attrs.text("dnsbl")
+        );
+    }
+
+    #[test]
+    fn test_dnsbl_null() {
+        let s = DnsblStage::new(res_init(ResType::Null), &["bl.example".into()]);
+        let mut ip = Ip::new("1.2.3.4");
+
+        s.enrich(&mut ip);
+        assert!(ip.attrs.is_empty());
+    }
+
+    #[test]
+    fn test_sender() {
+        let fb: Feedback = load(EXAMPLE);
+        let senders: Senders = "[[sender]]\nname = \"Relays\"\ncidr = [\"192.0.2.0/24\"]\n"
+            .parse()
+            .unwrap();
+        let p = Pipeline::new().with_record_stage(SenderStage::new(Arc::new(senders)));
+
+        let mut ip = Ip::new("192.0.2.10");
+        ip.attrs.set("asn", 64496u32);
+        let a = p.enrich_record(&ip, &fb[0].record[0]);
+        assert_eq!("Relays", a.text("sender"));
+        assert_eq!("64496", a.text("asn"));
+        assert!(p
+            .enrich_record(&Ip::new("198.51.100.7"), &fb[0].record[1])
+            .get("sender")
+            .is_none());
+    }
+}
@@ -59,7 +59,7 @@ pub enum Input {
 /// ```
 ///
 pub fn valid_input(itype: &str) -> Result<Input> {
-    return match itype.to_lowercase().as_str() {
+    match itype.to_lowercase().as_str() {
         "csv" => Ok(Input::Csv),
         "plain" => Ok(Input::Plain),
         "txt" => Ok(Input::Plain),
@@ -68,7 +68,7 @@ pub fn valid_input(itype: &str) -> Result<Input> {
         "xml" => Ok(Input::Xml),
         "zip" => Ok(Input::Zip),
         _ => Err(anyhow!("Invalid type")),
-    };
+    }
 }
 
 /// Matches a filename to a given input type based on the extension.
@@ -110,7 +110,7 @@ mod tests {
     #[case("foo.CSV", Input::Csv)]
     #[case(".CSV", Input::Plain)]
     fn test_ext_to_ftype(#[case] f: PathBuf, #[case] t: Input) {
-        let p = PathBuf::from(f);
+        let p = f;
         assert_eq!(t, ext_to_ftype(&p))
     }
 
@@ -1,44 +1,284 @@
 //! Helper module to deal with tuples of IP/names.
 //!
-//! We define IP as a tuple containing the `IpAddr` and the name (initially empty of course).
+//! We define IP as a tuple containing the `IpAddr` and its names (initially empty of course), as
+//! there can be several PTR records for one IP.  The first name is the primary one, returned by
+//! `name()`.  It can also carry the ASN and country found in a local database (see `crate::asn`).
 //! To facilitate manipulations, we also define `from` to magically convert tuples of strings
 //! into an `IP`.
 //!
+//! `Ip::new()` and the conversions from tuples panic on an invalid address and are meant for
+//! literals, the address being taken as is.  Untrusted input goes through `FromStr` or
+//! `TryFrom<&str>` instead, which return an `IpError`.  Surrounding whitespace is ignored,
+//! IPv4-mapped IPv6 addresses become plain IPv4 ones and zone IDs like `fe80::1%eth0` are rejected
+//! as they mean nothing outside of one host.
+//!
+//! With serde, an `Ip` is only its address, names and attributes, like
+//! `{"ip":"192.0.2.1","names":["mx1.example.net"]}`; a plain string is also accepted when reading.
+//!
+//! Addresses from the IANA special-purpose registries (private, loopback, documentation, CGNAT,
+//! IPv4-mapped, etc.) have no business being the source of real mail; `special()` tells which
+//! block an address belongs to, if any, so that they are not looked up and can be flagged.
+//!
 //! Example:
 //! ```
 //! use dmarc_rs::ip::Ip;
 //!
 //! let me = Ip::new("127.0.0.1");
+//! assert!(me.special().is_some());
 //! ```
 //! or
 //! ```
 //! use dmarc_rs::ip::Ip;
 //!
-//! let me = Ip::from(("::1", "localhost"));
+//! let me = Ip::try_from(("::1", "localhost")).unwrap();
+//! ```
+//! or
+//! ```
+//! use dmarc_rs::ip::Ip;
+//!
+//! let ip: Ip = " ::ffff:192.0.2.1 ".parse().unwrap();
+//! assert_eq!("192.0.2.1", ip.ip.to_string());
+//! assert!("fe80::1%eth0".parse::<Ip>().is_err());
 //! ```
 //!
 
 // Std library
 //
-use std::net::IpAddr;
+use std::fmt::{Display, Formatter};
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+// Our crates
+//
+use crate::asn::AsnInfo;
+use crate::attrs::Attributes;
+use crate::cidr::Cidr;
+use crate::resolver::{ptr_answer, SolveError};
 
 // External crates
 //
-use dns_lookup::lookup_addr;
+use dns_lookup::getnameinfo;
+use serde::{Deserialize, Serialize};
+
+/// Special-purpose address blocks, from the IANA IPv4 & IPv6 special-purpose address registries
+/// (plus multicast).
+///
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum Special {
+    /// 0.0.0.0/8, "this network"
+    ThisNetwork,
+    /// RFC 1918 private networks
+    Private,
+    /// 100.64.0.0/10, shared address space used by carrier-grade NAT
+    Cgnat,
+    /// 127.0.0.0/8 & ::1
+    Loopback,
+    /// 169.254.0.0/16 & fe80::/10
+    LinkLocal,
+    /// 192.0.0.0/24 & 2001::/23 IETF protocol assignments (not globally reachable, except for
+    /// the anycast services, Teredo, AMT, AS112, ORCHIDv2 & drone identifiers)
+    Protocol,
+    /// 192.0.2.0/24, 198.51.100.0/24, 203.0.113.0/24, 2001:db8::/32 & 3fff::/20
+    Documentation,
+    /// 198.18.0.0/15 & 2001:2::/48
+    Benchmarking,
+    /// 240.0.0.0/4 and the 6to4 relay anycast block
+    Reserved,
+    /// 255.255.255.255
+    Broadcast,
+    /// 224.0.0.0/4 & ff00::/8
+    Multicast,
+    /// ::
+    Unspecified,
+    /// ::ffff:0:0/96, an IPv4 address in IPv6 clothing
+    Ipv4Mapped,
+    /// 64:ff9b:1::/48 local-use IPv4/IPv6 translation
+    Translation,
+    /// 100::/64 discard-only
+    Discard,
+    /// fc00::/7 unique local addresses
+    UniqueLocal,
+}
+
+impl Display for Special {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Special::ThisNetwork => "this network",
+            Special::Private => "private",
+            Special::Cgnat => "CGNAT",
+            Special::Loopback => "loopback",
+            Special::LinkLocal => "link-local",
+            Special::Protocol => "IETF protocol",
+            Special::Documentation => "documentation",
+            Special::Benchmarking => "benchmarking",
+            Special::Reserved => "reserved",
+            Special::Broadcast => "broadcast",
+            Special::Multicast => "multicast",
+            Special::Unspecified => "unspecified",
+            Special::Ipv4Mapped => "IPv4-mapped",
+            Special::Translation => "translation",
+            Special::Discard => "discard",
+            Special::UniqueLocal => "unique local",
+        };
+        f.write_str(s)
+    }
+}
+
+/// The special-purpose blocks, most specific first where they overlap.
+///
+const SPECIAL: [(&str, Special); 28] = [
+    ("0.0.0.0/8", Special::ThisNetwork),
+    ("10.0.0.0/8", Special::Private),
+    ("100.64.0.0/10", Special::Cgnat),
+    ("127.0.0.0/8", Special::Loopback),
+    ("169.254.0.0/16", Special::LinkLocal),
+    ("172.16.0.0/12", Special::Private),
+    ("192.0.0.0/24", Special::Protocol),
+    ("192.0.2.0/24", Special::Documentation),
+    ("192.88.99.0/24", Special::Reserved),
+    ("192.168.0.0/16", Special::Private),
+    ("198.18.0.0/15", Special::Benchmarking),
+    ("198.51.100.0/24", Special::Documentation),
+    ("203.0.113.0/24", Special::Documentation),
+    ("224.0.0.0/4", Special::Multicast),
+    ("255.255.255.255/32", Special::Broadcast),
+    ("240.0.0.0/4", Special::Reserved),
+    ("::/128", Special::Unspecified),
+    ("::1/128", Special::Loopback),
+    ("::ffff:0:0/96", Special::Ipv4Mapped),
+    ("64:ff9b:1::/48", Special::Translation),
+    ("100::/64", Special::Discard),
+    ("2001:2::/48", Special::Benchmarking),
+    ("2001::/23", Special::Protocol),
+    ("2001:db8::/32", Special::Documentation),
+    ("3fff::/20", Special::Documentation),
+    ("fc00::/7", Special::UniqueLocal),
+    ("fe80::/10", Special::LinkLocal),
+    ("ff00::/8", Special::Multicast),
+];
+
+/// Globally reachable addresses inside the special-purpose blocks: Port Control Protocol, TURN
+/// and DNS-SD anycast, Teredo, AMT, AS112, ORCHIDv2 and drone identifiers.
+///
+const GLOBAL: [&str; 10] = [
+    "192.0.0.9/32",
+    "192.0.0.10/32",
+    "2001::/32",
+    "2001:1::1/128",
+    "2001:1::2/128",
+    "2001:1::3/128",
+    "2001:3::/32",
+    "2001:4:112::/48",
+    "2001:20::/28",
+    "2001:30::/28",
+];
+
+/// Find the special-purpose block of an address, if any.
+///
+/// Only the parts of 2001::/23 and 192.0.0.0/24 which are not globally reachable are flagged,
+/// Teredo & co. are regular addresses.
+///
+/// Example:
+/// ```
+/// # use dmarc_rs::ip::{special, Special};
+/// assert_eq!(Some(Special::Cgnat), special(&"100.64.1.2".parse().unwrap()));
+/// assert_eq!(None, special(&"1.1.1.1".parse().unwrap()));
+/// ```
+///
+pub fn special(ip: &IpAddr) -> Option<Special> {
+    static BLOCKS: OnceLock<Vec<(Cidr, Option<Special>)>> = OnceLock::new();
+
+    let blocks = BLOCKS.get_or_init(|| {
+        GLOBAL
+            .iter()
+            .map(|c| (c.parse::<Cidr>().unwrap(), None))
+            .chain(
+                SPECIAL
+                    .iter()
+                    .map(|(c, s)| (c.parse::<Cidr>().unwrap(), Some(*s))),
+            )
+            .collect()
+    });
+    blocks
+        .iter()
+        .find(|(c, _)| c.contains(ip))
+        .and_then(|(_, s)| *s)
+}
+
+/// Why a string is not a valid IP address.
+///
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum IpError {
+    /// Nothing but whitespace
+    Empty,
+    /// IPv6 address with a zone ID, only meaningful on one host
+    ZoneId(String),
+    /// Neither an IPv4 nor an IPv6 address
+    Invalid(String),
+}
+
+impl Display for IpError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IpError::Empty => write!(f, "empty IP address"),
+            IpError::ZoneId(s) => write!(f, "zone ID not allowed in {}", s),
+            IpError::Invalid(s) => write!(f, "invalid IP address {}", s),
+        }
+    }
+}
+
+impl std::error::Error for IpError {}
+
+/// Parse an address from untrusted input, see the module documentation for the normalization.
+///
+/// Example:
+/// ```
+/// # use dmarc_rs::ip::{parse_ip, IpError};
+/// assert_eq!("192.0.2.1", parse_ip("::ffff:192.0.2.1\n").unwrap().to_string());
+/// assert_eq!(Err(IpError::ZoneId("fe80::1%1".into())), parse_ip("fe80::1%1"));
+/// ```
+///
+pub fn parse_ip(s: &str) -> Result<IpAddr, IpError> {
+    let s = s.trim();
+    if s.is_empty() {
+        return Err(IpError::Empty);
+    }
+    match s.parse::<IpAddr>() {
+        Ok(ip) => Ok(ip.to_canonical()),
+        Err(_) if s.contains('%') => Err(IpError::ZoneId(s.into())),
+        Err(_) => Err(IpError::Invalid(s.into())),
+    }
+}
 
 /// Individual IP/name tuple
-#[derive(Clone, Debug, Eq, Ord, PartialOrd, PartialEq)]
+///
+/// More fields may be added, so outside of this crate an `Ip` is built from an address with
+/// `Ip::from()` or parsed, then filled.
+///
+#[derive(Clone, Debug, Deserialize, Eq, Ord, PartialOrd, PartialEq, Serialize)]
+#[serde(try_from = "IpRepr", into = "IpRepr")]
+#[non_exhaustive]
 pub struct Ip {
     /// IP, can be IPv4 or IPv6
     pub ip: IpAddr,
-    /// hostname.
-    pub name: String,
+    /// hostnames, primary one first.
+    pub names: Vec<String>,
+    /// ASN & country, if known.
+    pub asn: Option<AsnInfo>,
+    /// Why the name could not be resolved, if it failed.
+    pub error: Option<SolveError>,
+    /// Whether one of the names resolves back to the IP (FCrDNS), if checked.
+    pub verified: Option<bool>,
+    /// Everything else found about the IP, see `crate::attrs`.
+    pub attrs: Attributes,
 }
 
 impl Ip {
     /// Create a new tuple with empty name.
     ///
-    /// `new()` will panic with an invalid IPv{4,6} address
+    /// `new()` will panic with an invalid IPv{4,6} address and does not normalize it, use
+    /// `parse()` for untrusted input.
     ///
     /// Example:
     /// ```rust
@@ -47,66 +287,237 @@ impl Ip {
     /// ```
     ///
     pub fn new(s: &str) -> Self {
-        Ip {
-            ip: s.parse::<IpAddr>().unwrap(),
-            name: "".into(),
-        }
+        Ip::from(s.parse::<IpAddr>().unwrap())
     }
 
-    /// Get the PTR value for the given IP
+    /// Primary name, empty if there is none.
+    ///
+    /// Example:
+    /// ```rust
+    /// # use dmarc_rs::ip::Ip;
+    /// let ip = Ip::try_from(("1.1.1.1", "one.one.one.one")).unwrap();
+    /// assert_eq!("one.one.one.one", ip.name());
+    /// ```
+    ///
+    #[inline]
+    pub fn name(&self) -> &str {
+        self.names.first().map(|n| n.as_str()).unwrap_or_default()
+    }
+
+    /// Special-purpose block of this IP, if any (see `special()`).
+    ///
+    /// Example:
+    /// ```rust
+    /// # use dmarc_rs::ip::{Ip, Special};
+    /// assert_eq!(Some(Special::Private), Ip::new("192.168.1.1").special());
+    /// assert_eq!(None, Ip::new("1.1.1.1").special());
+    /// ```
+    ///
+    #[inline]
+    pub fn special(&self) -> Option<Special> {
+        special(&self.ip)
+    }
+
+    /// Get the network this IP belongs to, with different prefix lengths for IPv4 and IPv6.
+    ///
+    /// Lengths are capped to the size of the address.
+    ///
+    /// Example:
+    /// ```rust
+    /// # use dmarc_rs::ip::Ip;
+    /// let net = Ip::new("192.0.2.42").network(24, 48);
+    ///
+    /// assert_eq!("192.0.2.0/24", net.to_string());
+    /// ```
+    ///
+    pub fn network(&self, v4: u8, v6: u8) -> Cidr {
+        let len = match self.ip {
+            IpAddr::V4(_) => v4.min(32),
+            IpAddr::V6(_) => v6.min(128),
+        };
+        // Can not fail as the length is always valid
+        Cidr::new(self.ip, len).unwrap()
+    }
+
+    /// Get the PTR value for the given IP with the system resolver.
+    ///
+    /// The system resolver only gives us one name, even if there are several PTR records.
     ///
     /// Examples:
     /// ```rust,no_run
     /// # use dmarc_rs::ip::Ip;
-    /// let ptr = Ip::new("1.1.1.1").solve();
-    /// assert_eq!("one.one.one.one", ptr.name)
+    /// let ptr = Ip::new("1.1.1.1").solve().unwrap();
+    /// assert_eq!("one.one.one.one", ptr.name())
     /// # ;
     /// ```
     ///
-    /// If there is no PTR, returns a specific valid hostname.
+    /// If there is no PTR or the lookup failed, returns why.
     ///
     /// Example:
     /// ```rust,no_run
     /// # use dmarc_rs::ip::Ip;
+    /// # use dmarc_rs::resolver::SolveError;
     /// let ptr = Ip::new("192.0.2.1").solve();
-    /// assert_eq!("some.host.invalid", ptr.name)
+    /// assert_eq!(Err(SolveError::NotFound), ptr)
     /// # ;
     /// ```
     ///
-    pub fn solve(&self) -> Self {
-        let ip = self.ip;
-        let name = match lookup_addr(&ip) {
-            Ok(nm) => {
-                // No PTR, force one
-                if ip.to_string() == nm {
-                    "some.host.invalid".into()
-                } else {
-                    nm
-                }
-            }
-            Err(e) => e.to_string(),
-        };
-        Ip { ip, name }
+    pub fn solve(&self) -> Result<Self, SolveError> {
+        let sock = SocketAddr::new(self.ip, 0);
+        let name = ptr_answer(&self.ip, getnameinfo(&sock, 0).map(|(host, _)| host))?;
+        Ok(Ip {
+            names: vec![name],
+            error: None,
+            verified: None,
+            ..self.clone()
+        })
     }
 }
 
-/// Create a new IP from a tuple with all fields
+/// Create a new IP without name from an address.
 ///
 /// Example:
 /// ```
 /// # use dmarc_rs::ip::Ip;
-/// let t = Ip::from(("1.1.1.1", "one.one.one.one"));
+/// let ip = Ip::from("192.0.2.1".parse::<std::net::IpAddr>().unwrap());
+/// assert!(ip.names.is_empty());
 /// ```
 ///
-impl From<(&str, &str)> for Ip {
-    fn from((ip, name): (&str, &str)) -> Self {
+impl From<IpAddr> for Ip {
+    fn from(ip: IpAddr) -> Self {
         Ip {
-            ip: ip.parse::<IpAddr>().unwrap(),
-            name: name.into(),
+            ip,
+            names: vec![],
+            asn: None,
+            error: None,
+            verified: None,
+            attrs: Attributes::new(),
         }
     }
 }
 
+/// Parse an IP without name, see `parse_ip()`.
+///
+/// Example:
+/// ```
+/// # use dmarc_rs::ip::Ip;
+/// let ip: Ip = "2001:db8::1".parse().unwrap();
+/// assert!("192.0.2.256".parse::<Ip>().is_err());
+/// ```
+///
+impl FromStr for Ip {
+    type Err = IpError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_ip(s).map(Ip::from)
+    }
+}
+
+/// Same as `FromStr`.
+///
+/// Example:
+/// ```
+/// # use dmarc_rs::ip::Ip;
+/// let ip = Ip::try_from("192.0.2.1").unwrap();
+/// # assert!(ip.names.is_empty());
+/// ```
+///
+impl TryFrom<&str> for Ip {
+    type Error = IpError;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Serialized form of an `Ip`.
+///
+#[derive(Deserialize, Serialize)]
+#[serde(untagged)]
+enum IpRepr {
+    /// Only the address
+    Addr(String),
+    /// Address and names
+    Full {
+        ip: String,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        names: Vec<String>,
+        #[serde(default, skip_serializing_if = "Attributes::is_empty")]
+        attrs: Attributes,
+    },
+}
+
+impl TryFrom<IpRepr> for Ip {
+    type Error = IpError;
+
+    fn try_from(r: IpRepr) -> Result<Self, Self::Error> {
+        match r {
+            IpRepr::Addr(ip) => ip.parse(),
+            IpRepr::Full { ip, names, attrs } => Ok(Ip {
+                names: names.into_iter().filter(|n| !n.is_empty()).collect(),
+                attrs,
+                ..ip.parse()?
+            }),
+        }
+    }
+}
+
+impl From<Ip> for IpRepr {
+    fn from(ip: Ip) -> Self {
+        IpRepr::Full {
+            ip: ip.ip.to_string(),
+            names: ip.names,
+            attrs: ip.attrs,
+        }
+    }
+}
+
+/// Create a new IP from a tuple with all fields, an empty name meaning no name.
+///
+/// Fails with an invalid address, see `parse_ip()`.
+///
+/// Example:
+/// ```
+/// # use dmarc_rs::ip::{Ip, IpError};
+/// let t = Ip::try_from(("1.1.1.1", "one.one.one.one")).unwrap();
+/// assert_eq!("one.one.one.one", t.name());
+/// assert_eq!(Err(IpError::Invalid("foo".into())), Ip::try_from(("foo", "bar")));
+/// ```
+///
+impl TryFrom<(&str, &str)> for Ip {
+    type Error = IpError;
+
+    fn try_from((ip, name): (&str, &str)) -> Result<Self, Self::Error> {
+        Ip::try_from((ip, [name].as_slice()))
+    }
+}
+
+/// Create a new IP with several names, the primary one first.
+///
+/// Fails with an invalid address, see `parse_ip()`.
+///
+/// Example:
+/// ```
+/// # use dmarc_rs::ip::Ip;
+/// let t = Ip::try_from(("192.0.2.1", ["mx1.example.net", "www.example.net"].as_slice())).unwrap();
+/// assert_eq!("mx1.example.net", t.name());
+/// ```
+///
+impl TryFrom<(&str, &[&str])> for Ip {
+    type Error = IpError;
+
+    fn try_from((ip, names): (&str, &[&str])) -> Result<Self, Self::Error> {
+        Ok(Ip {
+            names: names
+                .iter()
+                .filter(|n| !n.is_empty())
+                .map(|n| n.to_string())
+                .collect(),
+            ..parse_ip(ip).map(Ip::from)?
+        })
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -125,7 +536,7 @@ mod tests {
     #[case("3ffe::a:b:c:d:e")]
     fn test_ip_new_ok(#[case] s: &str) {
         let a1 = Ip::new(s);
-        assert!(a1.name.is_empty());
+        assert!(a1.names.is_empty());
         assert_eq!(s.parse::<IpAddr>().unwrap(), a1.ip)
     }
 
@@ -136,40 +547,224 @@ mod tests {
     #[case("10.0.0.555")]
     #[case("foobar")]
     #[case("::blah:blah::.168.1.1")]
+    #[case(" 1.1.1.1")]
+    #[case("fe80::1%1")]
     #[should_panic]
     fn test_ip_new_nok(#[case] s: &str) {
         let _a1 = Ip::new(s);
     }
 
     #[rstest]
-    #[case("1.1.1.1", "one.one.one.one")]
-    #[case("2606:4700:4700::1111", "one.one.one.one")]
-    #[case("192.0.2.1", "some.host.invalid")]
-    fn test_ip_solve(#[case] s: &str, #[case] p: &str) {
+    #[case("192.0.2.1", Ok("192.0.2.1"))]
+    #[case(" 2001:db8::1\t", Ok("2001:db8::1"))]
+    #[case("::ffff:192.0.2.1", Ok("192.0.2.1"))]
+    #[case("::FFFF:c000:201", Ok("192.0.2.1"))]
+    #[case("2001:DB8::1", Ok("2001:db8::1"))]
+    #[case("", Err(IpError::Empty))]
+    #[case(" \n", Err(IpError::Empty))]
+    #[case("fe80::1%eth0", Err(IpError::ZoneId("fe80::1%eth0".into())))]
+    #[case(" fe80::1%2 ", Err(IpError::ZoneId("fe80::1%2".into())))]
+    #[case("192.0.2.1%1", Err(IpError::ZoneId("192.0.2.1%1".into())))]
+    #[case("192.0.2.256", Err(IpError::Invalid("192.0.2.256".into())))]
+    #[case("192.0.2.1/24", Err(IpError::Invalid("192.0.2.1/24".into())))]
+    #[case("foo", Err(IpError::Invalid("foo".into())))]
+    fn test_parse_ip(#[case] s: &str, #[case] r: Result<&str, IpError>) {
+        assert_eq!(
+            r,
+            parse_ip(s)
+                .map(|ip| ip.to_string())
+                .as_deref()
+                .map_err(|e| e.clone())
+        );
+        assert_eq!(
+            r,
+            s.parse::<Ip>()
+                .map(|ip| ip.ip.to_string())
+                .as_deref()
+                .map_err(|e| e.clone())
+        );
+        assert_eq!(r.is_ok(), Ip::try_from(s).is_ok());
+    }
+
+    #[test]
+    fn test_ip_error_display() {
+        assert_eq!(
+            "zone ID not allowed in fe80::1%eth0",
+            IpError::ZoneId("fe80::1%eth0".into()).to_string()
+        );
+        assert_eq!(
+            "invalid IP address foo",
+            IpError::Invalid("foo".into()).to_string()
+        );
+    }
+
+    #[rstest]
+    #[case("1.1.1.1", Ok("one.one.one.one"))]
+    #[case("2606:4700:4700::1111", Ok("one.one.one.one"))]
+    #[case("192.0.2.1", Err(SolveError::NotFound))]
+    fn test_ip_solve(#[case] s: &str, #[case] p: Result<&str, SolveError>) {
         let ptr = Ip::new(s).solve();
-        assert_eq!(s.parse::<IpAddr>().unwrap(), ptr.ip);
-        assert_eq!(p.to_string(), ptr.name);
+        assert_eq!(p, ptr.as_ref().map(|ip| ip.name()).map_err(|e| e.clone()));
+        if let Ok(ptr) = ptr {
+            assert_eq!(s.parse::<IpAddr>().unwrap(), ptr.ip);
+        }
+    }
+
+    #[rstest]
+    #[case("0.1.2.3", Some(Special::ThisNetwork))]
+    #[case("10.1.2.3", Some(Special::Private))]
+    #[case("172.31.255.255", Some(Special::Private))]
+    #[case("172.32.0.1", None)]
+    #[case("192.168.1.1", Some(Special::Private))]
+    #[case("100.64.0.1", Some(Special::Cgnat))]
+    #[case("100.128.0.1", None)]
+    #[case("127.0.0.1", Some(Special::Loopback))]
+    #[case("169.254.1.1", Some(Special::LinkLocal))]
+    #[case("192.0.0.8", Some(Special::Protocol))]
+    #[case("192.0.0.9", None)]
+    #[case("192.0.0.10", None)]
+    #[case("192.0.0.170", Some(Special::Protocol))]
+    #[case("192.0.2.1", Some(Special::Documentation))]
+    #[case("198.51.100.7", Some(Special::Documentation))]
+    #[case("203.0.113.1", Some(Special::Documentation))]
+    #[case("198.19.1.1", Some(Special::Benchmarking))]
+    #[case("224.0.0.1", Some(Special::Multicast))]
+    #[case("250.1.1.1", Some(Special::Reserved))]
+    #[case("255.255.255.255", Some(Special::Broadcast))]
+    #[case("1.1.1.1", None)]
+    #[case("217.70.183.200", None)]
+    #[case("::", Some(Special::Unspecified))]
+    #[case("::1", Some(Special::Loopback))]
+    #[case("::ffff:1.1.1.1", Some(Special::Ipv4Mapped))]
+    #[case("64:ff9b:1::1", Some(Special::Translation))]
+    #[case("64:ff9b::1.1.1.1", None)]
+    #[case("100::1", Some(Special::Discard))]
+    #[case("2001:2::1", Some(Special::Benchmarking))]
+    #[case("2001:db8:1:2::25", Some(Special::Documentation))]
+    #[case("3fff:1::1", Some(Special::Documentation))]
+    #[case("2001:0:4136:e378::1", None)]
+    #[case("2001:1::1", None)]
+    #[case("2001:1::4", Some(Special::Protocol))]
+    #[case("2001:3::1", None)]
+    #[case("2001:4:112::1", None)]
+    #[case("2001:4:113::1", Some(Special::Protocol))]
+    #[case("2001:10::1", Some(Special::Protocol))]
+    #[case("2001:20::1", None)]
+    #[case("2001:1ff::1", Some(Special::Protocol))]
+    #[case("2001:200::1", None)]
+    #[case("fd00::1", Some(Special::UniqueLocal))]
+    #[case("fe80::1", Some(Special::LinkLocal))]
+    #[case("ff02::1", Some(Special::Multicast))]
+    #[case("2606:4700:4700::1111", None)]
+    fn test_ip_special(#[case] s: &str, #[case] r: Option<Special>) {
+        assert_eq!(r, Ip::new(s).special());
+    }
+
+    #[test]
+    fn test_ip_special_mapped() {
+        // Kept as is by new() and in reports, but not by parse()
+        let ip: IpAddr = "::ffff:1.1.1.1".parse().unwrap();
+        assert_eq!(Some(Special::Ipv4Mapped), special(&ip));
+        assert_eq!(ip, Ip::new("::ffff:1.1.1.1").ip);
+        assert_eq!(None, "::ffff:1.1.1.1".parse::<Ip>().unwrap().special());
+    }
+
+    #[rstest]
+    #[case(Special::Private, "private")]
+    #[case(Special::Cgnat, "CGNAT")]
+    #[case(Special::Ipv4Mapped, "IPv4-mapped")]
+    fn test_special_display(#[case] s: Special, #[case] r: &str) {
+        assert_eq!(r, s.to_string());
+    }
+
+    #[rstest]
+    #[case("192.0.2.42", "192.0.2.0/24")]
+    #[case("2001:db8:1:2::1", "2001:db8:1::/48")]
+    fn test_ip_network(#[case] s: &str, #[case] n: &str) {
+        assert_eq!(n, Ip::new(s).network(24, 48).to_string());
+    }
+
+    #[test]
+    fn test_ip_network_capped() {
+        assert_eq!(
+            "192.0.2.42/32",
+            Ip::new("192.0.2.42").network(64, 200).to_string()
+        );
     }
 
     #[test]
     fn test_new_from_tuple() {
         let exp = Ip {
             ip: "1.1.1.1".parse::<IpAddr>().unwrap(),
-            name: "one.one.one.one".into(),
+            names: vec!["one.one.one.one".into()],
+            asn: None,
+            error: None,
+            verified: None,
+            attrs: Attributes::new(),
         };
 
-        let t = Ip::from(("1.1.1.1", "one.one.one.one"));
+        let t = Ip::try_from(("1.1.1.1", "one.one.one.one")).unwrap();
 
         assert_eq!(exp, t);
     }
 
+    #[test]
+    fn test_names() {
+        let ip = Ip::try_from((
+            "192.0.2.1",
+            ["mx1.example.net", "", "www.example.net"].as_slice(),
+        ))
+        .unwrap();
+        assert_eq!(vec!["mx1.example.net", "www.example.net"], ip.names);
+        assert_eq!("mx1.example.net", ip.name());
+
+        assert_eq!("", Ip::new("192.0.2.1").name());
+        assert!(Ip::try_from(("192.0.2.1", "")).unwrap().names.is_empty());
+    }
+
+    #[rstest]
+    #[case("", IpError::Empty)]
+    #[case("foo", IpError::Invalid("foo".into()))]
+    #[case("192.0.2.256", IpError::Invalid("192.0.2.256".into()))]
+    #[case("fe80::1%eth0", IpError::ZoneId("fe80::1%eth0".into()))]
+    fn test_try_from_tuple_invalid(#[case] ip: &str, #[case] err: IpError) {
+        assert_eq!(Err(err.clone()), Ip::try_from((ip, "mx1.example.net")));
+        assert_eq!(
+            Err(err),
+            Ip::try_from((ip, ["mx1.example.net", "www.example.net"].as_slice()))
+        );
+    }
+
     #[test]
     fn test_partial_eq() {
         let r = Ip {
             ip: "127.0.0.1".parse::<IpAddr>().unwrap(),
-            name: "".into(),
+            names: vec![],
+            asn: None,
+            error: None,
+            verified: None,
+            attrs: Attributes::new(),
         };
 
         assert_eq!(r, Ip::new("127.0.0.1"));
     }
+
+    #[test]
+    fn test_serde() {
+        let ip = Ip::try_from(("192.0.2.1", "mx1.example.net")).unwrap();
+        let s = serde_json::to_string(&ip).unwrap();
+        assert_eq!(r#"{"ip":"192.0.2.1","names":["mx1.example.net"]}"#, s);
+        assert_eq!(ip, serde_json::from_str(&s).unwrap());
+
+        assert_eq!(
+            r#"{"ip":"2001:db8::1"}"#,
+            serde_json::to_string(&Ip::new("2001:db8::1")).unwrap()
+        );
+        assert_eq!(
+            Ip::new("192.0.2.1"),
+            serde_json::from_str::<Ip>(r#"" ::ffff:192.0.2.1""#).unwrap()
+        );
+        assert!(serde_json::from_str::<Ip>(r#"{"ip":"fe80::1%eth0"}"#).is_err());
+        assert!(serde_json::from_str::<Ip>(r#"{"names":[]}"#).is_err());
+    }
 }
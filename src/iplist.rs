@@ -2,17 +2,99 @@
 //!
 //! **NOTE**  all the resolving part has been removed from that part due to issues
 //! with implementing support for multiple resolvers (useful for testing).
+//!
+//! Lists can be compared as sets of addresses, names and other fields being ignored, and a
+//! `CountedIpList` keeps the number of records and messages of each IP in a set of reports.
+//!
+//! Example:
+//! ```
+//! use dmarc_rs::iplist::IpList;
+//!
+//! let last = IpList::from(["192.0.2.1", "192.0.2.2"]);
+//! let this = IpList::from(["192.0.2.2", "192.0.2.3", "192.0.2.3"]);
+//!
+//! // New senders this week
+//! assert_eq!(IpList::from(["192.0.2.3"]), this.difference(&last));
+//! ```
+//!
+//! Lists can be read from and written to files in one of the `ListFormat`, chosen from the
+//! extension by `load()` and `save()`:
+//!
+//! - `.json`: an array of `Ip` (see `crate::ip`),
+//! - `.csv`: an `ip,name` header then one IP per line with its primary name, quoted if needed,
+//! - anything else: plain `ip[,name]` lines, blank lines and `#` comments being ignored.
+//!
+//! Both text formats have one IP per line, so a list with a newline in a name can only be saved
+//! as JSON.
+//!
+//! Example:
+//! ```
+//! use dmarc_rs::iplist::{IpList, ListFormat};
+//!
+//! let s = "# allowed\n192.0.2.1,mx1.example.net\n2001:db8::1\n";
+//! let l = IpList::parse_as(s, ListFormat::Plain).unwrap();
+//! assert_eq!("mx1.example.net", l[0].name());
+//!
+//! let s = l.to_string_as(ListFormat::Csv).unwrap();
+//! assert_eq!("ip,name\n192.0.2.1,mx1.example.net\n2001:db8::1,\n", s);
+//! ```
+//!
 
 // Our crates
 //
-use crate::ip::Ip;
+use crate::cidr::Cidr;
+use crate::ip::{Ip, IpError};
+use crate::types::Report;
 
 // Std library
 //
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::net::IpAddr;
 use std::ops::{Index, IndexMut};
+use std::path::Path;
 
 // External crates
 //
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+/// File formats for lists of IPs, see above.
+///
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ListFormat {
+    /// Array of `Ip`
+    Json,
+    /// `ip,name` with a header
+    Csv,
+    /// `ip[,name]` lines
+    Plain,
+}
+
+impl ListFormat {
+    /// Format of a file from its extension.
+    ///
+    /// Example:
+    /// ```
+    /// # use dmarc_rs::iplist::ListFormat;
+    /// assert_eq!(ListFormat::Csv, ListFormat::from_path("senders.CSV"));
+    /// assert_eq!(ListFormat::Plain, ListFormat::from_path("allowlist.txt"));
+    /// ```
+    ///
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Self {
+        match path
+            .as_ref()
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_ascii_lowercase())
+            .as_deref()
+        {
+            Some("json") => ListFormat::Json,
+            Some("csv") => ListFormat::Csv,
+            _ => ListFormat::Plain,
+        }
+    }
+}
 
 /// List of IP tuples.
 ///
@@ -25,7 +107,8 @@ use std::ops::{Index, IndexMut};
 /// We also define a large set of operations over `IpList` in order to facilitate manipulation
 /// with iterators, creating from iterators, sorting, indexed access, etc.
 ///
-#[derive(Clone, Debug, Eq, PartialOrd, Ord, PartialEq)]
+#[derive(Clone, Debug, Deserialize, Eq, PartialOrd, Ord, PartialEq, Serialize)]
+#[serde(transparent)]
 pub struct IpList(Vec<Ip>);
 
 /// Implement the Default Trait.
@@ -111,6 +194,419 @@ impl IpList {
     pub fn sort(&mut self) {
         self.0.sort();
     }
+
+    /// Iterate over the list of Ip without consuming it.
+    ///
+    /// Example:
+    /// ```
+    /// # use dmarc_rs::iplist::IpList;
+    /// let ipl = IpList::from(["192.0.2.1", "2001:db8::1"]);
+    ///
+    /// assert_eq!(1, ipl.iter().filter(|ip| ip.ip.is_ipv6()).count());
+    /// ```
+    ///
+    #[inline]
+    pub fn iter(&self) -> std::slice::Iter<'_, Ip> {
+        self.0.iter()
+    }
+
+    /// Remove the IPs already seen earlier in the list, keeping the first of each address.
+    ///
+    /// Example:
+    /// ```
+    /// # use dmarc_rs::iplist::IpList;
+    /// let mut ipl = IpList::from(["192.0.2.2", "192.0.2.1", "192.0.2.2"]);
+    ///
+    /// ipl.dedup();
+    /// assert_eq!(IpList::from(["192.0.2.2", "192.0.2.1"]), ipl);
+    /// ```
+    ///
+    pub fn dedup(&mut self) {
+        let mut seen = HashSet::new();
+        self.0.retain(|ip| seen.insert(ip.ip));
+    }
+
+    /// Is this address in the list?
+    ///
+    /// Example:
+    /// ```
+    /// # use dmarc_rs::iplist::IpList;
+    /// let ipl = IpList::from(["192.0.2.1", "2001:db8::1"]);
+    ///
+    /// assert!(ipl.contains(&"2001:db8::1".parse().unwrap()));
+    /// assert!(!ipl.contains(&"192.0.2.2".parse().unwrap()));
+    /// ```
+    ///
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        self.0.iter().any(|i| i.ip == *ip)
+    }
+
+    /// Is one of the IPs of the list inside this network?
+    ///
+    /// Example:
+    /// ```
+    /// # use dmarc_rs::iplist::IpList;
+    /// let ipl = IpList::from(["192.0.2.1", "2001:db8::1"]);
+    ///
+    /// assert!(ipl.contains_net(&"2001:db8::/32".parse().unwrap()));
+    /// assert!(!ipl.contains_net(&"198.51.100.0/24".parse().unwrap()));
+    /// ```
+    ///
+    pub fn contains_net(&self, net: &Cidr) -> bool {
+        self.0.iter().any(|i| net.contains(&i.ip))
+    }
+
+    /// IPs of the list inside this network, in order.
+    ///
+    /// Example:
+    /// ```
+    /// # use dmarc_rs::iplist::IpList;
+    /// let ipl = IpList::from(["192.0.2.1", "198.51.100.1", "192.0.2.200"]);
+    ///
+    /// let l = ipl.within(&"192.0.2.0/24".parse().unwrap());
+    /// assert_eq!(IpList::from(["192.0.2.1", "192.0.2.200"]), l);
+    /// ```
+    ///
+    pub fn within(&self, net: &Cidr) -> IpList {
+        self.0
+            .iter()
+            .filter(|i| net.contains(&i.ip))
+            .cloned()
+            .collect()
+    }
+
+    /// IPs of both lists without duplicates, those of this one first.
+    ///
+    /// Example:
+    /// ```
+    /// # use dmarc_rs::iplist::IpList;
+    /// let a = IpList::from(["192.0.2.1", "192.0.2.2"]);
+    /// let b = IpList::from(["192.0.2.3", "192.0.2.1"]);
+    ///
+    /// assert_eq!(IpList::from(["192.0.2.1", "192.0.2.2", "192.0.2.3"]), a.union(&b));
+    /// ```
+    ///
+    pub fn union(&self, other: &IpList) -> IpList {
+        let mut ipl: IpList = self.0.iter().chain(other.0.iter()).cloned().collect();
+        ipl.dedup();
+        ipl
+    }
+
+    /// IPs of this list also in the other one, without duplicates.
+    ///
+    /// Example:
+    /// ```
+    /// # use dmarc_rs::iplist::IpList;
+    /// let a = IpList::from(["192.0.2.1", "192.0.2.2"]);
+    /// let b = IpList::from(["192.0.2.3", "192.0.2.1"]);
+    ///
+    /// assert_eq!(IpList::from(["192.0.2.1"]), a.intersection(&b));
+    /// ```
+    ///
+    pub fn intersection(&self, other: &IpList) -> IpList {
+        let other: HashSet<IpAddr> = other.0.iter().map(|i| i.ip).collect();
+        self.select(|ip| other.contains(ip))
+    }
+
+    /// IPs of this list not in the other one, without duplicates.
+    ///
+    /// Example:
+    /// ```
+    /// # use dmarc_rs::iplist::IpList;
+    /// let a = IpList::from(["192.0.2.1", "192.0.2.2"]);
+    /// let b = IpList::from(["192.0.2.3", "192.0.2.1"]);
+    ///
+    /// assert_eq!(IpList::from(["192.0.2.2"]), a.difference(&b));
+    /// ```
+    ///
+    pub fn difference(&self, other: &IpList) -> IpList {
+        let other: HashSet<IpAddr> = other.0.iter().map(|i| i.ip).collect();
+        self.select(|ip| !other.contains(ip))
+    }
+
+    /// Load a list from a file, the format depending on the extension.
+    ///
+    /// Example:
+    /// ```no_run
+    /// # use dmarc_rs::iplist::IpList;
+    /// let allowed = IpList::load("allowlist.csv").unwrap();
+    /// ```
+    ///
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let s = fs::read_to_string(path).map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+        IpList::parse_as(&s, ListFormat::from_path(path))
+            .map_err(|e| anyhow!("{}: {}", path.display(), e))
+    }
+
+    /// Save the list to a file, the format depending on the extension.
+    ///
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let s = self.to_string_as(ListFormat::from_path(path))?;
+        fs::write(path, s).map_err(|e| anyhow!("{}: {}", path.display(), e))
+    }
+
+    /// Read a list in the given format.
+    ///
+    pub fn parse_as(s: &str, format: ListFormat) -> Result<Self> {
+        if format == ListFormat::Json {
+            return Ok(serde_json::from_str(s)?);
+        }
+
+        let mut ipl = IpList::new();
+        for (n, line) in s.lines().enumerate() {
+            let fields = match format {
+                ListFormat::Csv => csv_fields(line),
+                _ => match line.split('#').next().unwrap_or_default().trim() {
+                    "" => continue,
+                    l => Ok(l.splitn(2, ',').map(|f| f.trim().to_owned()).collect()),
+                },
+            }
+            .map_err(|e| anyhow!("line {}: {}", n + 1, e))?;
+            let ip = match fields.as_slice() {
+                [ip, ..] if n == 0 && format == ListFormat::Csv && ip == "ip" => continue,
+                [ip] if ip.is_empty() => continue,
+                [ip] => ip.parse::<Ip>(),
+                [ip, name] => ip.parse::<Ip>().map(|ip| Ip {
+                    names: [name]
+                        .into_iter()
+                        .filter(|n| !n.is_empty())
+                        .cloned()
+                        .collect(),
+                    ..ip
+                }),
+                _ => return Err(anyhow!("line {}: too many fields", n + 1)),
+            };
+            ipl.push(ip.map_err(|e| anyhow!("line {}: {}", n + 1, e))?);
+        }
+        Ok(ipl)
+    }
+
+    /// Write the list in the given format, only the primary name being kept in CSV and plain
+    /// formats.  These can not have a newline in a name, being read line by line.
+    ///
+    pub fn to_string_as(&self, format: ListFormat) -> Result<String> {
+        let mut s = match format {
+            ListFormat::Json => return Ok(serde_json::to_string_pretty(self)? + "\n"),
+            ListFormat::Csv => String::from("ip,name\n"),
+            ListFormat::Plain => String::new(),
+        };
+        for ip in self.0.iter() {
+            if ip.name().contains(['\n', '\r']) {
+                return Err(anyhow!("{}: newline in name {:?}", ip.ip, ip.name()));
+            }
+            let line = match (format, ip.name()) {
+                (ListFormat::Csv, name) => format!("{},{}\n", ip.ip, csv_quote(name)),
+                (_, "") => format!("{}\n", ip.ip),
+                (_, name) => format!("{},{}\n", ip.ip, name),
+            };
+            s.push_str(&line);
+        }
+        Ok(s)
+    }
+
+    /// Keep the first of each address matching `f`, in order.
+    ///
+    fn select<F: Fn(&IpAddr) -> bool>(&self, f: F) -> IpList {
+        let mut ipl: IpList = self.0.iter().filter(|i| f(&i.ip)).cloned().collect();
+        ipl.dedup();
+        ipl
+    }
+}
+
+/// Split a CSV line into fields, with double quotes around fields containing a comma or a quote.
+///
+fn csv_fields(line: &str) -> Result<Vec<String>> {
+    let mut fields = vec![];
+    let mut f = String::new();
+    let mut quoted = false;
+    let mut chars = line.trim_end_matches('\r').chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, quoted) {
+            ('"', true) if chars.peek() == Some(&'"') => {
+                chars.next();
+                f.push('"');
+            }
+            ('"', true) => quoted = false,
+            ('"', false) if f.is_empty() => quoted = true,
+            (',', false) => fields.push(std::mem::take(&mut f)),
+            (c, _) => f.push(c),
+        }
+    }
+    if quoted {
+        return Err(anyhow!("unterminated quote"));
+    }
+    fields.push(f);
+    Ok(fields.into_iter().map(|f| f.trim().to_owned()).collect())
+}
+
+/// Quote a CSV field if needed.
+///
+fn csv_quote(f: &str) -> String {
+    match f.contains([',', '"']) {
+        true => format!("\"{}\"", f.replace('"', "\"\"")),
+        false => f.to_owned(),
+    }
+}
+
+/// Number of records and messages an IP appears in.
+///
+#[derive(Clone, Copy, Debug, Default, Eq, Ord, PartialEq, PartialOrd)]
+pub struct IpCount {
+    /// Records with this source IP
+    pub records: usize,
+    /// Sum of their message count
+    pub messages: u64,
+}
+
+/// List of unique IPs with how often each of them appears, in the order they were first seen.
+///
+/// Example:
+/// ```
+/// # use dmarc_rs::ip::Ip;
+/// use dmarc_rs::iplist::CountedIpList;
+///
+/// let mut l = CountedIpList::new();
+/// l.add(Ip::new("192.0.2.1"), 10);
+/// l.add(Ip::new("192.0.2.2"), 1);
+/// l.add(Ip::new("192.0.2.1"), 5);
+///
+/// assert_eq!(2, l.len());
+/// let c = l.count(&"192.0.2.1".parse().unwrap()).unwrap();
+/// assert_eq!((2, 15), (c.records, c.messages));
+/// ```
+///
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CountedIpList {
+    list: Vec<(Ip, IpCount)>,
+    index: HashMap<IpAddr, usize>,
+}
+
+impl CountedIpList {
+    /// Empty list.
+    ///
+    #[inline]
+    pub fn new() -> Self {
+        CountedIpList::default()
+    }
+
+    /// Count one record of `messages` messages from this IP.
+    ///
+    pub fn add(&mut self, ip: Ip, messages: u64) {
+        let i = match self.index.get(&ip.ip) {
+            Some(&i) => i,
+            None => {
+                self.index.insert(ip.ip, self.list.len());
+                self.list.push((ip, IpCount::default()));
+                self.list.len() - 1
+            }
+        };
+        let c = &mut self.list[i].1;
+        c.records += 1;
+        c.messages += messages;
+    }
+
+    /// Count all the records of a report.
+    ///
+    pub fn add_report(&mut self, rep: &Report) {
+        for r in rep.record.iter() {
+            self.add(Ip::from(r.row.source_ip), r.row.count as u64);
+        }
+    }
+
+    /// Number of unique IPs.
+    ///
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    /// Is there no IP at all?
+    ///
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    /// Counts of this address, if seen.
+    ///
+    pub fn count(&self, ip: &IpAddr) -> Option<IpCount> {
+        self.index.get(ip).map(|&i| self.list[i].1)
+    }
+
+    /// Iterate over the IPs with their counts, in the order they were first seen.
+    ///
+    pub fn iter(&self) -> impl Iterator<Item = &(Ip, IpCount)> {
+        self.list.iter()
+    }
+
+    /// Sort by decreasing number of messages then records, the address breaking ties.
+    ///
+    /// Example:
+    /// ```
+    /// # use dmarc_rs::ip::Ip;
+    /// # use dmarc_rs::iplist::CountedIpList;
+    /// let mut l: CountedIpList = [(Ip::new("192.0.2.1"), 1), (Ip::new("192.0.2.2"), 10)]
+    ///     .into_iter()
+    ///     .collect();
+    ///
+    /// l.sort_by_count();
+    /// assert_eq!("192.0.2.2", l.ips()[0].ip.to_string());
+    /// ```
+    ///
+    pub fn sort_by_count(&mut self) {
+        self.list.sort_by(|(a, ca), (b, cb)| {
+            (cb.messages, cb.records, &a.ip).cmp(&(ca.messages, ca.records, &b.ip))
+        });
+        self.index = self
+            .list
+            .iter()
+            .enumerate()
+            .map(|(i, (ip, _))| (ip.ip, i))
+            .collect();
+    }
+
+    /// The IPs without their counts.
+    ///
+    pub fn ips(&self) -> IpList {
+        self.list.iter().map(|(ip, _)| ip.clone()).collect()
+    }
+}
+
+/// Count the records of a set of reports.
+///
+/// Example:
+/// ```
+/// # use dmarc_rs::iplist::CountedIpList;
+/// # use dmarc_rs::types::Feedback;
+/// let reports: Feedback = vec![];
+///
+/// let l: CountedIpList = reports.iter().collect();
+/// assert!(l.is_empty());
+/// ```
+///
+impl<'a> FromIterator<&'a Report> for CountedIpList {
+    fn from_iter<T: IntoIterator<Item = &'a Report>>(iter: T) -> Self {
+        let mut l = CountedIpList::new();
+        for rep in iter {
+            l.add_report(rep);
+        }
+        l
+    }
+}
+
+/// Count `(Ip, messages)` tuples, each one being a record.
+///
+impl FromIterator<(Ip, u64)> for CountedIpList {
+    fn from_iter<T: IntoIterator<Item = (Ip, u64)>>(iter: T) -> Self {
+        let mut l = CountedIpList::new();
+        for (ip, n) in iter {
+            l.add(ip, n);
+        }
+        l
+    }
 }
 
 /// Implement `IntoIterator` for `IpList` by calling the inner `into_iter()`.
@@ -139,13 +635,18 @@ impl IntoIterator for IpList {
 
 /// Create an `IpList` from an iterator of `&str`.
 ///
+/// Panics on an invalid address like `Ip::new()`.
+///
 impl<const N: usize> From<[(&str, &str); N]> for IpList {
     /// Used as a shortcut to `from_iter()`
     ///
     /// Example:
     /// ```
     /// # use dmarc_rs::iplist::IpList;
-    /// let l = IpList::from([("1.1.1.1", "one.one.one.one"), ("2606:4700:4700::1111", "one.one.one.one")]);
+    /// let l = IpList::from([
+    ///     ("1.1.1.1", "one.one.one.one"),
+    ///     ("2606:4700:4700::1111", "one.one.one.one"),
+    /// ]);
     ///
     /// assert_eq!(2, l.len());
     /// ```
@@ -158,6 +659,8 @@ impl<const N: usize> From<[(&str, &str); N]> for IpList {
 
 /// Create an `IpList` from an iterator of `(&str,&str)` tuples.
 ///
+/// Panics on an invalid address like `Ip::new()`.
+///
 impl<const N: usize> From<[&str; N]> for IpList {
     /// Used as a shortcut to `from_iter()`
     ///
@@ -175,6 +678,32 @@ impl<const N: usize> From<[&str; N]> for IpList {
     }
 }
 
+/// Parse a list of addresses from untrusted input, failing on the first invalid one.
+///
+/// Collecting parsed `Ip` into a `Result` does the same with any iterator.
+///
+/// Example:
+/// ```
+/// # use dmarc_rs::ip::{Ip, IpError};
+/// # use dmarc_rs::iplist::IpList;
+/// let l = IpList::try_from(["192.0.2.1", " 2001:db8::1 "].as_slice()).unwrap();
+/// assert_eq!(2, l.len());
+///
+/// let l: Result<IpList, IpError> = "192.0.2.1 fe80::1%eth0"
+///     .split(' ')
+///     .map(|s| s.parse::<Ip>())
+///     .collect();
+/// assert!(l.is_err());
+/// ```
+///
+impl TryFrom<&[&str]> for IpList {
+    type Error = IpError;
+
+    fn try_from(l: &[&str]) -> Result<Self, Self::Error> {
+        l.iter().map(|s| s.parse::<Ip>()).collect()
+    }
+}
+
 impl From<Ip> for IpList {
     /// Create an `IpList` from a single `Ip`.
     ///
@@ -223,7 +752,7 @@ impl<'a> FromIterator<(&'a str, &'a str)> for IpList {
         let mut ipl = IpList::new();
 
         for (ip, name) in iter {
-            ipl.push(Ip::from((ip, name)))
+            ipl.push(Ip::try_from((ip, name)).unwrap())
         }
         ipl
     }
@@ -293,9 +822,12 @@ impl IndexMut<usize> for IpList {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::attrs::Attributes;
     use crate::ip::Ip;
     use std::net::IpAddr;
 
+    use rstest::rstest;
+
     #[test]
     fn test_push() {
         let mut l = IpList::new();
@@ -324,7 +856,7 @@ mod tests {
         assert!(ipl.is_empty());
 
         ipl.push(Ip::new("1.0.0.1"));
-        assert_eq!(false, ipl.is_empty());
+        assert!(!ipl.is_empty());
     }
 
     #[test]
@@ -349,6 +881,132 @@ mod tests {
         assert_eq!(l, l2);
     }
 
+    #[test]
+    fn test_try_from() {
+        let l = IpList::try_from(["192.0.2.1", "::ffff:192.0.2.2"].as_slice()).unwrap();
+        assert_eq!(IpList::from(["192.0.2.1", "192.0.2.2"]), l);
+
+        assert_eq!(
+            Err(IpError::Invalid("192.0.2.256".into())),
+            IpList::try_from(["192.0.2.1", "192.0.2.256"].as_slice())
+        );
+        assert_eq!(Err(IpError::Empty), IpList::try_from([""].as_slice()));
+    }
+
+    #[test]
+    fn test_serde() {
+        let l = IpList::from([("192.0.2.1", "mx1.example.net"), ("2001:db8::1", "")]);
+        let s = serde_json::to_string(&l).unwrap();
+
+        assert_eq!(
+            r#"[{"ip":"192.0.2.1","names":["mx1.example.net"]},{"ip":"2001:db8::1"}]"#,
+            s
+        );
+        assert_eq!(l, serde_json::from_str(&s).unwrap());
+        assert_eq!(
+            IpList::from(["192.0.2.1"]),
+            serde_json::from_str::<IpList>(r#"["192.0.2.1"]"#).unwrap()
+        );
+    }
+
+    #[rstest]
+    #[case(ListFormat::Json)]
+    #[case(ListFormat::Csv)]
+    #[case(ListFormat::Plain)]
+    fn test_format_roundtrip(#[case] f: ListFormat) {
+        let l = IpList::from([
+            ("192.0.2.1", "mx1.example.net"),
+            ("2001:db8::1", ""),
+            ("192.0.2.2", "odd,name"),
+        ]);
+
+        let s = l.to_string_as(f).unwrap();
+        assert_eq!(l, IpList::parse_as(&s, f).unwrap());
+    }
+
+    #[test]
+    fn test_parse_plain() {
+        let s = concat!(
+            "# allowed senders\n\n192.0.2.1\n",
+            " 192.0.2.2 , mx2.example.net # backup\n",
+            "::ffff:192.0.2.3,\n"
+        );
+        let l = IpList::parse_as(s, ListFormat::Plain).unwrap();
+
+        assert_eq!(
+            IpList::from([
+                ("192.0.2.1", ""),
+                ("192.0.2.2", "mx2.example.net"),
+                ("192.0.2.3", "")
+            ]),
+            l
+        );
+    }
+
+    #[test]
+    fn test_parse_csv() {
+        let s = concat!(
+            "ip,name\r\n",
+            "192.0.2.1,\"mx1.example.net\"\r\n",
+            "192.0.2.2,\"a \"\"b\"\", c\"\r\n",
+            "192.0.2.3\r\n"
+        );
+        let l = IpList::parse_as(s, ListFormat::Csv).unwrap();
+
+        assert_eq!(3, l.len());
+        assert_eq!("mx1.example.net", l[0].name());
+        assert_eq!("a \"b\", c", l[1].name());
+        assert_eq!("", l[2].name());
+    }
+
+    #[rstest]
+    #[case(
+        "192.0.2.1\nfoo\n",
+        ListFormat::Plain,
+        "line 2: invalid IP address foo"
+    )]
+    #[case(
+        "fe80::1%eth0\n",
+        ListFormat::Plain,
+        "line 1: zone ID not allowed in fe80::1%eth0"
+    )]
+    #[case("ip,name\n192.0.2.1,a,b\n", ListFormat::Csv, "line 2: too many fields")]
+    #[case("192.0.2.1,\"a\n", ListFormat::Csv, "line 1: unterminated quote")]
+    fn test_parse_nok(#[case] s: &str, #[case] f: ListFormat, #[case] e: &str) {
+        assert_eq!(e, IpList::parse_as(s, f).unwrap_err().to_string());
+    }
+
+    #[rstest]
+    #[case(ListFormat::Csv)]
+    #[case(ListFormat::Plain)]
+    fn test_format_newline(#[case] f: ListFormat) {
+        let l = IpList::from([("192.0.2.1", "a\nb")]);
+
+        assert_eq!(
+            "192.0.2.1: newline in name \"a\\nb\"",
+            l.to_string_as(f).unwrap_err().to_string()
+        );
+        assert!(l.to_string_as(ListFormat::Json).is_ok());
+    }
+
+    #[test]
+    fn test_load_save() {
+        let dir = std::env::temp_dir().join(format!("dmarc-iplist-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let l = IpList::from([("192.0.2.1", "mx1.example.net"), ("2001:db8::1", "")]);
+
+        for f in ["ips.json", "ips.csv", "ips.txt"] {
+            let path = dir.join(f);
+            l.save(&path).unwrap();
+            assert_eq!(l, IpList::load(&path).unwrap());
+        }
+        assert!(fs::read_to_string(dir.join("ips.csv"))
+            .unwrap()
+            .starts_with("ip,name\n"));
+        assert!(IpList::load(dir.join("nonexistent")).is_err());
+        let _ = fs::remove_dir_all(dir);
+    }
+
     #[test]
     fn test_from_array_tuples() {
         use std::net::IpAddr;
@@ -356,15 +1014,27 @@ mod tests {
         let l = IpList(vec![
             Ip {
                 ip: "1.1.1.1".parse::<IpAddr>().unwrap(),
-                name: "one.one.one.one".into(),
+                names: vec!["one.one.one.one".into()],
+                asn: None,
+                error: None,
+                verified: None,
+                attrs: Attributes::new(),
             },
             Ip {
                 ip: "2606:4700:4700::1111".parse::<IpAddr>().unwrap(),
-                name: "one.one.one.one".into(),
+                names: vec!["one.one.one.one".into()],
+                asn: None,
+                error: None,
+                verified: None,
+                attrs: Attributes::new(),
             },
             Ip {
                 ip: "192.0.2.1".parse::<IpAddr>().unwrap(),
-                name: "some.host.invalid".into(),
+                names: vec!["some.host.invalid".into()],
+                asn: None,
+                error: None,
+                verified: None,
+                attrs: Attributes::new(),
             },
         ]);
         let l2 = IpList::from([
@@ -380,7 +1050,7 @@ mod tests {
     fn test_collect() {
         let l = IpList::from(["1.1.1.1", "2606:4700:4700::1111", "192.0.2.1"]);
 
-        let s: IpList = l.into_iter().map(|ip| ip.clone()).collect();
+        let s: IpList = l.into_iter().collect();
         assert_eq!(3, s.len());
     }
 
@@ -451,4 +1121,87 @@ mod tests {
 
         assert_eq!(ipl, r);
     }
+
+    #[test]
+    fn test_dedup() {
+        let mut ipl = IpList::from([
+            ("192.0.2.2", "a.example.net"),
+            ("192.0.2.1", ""),
+            ("192.0.2.2", "b.example.net"),
+        ]);
+
+        ipl.dedup();
+        assert_eq!(2, ipl.len());
+        assert_eq!("a.example.net", ipl[0].name());
+    }
+
+    #[test]
+    fn test_set_ops() {
+        let a = IpList::from(["192.0.2.1", "192.0.2.2", "2001:db8::1", "192.0.2.1"]);
+        let b = IpList::from(["2001:db8::1", "192.0.2.3", "192.0.2.1"]);
+
+        assert_eq!(
+            IpList::from(["192.0.2.1", "192.0.2.2", "2001:db8::1", "192.0.2.3"]),
+            a.union(&b)
+        );
+        assert_eq!(
+            IpList::from(["192.0.2.1", "2001:db8::1"]),
+            a.intersection(&b)
+        );
+        assert_eq!(IpList::from(["192.0.2.2"]), a.difference(&b));
+        assert_eq!(IpList::from(["192.0.2.3"]), b.difference(&a));
+        assert!(a.difference(&a).is_empty());
+        assert_eq!(a.union(&IpList::new()), a.intersection(&a));
+    }
+
+    #[test]
+    fn test_contains() {
+        let ipl = IpList::from(["192.0.2.1", "2001:db8:1::1"]);
+
+        assert!(ipl.contains(&"192.0.2.1".parse().unwrap()));
+        assert!(!ipl.contains(&"192.0.2.2".parse().unwrap()));
+        assert!(ipl.contains_net(&"192.0.2.0/30".parse().unwrap()));
+        assert!(ipl.contains_net(&"2001:db8::/32".parse().unwrap()));
+        assert!(!ipl.contains_net(&"2001:db8:2::/48".parse().unwrap()));
+        assert_eq!(
+            IpList::from(["2001:db8:1::1"]),
+            ipl.within(&"::/0".parse().unwrap())
+        );
+    }
+
+    #[test]
+    fn test_counted() {
+        use crate::testutil::{load, EXAMPLE};
+        use crate::types::Feedback;
+
+        let mut fb: Feedback = load(EXAMPLE);
+        fb.push(load::<Feedback>(EXAMPLE).remove(0));
+
+        let mut l: CountedIpList = fb.iter().collect();
+        assert_eq!(5, l.len());
+        assert_eq!(
+            Some(IpCount {
+                records: 2,
+                messages: 6
+            }),
+            l.count(&"198.51.100.7".parse().unwrap())
+        );
+        assert_eq!(None, l.count(&"192.0.2.1".parse().unwrap()));
+        assert_eq!("192.0.2.10", l.ips()[0].ip.to_string());
+
+        l.add(Ip::new("192.0.2.20"), 100);
+        l.sort_by_count();
+        let order: Vec<String> = l.iter().map(|(ip, _)| ip.ip.to_string()).collect();
+        assert_eq!(
+            vec![
+                "192.0.2.20",
+                "192.0.2.10",
+                "2001:db8:1:2::25",
+                "198.51.100.7",
+                "2001:db8:1:ff::26"
+            ],
+            order
+        );
+        assert_eq!(3, l.count(&"192.0.2.20".parse().unwrap()).unwrap().records);
+    }
 }
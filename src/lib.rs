@@ -1,9 +1,25 @@
 //! This crate implement the library part of `dmarc-rs`, dealing with IPs and list of IPs
 //!
 
+pub mod asn;
+#[cfg(feature = "async")]
+pub mod asyncres;
+pub mod attrs;
+pub mod bulk;
+pub mod cache;
+pub mod cidr;
+pub mod dns;
+pub mod enrich;
 pub mod entry;
 pub mod filetype;
 pub mod ip;
 pub mod iplist;
+pub mod metrics;
+pub mod ratelimit;
 pub mod resolver;
+pub mod sender;
+pub mod stats;
 pub mod types;
+
+#[cfg(test)]
+mod testutil;
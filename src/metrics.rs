@@ -0,0 +1,365 @@
+//! Module implementing resolver metrics.
+//!
+//! Choosing the number of jobs, the cache TTLs or the nameservers is easier with real numbers.
+//! A `Metrics` handle wraps a `Solver` and records every query going through it:
+//!
+//! - the number of reverse and forward lookups,
+//! - their outcome: found, NXDOMAIN (or no record), timeout or other failure,
+//! - their latency, in a histogram,
+//!
+//! and the hits of a `Cache` given the same handle with `Cache::with_metrics()`.  The wrapper
+//! should be put under the cache so that only the queries actually sent are counted.
+//!
+//! All the clones of a handle share the same counters; `snapshot()` returns them as a
+//! `ResolverStats`, which displays as a short summary.
+//!
+//! Example:
+//! ```
+//! use dmarc_rs::ip::Ip;
+//! use dmarc_rs::metrics::Metrics;
+//! use dmarc_rs::resolver::{res_init, ResType};
+//!
+//! let m = Metrics::new();
+//! let res = m.wrap(res_init(ResType::Null));
+//!
+//! res.solve(&Ip::new("192.0.2.1")).unwrap();
+//! let stats = m.snapshot();
+//! assert_eq!(1, stats.lookups);
+//! assert_eq!(1, stats.found);
+//! println!("{}", stats);
+//! ```
+//!
+
+// Std library
+//
+use std::fmt::{Debug, Display, Formatter};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Our crates
+//
+use crate::ip::Ip;
+use crate::resolver::{Resolver, SolveError, Solver};
+
+/// Upper bounds of the latency buckets, in ms, the last bucket being everything above.
+pub const BUCKETS: [u64; 8] = [1, 5, 10, 50, 100, 500, 1000, 5000];
+
+/// Latency histogram.
+///
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Histogram {
+    counts: [usize; BUCKETS.len() + 1],
+    total: Duration,
+    max: Duration,
+}
+
+impl Histogram {
+    /// Add one measure.
+    ///
+    pub fn record(&mut self, d: Duration) {
+        let ms = d.as_millis();
+        let i = BUCKETS
+            .iter()
+            .position(|&b| ms < b as u128)
+            .unwrap_or(BUCKETS.len());
+        self.counts[i] += 1;
+        self.total += d;
+        self.max = self.max.max(d);
+    }
+
+    /// Number of measures.
+    ///
+    pub fn count(&self) -> usize {
+        self.counts.iter().sum()
+    }
+
+    /// Longest measure.
+    ///
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// Average of the measures.
+    ///
+    pub fn mean(&self) -> Duration {
+        match self.count() {
+            0 => Duration::ZERO,
+            n => self.total / n as u32,
+        }
+    }
+
+    /// Upper bound of the bucket holding the `p`th percentile (0 to 100), at most the maximum.
+    ///
+    pub fn percentile(&self, p: f64) -> Duration {
+        let rank = (self.count() as f64 * p / 100.).ceil().max(1.) as usize;
+        let mut seen = 0;
+        for (i, n) in self.counts.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                return match BUCKETS.get(i) {
+                    Some(&b) => Duration::from_millis(b).min(self.max),
+                    None => self.max,
+                };
+            }
+        }
+        self.max
+    }
+
+    /// Count of each bucket with its upper bound, `None` for the last one.
+    ///
+    pub fn buckets(&self) -> Vec<(Option<Duration>, usize)> {
+        self.counts
+            .iter()
+            .enumerate()
+            .map(|(i, &n)| (BUCKETS.get(i).map(|&b| Duration::from_millis(b)), n))
+            .collect()
+    }
+}
+
+/// Counters of a `Metrics` handle.
+///
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ResolverStats {
+    /// Reverse lookups sent
+    pub lookups: usize,
+    /// Forward lookups sent
+    pub forwards: usize,
+    /// Answers found in the cache
+    pub cache_hits: usize,
+    /// Lookups with an answer
+    pub found: usize,
+    /// NXDOMAIN or no record
+    pub nxdomain: usize,
+    /// Lookups without any answer in time
+    pub timeouts: usize,
+    /// Other temporary failures (SERVFAIL, etc.)
+    pub failures: usize,
+    /// Latency of all lookups
+    pub latency: Histogram,
+}
+
+impl Display for ResolverStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "DNS: {} lookups, {} forward lookups, {} cache hits",
+            self.lookups, self.forwards, self.cache_hits
+        )?;
+        writeln!(
+            f,
+            "DNS: {} found, {} NXDOMAIN, {} timeouts, {} other failures",
+            self.found, self.nxdomain, self.timeouts, self.failures
+        )?;
+        if self.latency.count() == 0 {
+            return Ok(());
+        }
+        writeln!(
+            f,
+            "DNS latency: mean {}, p50 {}, p90 {}, p99 {}, max {}",
+            ms(self.latency.mean()),
+            ms(self.latency.percentile(50.)),
+            ms(self.latency.percentile(90.)),
+            ms(self.latency.percentile(99.)),
+            ms(self.latency.max())
+        )?;
+        let buckets: Vec<String> = self
+            .latency
+            .buckets()
+            .into_iter()
+            .map(|(b, n)| match b {
+                Some(b) => format!("<{} {}", ms(b), n),
+                None => format!(
+                    ">={} {}",
+                    ms(Duration::from_millis(BUCKETS[BUCKETS.len() - 1])),
+                    n
+                ),
+            })
+            .collect();
+        writeln!(f, "DNS latency: {}", buckets.join(", "))
+    }
+}
+
+/// Handle recording the metrics of the `Solver` it wraps, shared by all its clones.
+///
+#[derive(Clone, Default)]
+pub struct Metrics(Arc<Mutex<ResolverStats>>);
+
+impl Metrics {
+    /// Start with all counters at 0.
+    ///
+    pub fn new() -> Self {
+        Metrics::default()
+    }
+
+    /// Wrap a `Solver` to record its queries.
+    ///
+    pub fn wrap(&self, inner: Solver) -> Solver {
+        Solver::new(MetricsResolver {
+            metrics: self.clone(),
+            inner,
+        })
+    }
+
+    /// Count one answer from the cache.
+    ///
+    pub fn cache_hit(&self) {
+        self.0.lock().unwrap().cache_hits += 1;
+    }
+
+    /// Current value of the counters.
+    ///
+    pub fn snapshot(&self) -> ResolverStats {
+        self.0.lock().unwrap().clone()
+    }
+
+    /// Count one query.
+    ///
+    pub(crate) fn record(&self, forward: bool, r: Option<&SolveError>, d: Duration) {
+        let mut s = self.0.lock().unwrap();
+
+        match forward {
+            true => s.forwards += 1,
+            false => s.lookups += 1,
+        }
+        match r {
+            None => s.found += 1,
+            Some(SolveError::NotFound) => s.nxdomain += 1,
+            Some(SolveError::Timeout) => s.timeouts += 1,
+            Some(SolveError::TempFail(_)) => s.failures += 1,
+        }
+        s.latency.record(d);
+    }
+}
+
+impl Debug for Metrics {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "metrics {:?}", self.snapshot())
+    }
+}
+
+/// A resolver recording its queries in a `Metrics`.
+///
+struct MetricsResolver {
+    metrics: Metrics,
+    inner: Solver,
+}
+
+impl Resolver for MetricsResolver {
+    /// Implement the `Resolver` trait.
+    ///
+    fn solve(&self, ip: &Ip) -> Result<Ip, SolveError> {
+        let start = Instant::now();
+        let r = self.inner.solve(ip);
+        self.metrics
+            .record(false, r.as_ref().err(), start.elapsed());
+        r
+    }
+
+    /// Implement the `Resolver` trait.
+    ///
+    fn forward(&self, name: &str) -> Result<Vec<IpAddr>, SolveError> {
+        let start = Instant::now();
+        let r = self.inner.forward(name);
+        self.metrics.record(true, r.as_ref().err(), start.elapsed());
+        r
+    }
+}
+
+/// Display a duration in ms, or in s above 1s.
+///
+fn ms(d: Duration) -> String {
+    match d.as_millis() {
+        n if n >= 1000 => format!("{:.1}s", d.as_secs_f64()),
+        n => format!("{}ms", n),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::stubs::{Failing, TimingOut};
+    use crate::resolver::{res_init, FakeResolver, ResType};
+
+    use rstest::rstest;
+
+    #[test]
+    fn test_metrics() {
+        let m = Metrics::new();
+        let res = m.wrap(Solver::new(FakeResolver::load("testdata/hosts").unwrap()));
+
+        for ip in ["192.0.2.10", "192.0.2.20", "198.51.100.7", "198.51.100.8"] {
+            let _ = res.solve(&Ip::new(ip));
+        }
+        let _ = res.forward("mx1.example.net");
+
+        let s = m.snapshot();
+        assert_eq!(4, s.lookups);
+        assert_eq!(1, s.forwards);
+        assert_eq!(3, s.found);
+        assert_eq!(1, s.nxdomain);
+        assert_eq!(1, s.failures);
+        assert_eq!(0, s.timeouts);
+        assert_eq!(5, s.latency.count());
+    }
+
+    #[test]
+    fn test_metrics_timeouts() {
+        let m = Metrics::new();
+
+        let _ = m.wrap(Solver::new(TimingOut)).solve(&Ip::new("192.0.2.1"));
+        let _ = m.wrap(Solver::new(Failing("SERVFAIL"))).forward("foo");
+        m.cache_hit();
+
+        let s = m.snapshot();
+        assert_eq!(
+            (1, 1, 1, 1),
+            (s.timeouts, s.failures, s.lookups, s.forwards)
+        );
+        assert_eq!(1, s.cache_hits);
+    }
+
+    #[test]
+    fn test_histogram() {
+        let mut h = Histogram::default();
+        assert_eq!(Duration::ZERO, h.mean());
+        assert_eq!(Duration::ZERO, h.percentile(50.));
+
+        for ms in [0, 3, 3, 7, 20, 20, 20, 200, 800, 6000] {
+            h.record(Duration::from_millis(ms));
+        }
+        assert_eq!(10, h.count());
+        assert_eq!(Duration::from_millis(6000), h.max());
+        assert_eq!(707, h.mean().as_millis());
+        assert_eq!(Duration::from_millis(50), h.percentile(50.));
+        assert_eq!(Duration::from_millis(1000), h.percentile(90.));
+        assert_eq!(Duration::from_millis(6000), h.percentile(100.));
+        assert_eq!(
+            vec![1, 2, 1, 3, 0, 1, 1, 0, 1],
+            h.buckets().into_iter().map(|(_, n)| n).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_display() {
+        let m = Metrics::new();
+        let res = m.wrap(res_init(ResType::Null));
+        res.solve(&Ip::new("192.0.2.1")).unwrap();
+
+        let s = m.snapshot().to_string();
+        assert!(s.contains("DNS: 1 lookups, 0 forward lookups, 0 cache hits"));
+        assert!(s.contains("DNS: 1 found, 0 NXDOMAIN, 0 timeouts, 0 other failures"));
+        assert!(s.contains("<1ms 1"));
+        assert_eq!(4, s.lines().count());
+
+        assert_eq!(2, ResolverStats::default().to_string().lines().count());
+    }
+
+    #[rstest]
+    #[case(0, "0ms")]
+    #[case(999, "999ms")]
+    #[case(1500, "1.5s")]
+    fn test_ms(#[case] d: u64, #[case] r: &str) {
+        assert_eq!(r, ms(Duration::from_millis(d)));
+    }
+}
@@ -0,0 +1,268 @@
+//! Module implementing rate limiting for resolvers.
+//!
+//! Going through a year of reports means thousands of lookups in a few seconds, enough to trip
+//! the rate limits of a recursive resolver.  A `RateLimit` is a token bucket shared by every
+//! `Solver` it wraps and all their clones, so the limit holds across all the parallel workers:
+//!
+//! - `qps` queries per second are allowed on average,
+//! - up to `burst` queries can go at once after an idle period (`qps` rounded up by default).
+//!
+//! A query over the limit waits for its turn (reverse and forward lookups alike).  The number of
+//! queries delayed and the total time spent waiting are available with `throttled()` and
+//! `delay()`.
+//!
+//! The limiter should be put under the cache, if any, so that cached answers are not slowed down.
+//!
+//! Example:
+//! ```
+//! use dmarc_rs::ip::Ip;
+//! use dmarc_rs::ratelimit::RateLimit;
+//! use dmarc_rs::resolver::{res_init, ResType};
+//!
+//! let rl = RateLimit::new(100.).unwrap().with_burst(2);
+//! let res = rl.wrap(res_init(ResType::Null));
+//!
+//! for i in 1..=4 {
+//!     res.solve(&Ip::new(&format!("192.0.2.{}", i))).unwrap();
+//! }
+//! assert_eq!(2, rl.throttled());
+//! ```
+//!
+
+// Std library
+//
+use std::fmt::{Debug, Formatter};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+// Our crates
+//
+use crate::ip::Ip;
+use crate::resolver::{Resolver, SolveError, Solver};
+
+// External crates
+//
+use anyhow::{anyhow, Result};
+
+/// State of the bucket.
+///
+#[derive(Debug)]
+struct Bucket {
+    /// Available tokens, negative when queries are waiting
+    tokens: f64,
+    /// Last refill
+    last: Instant,
+    /// Number of queries delayed
+    throttled: usize,
+    /// Total time spent waiting
+    delay: Duration,
+}
+
+/// Token bucket limiting the number of queries per second.
+///
+#[derive(Clone)]
+pub struct RateLimit {
+    qps: f64,
+    burst: u32,
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+impl RateLimit {
+    /// Allow `qps` queries per second, which has to be positive.
+    ///
+    pub fn new(qps: f64) -> Result<Self> {
+        if !qps.is_finite() || qps <= 0. {
+            return Err(anyhow!("invalid rate {}", qps));
+        }
+        let burst = (qps.ceil() as u32).max(1);
+        Ok(RateLimit {
+            qps,
+            burst,
+            bucket: Arc::new(Mutex::new(Bucket {
+                tokens: burst as f64,
+                last: Instant::now(),
+                throttled: 0,
+                delay: Duration::ZERO,
+            })),
+        })
+    }
+
+    /// Allow `burst` queries at once (at least 1), the bucket starting full.
+    ///
+    pub fn with_burst(self, burst: u32) -> Self {
+        let burst = burst.max(1);
+        self.bucket.lock().unwrap().tokens = burst as f64;
+        RateLimit { burst, ..self }
+    }
+
+    /// Wrap a `Solver` so that its queries go through the limiter.
+    ///
+    pub fn wrap(&self, inner: Solver) -> Solver {
+        Solver::new(RateLimitResolver {
+            limit: self.clone(),
+            inner,
+        })
+    }
+
+    /// Number of queries which had to wait.
+    ///
+    pub fn throttled(&self) -> usize {
+        self.bucket.lock().unwrap().throttled
+    }
+
+    /// Total time spent waiting by all the queries.
+    ///
+    pub fn delay(&self) -> Duration {
+        self.bucket.lock().unwrap().delay
+    }
+
+    /// Take a token, waiting for it if needed.
+    ///
+    /// The token is reserved before sleeping so that waiting queries are served in turn without
+    /// holding the lock.
+    ///
+    fn acquire(&self) {
+        let wait = {
+            let mut b = self.bucket.lock().unwrap();
+            let now = Instant::now();
+            let elapsed = now.duration_since(b.last).as_secs_f64();
+
+            b.tokens = (b.tokens + elapsed * self.qps).min(self.burst as f64) - 1.;
+            b.last = now;
+            if b.tokens >= 0. {
+                return;
+            }
+            let wait = Duration::from_secs_f64(-b.tokens / self.qps);
+            b.throttled += 1;
+            b.delay += wait;
+            wait
+        };
+        log::trace!("throttling query for {:?}", wait);
+        sleep(wait);
+    }
+}
+
+impl Debug for RateLimit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ratelimit qps={} burst={}", self.qps, self.burst)
+    }
+}
+
+/// A resolver going through a `RateLimit`.
+///
+struct RateLimitResolver {
+    limit: RateLimit,
+    inner: Solver,
+}
+
+impl Resolver for RateLimitResolver {
+    /// Implement the `Resolver` trait.
+    ///
+    fn solve(&self, ip: &Ip) -> Result<Ip, SolveError> {
+        self.limit.acquire();
+        self.inner.solve(ip)
+    }
+
+    /// Implement the `Resolver` trait.
+    ///
+    fn forward(&self, name: &str) -> Result<Vec<IpAddr>, SolveError> {
+        self.limit.acquire();
+        self.inner.forward(name)
+    }
+}
+
+/// Parse a rate given on the command line, in queries per second.
+///
+/// Example:
+/// ```
+/// # use dmarc_rs::ratelimit::parse_rate;
+/// assert_eq!(0.5, parse_rate("0.5").unwrap());
+/// assert!(parse_rate("0").is_err());
+/// ```
+///
+pub fn parse_rate(s: &str) -> Result<f64> {
+    match s.parse::<f64>() {
+        Ok(qps) if qps.is_finite() && qps > 0. => Ok(qps),
+        _ => Err(anyhow!("invalid rate {}", s)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::{res_init, ResType};
+
+    use rstest::rstest;
+    use std::thread;
+
+    #[test]
+    fn test_burst() {
+        let rl = RateLimit::new(10.).unwrap();
+        let res = rl.wrap(res_init(ResType::Null));
+
+        for _ in 0..10 {
+            res.solve(&Ip::new("192.0.2.1")).unwrap();
+        }
+        assert_eq!(0, rl.throttled());
+        assert_eq!(Duration::ZERO, rl.delay());
+    }
+
+    #[test]
+    fn test_rate() {
+        let rl = RateLimit::new(5.).unwrap().with_burst(1);
+        let res = rl.wrap(res_init(ResType::Null));
+
+        // 1 at once then 1 every 200 ms, sleeping a bit longer does not give a token back
+        for _ in 0..3 {
+            res.solve(&Ip::new("192.0.2.1")).unwrap();
+        }
+        assert_eq!(2, rl.throttled());
+        assert!(rl.delay() > Duration::from_millis(150), "{:?}", rl.delay());
+        assert!(rl.delay() <= Duration::from_millis(400), "{:?}", rl.delay());
+    }
+
+    #[test]
+    fn test_shared() {
+        let rl = RateLimit::new(100.).unwrap().with_burst(1);
+        let res = rl.wrap(res_init(ResType::Null));
+
+        // 4 workers, 5 queries each, all sharing 100 qps
+        let start = Instant::now();
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let res = res.clone();
+                thread::spawn(move || {
+                    for _ in 0..5 {
+                        res.forward("192.0.2.1").unwrap();
+                    }
+                })
+            })
+            .collect();
+        for w in workers {
+            w.join().unwrap();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(190));
+        assert_eq!(19, rl.throttled());
+    }
+
+    #[rstest]
+    #[case(0.)]
+    #[case(-1.)]
+    #[case(f64::NAN)]
+    #[case(f64::INFINITY)]
+    fn test_new_nok(#[case] qps: f64) {
+        assert!(RateLimit::new(qps).is_err());
+    }
+
+    #[rstest]
+    #[case("10", Some(10.))]
+    #[case("0.5", Some(0.5))]
+    #[case("0", None)]
+    #[case("-3", None)]
+    #[case("foo", None)]
+    fn test_parse_rate(#[case] s: &str, #[case] r: Option<f64>) {
+        assert_eq!(r, parse_rate(s).ok());
+    }
+}
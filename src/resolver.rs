@@ -12,44 +12,151 @@
 //! Here we define 3 main modules:
 //!
 //! - `NullResolver`: this one just does a copy of the original IP address and the name is the same
-//! as the original IP.
+//!   as the original IP.
 //! - `FakeResolver`: this one is for testing mainly as it enables you to `load()` a set of preset
-//! values that will be matched and returned.
+//!   values that will be matched and returned (names or failures), from a hosts-style or TOML file.
 //! - `RealResolver`: this one is used in the general case (and is the default).  It uses the
-//! `lookup_addr()` from the `dns_lookup`  crate.
+//!   `getnameinfo()` from the `dns_lookup`  crate.
 //!
-//! **BUGS** this version only handle **one** name per IP (whatever is returned by `lookup_addr()`).
+//! `solve()` never panics, it returns either the resolved `Ip` or a `SolveError` telling whether
+//! there is no name for this IP (NXDOMAIN or no PTR record) or the lookup failed (timeout,
+//! SERVFAIL, etc.) and might succeed later.
+//!
+//! Resolvers also implement `forward()` to get the addresses of a name.  Any `Solver` can be
+//! wrapped with `fcrdns()` to check the names it returns with
+//! [forward-confirmed reverse DNS][fcrdns]: each name is looked up and the `verified` field of
+//! the `Ip` tells whether the original IP is among the addresses of at least one of them.  A PTR
+//! record is easy to fake for whoever owns the reverse zone, a name resolving back to the same IP
+//! is not.
+//!
+//! Several resolvers can be tried in turn with `chain()`, an IP going from one to the next until
+//! one of them finds a name (think preset answers, then the DNS, then the IP itself).
+//!
+//! Resolvers return all the names they know for an IP, the primary one first, but the system
+//! resolver only gives one name per IP (whatever is returned by `getnameinfo()`).  Use our DNS
+//! client (`crate::dns::DnsResolver`) to get every PTR record.
 //!
 //! [dep-inj]: https://en.wikipedia.org/wiki/Dependency_injection
 //! [jmmv]: https://jmmv.dev/2022/04/rust-traits-and-dependency-injection.html
+//! [fcrdns]: https://en.wikipedia.org/wiki/Forward-confirmed_reverse_DNS
 
 // Std Library
 //
-use std::fmt::{Debug, Formatter};
-use std::net::IpAddr;
+use std::collections::HashMap;
+use std::fmt::{Debug, Display, Formatter};
+use std::fs;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
 use std::sync::Arc;
 
 // Our crates
 //
 use crate::ip::Ip;
-use crate::iplist::IpList;
 
 // External crates
 //
+use anyhow::anyhow;
 #[cfg(not(test))]
-use dns_lookup::lookup_addr;
+use dns_lookup::{getaddrinfo, getnameinfo, AddrInfoHints, SockType};
+use dns_lookup::{LookupError, LookupErrorKind};
+use serde::Deserialize;
 
-// When testing, hide the external function to put our own.
+// When testing, hide the external functions to put our own.
 // It has to be here and not inside `mod tests` in order to properly shadow the real one.
 #[cfg(test)]
-fn lookup_addr(_ip: &IpAddr) -> anyhow::Result<String> {
-    Ok("foo.bar.invalid".to_string())
+fn getnameinfo(_sock: &SocketAddr, _flags: i32) -> Result<(String, String), LookupError> {
+    Ok(("foo.bar.invalid".to_string(), "0".to_string()))
+}
+
+#[cfg(test)]
+fn host_lookup(name: &str) -> Result<Vec<IpAddr>, LookupError> {
+    match name {
+        "foo.bar.invalid" => Ok(vec!["1.1.1.1".parse().unwrap()]),
+        _ => Err(LookupError::from(io::Error::new(
+            io::ErrorKind::TimedOut,
+            "timeout",
+        ))),
+    }
+}
+
+/// Why an IP could not be resolved.
+///
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum SolveError {
+    /// NXDOMAIN or no PTR record
+    NotFound,
+    /// Temporary failure (SERVFAIL, network error, etc.) with the reason
+    TempFail(String),
+    /// Temporary failure as no answer came in time
+    Timeout,
+}
+
+impl Display for SolveError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SolveError::NotFound => write!(f, "no PTR record"),
+            SolveError::TempFail(e) => write!(f, "temporary failure: {}", e),
+            SolveError::Timeout => write!(f, "temporary failure: timed out"),
+        }
+    }
+}
+
+impl std::error::Error for SolveError {}
+
+/// Get the PTR of an IP from the system resolver.
+///
+/// `getnameinfo()` returns the address itself when there is no PTR record, this is reported as
+/// `NotFound` like an explicit NXDOMAIN.
+///
+fn ptr_lookup(ip: &IpAddr) -> Result<String, SolveError> {
+    let sock = SocketAddr::new(*ip, 0);
+    ptr_answer(ip, getnameinfo(&sock, 0).map(|(host, _)| host))
+}
+
+/// Turn the answer from `getnameinfo()` into our own result.
+///
+pub(crate) fn ptr_answer(
+    ip: &IpAddr,
+    r: Result<String, LookupError>,
+) -> Result<String, SolveError> {
+    match r {
+        Ok(name) if name.is_empty() || name == ip.to_string() => Err(SolveError::NotFound),
+        Ok(name) => Ok(name),
+        Err(e) => Err(lookup_error(e)),
+    }
+}
+
+/// Get the addresses of a name from the system resolver.
+///
+#[cfg(not(test))]
+fn host_lookup(name: &str) -> Result<Vec<IpAddr>, LookupError> {
+    let hints = AddrInfoHints {
+        socktype: SockType::Stream.into(),
+        ..AddrInfoHints::default()
+    };
+    Ok(getaddrinfo(Some(name), None, Some(hints))?
+        .filter_map(|a| a.ok())
+        .map(|a| a.sockaddr.ip())
+        .collect())
+}
+
+/// Turn a system resolver error into our own.
+///
+fn lookup_error(e: LookupError) -> SolveError {
+    match e.kind() {
+        LookupErrorKind::NoName | LookupErrorKind::NoData => SolveError::NotFound,
+        _ => match io::Error::from(e) {
+            e if e.kind() == io::ErrorKind::TimedOut => SolveError::Timeout,
+            e => SolveError::TempFail(e.to_string()),
+        },
+    }
 }
 
 /// This trait will allow us to override the resolving function during tests & at run-time.
 /// It defines a single function that basically get the PTR value from an IP address.  It takes an
 /// `Ip` as defined in `crate::dmarc_rs` and returns the same with the `name` field changed to the
-/// corresponding resolved name.
+/// corresponding resolved name or the reason why it could not be resolved.
 ///
 /// Creating a different resolving mechanism is done simply by creating a new type and implementing
 /// the `Resolver` trait.
@@ -57,7 +164,14 @@ fn lookup_addr(_ip: &IpAddr) -> anyhow::Result<String> {
 pub trait Resolver {
     /// Get the name associated with the given `Ip`.
     ///
-    fn solve(&self, ip: &Ip) -> Ip;
+    fn solve(&self, ip: &Ip) -> Result<Ip, SolveError>;
+
+    /// Get the addresses associated with the given name, resolvers which can not do it need not
+    /// implement it.
+    ///
+    fn forward(&self, _name: &str) -> Result<Vec<IpAddr>, SolveError> {
+        Err(SolveError::NotFound)
+    }
 }
 
 /// Opaque type representing the implementation of the `Resolver` trait.
@@ -66,11 +180,37 @@ pub trait Resolver {
 pub struct Solver(Arc<dyn Resolver + Send + Sync + 'static>);
 
 impl Solver {
+    /// Wrap any implementation of the `Resolver` trait.
+    ///
+    pub fn new<R: Resolver + Send + Sync + 'static>(r: R) -> Self {
+        Solver(Arc::new(r))
+    }
+
     /// Calling the inner implementation of `solve()`
     ///
-    pub fn solve(&self, ip: &Ip) -> Ip {
+    pub fn solve(&self, ip: &Ip) -> Result<Ip, SolveError> {
         self.0.solve(ip)
     }
+
+    /// Calling the inner implementation of `forward()`
+    ///
+    pub fn forward(&self, name: &str) -> Result<Vec<IpAddr>, SolveError> {
+        self.0.forward(name)
+    }
+
+    /// Same as `solve()` but failures are recorded in the `error` field of the returned `Ip`
+    /// instead, without any name.
+    ///
+    pub fn solve_or_mark(&self, ip: &Ip) -> Ip {
+        match self.0.solve(ip) {
+            Ok(ip) => ip,
+            Err(e) => Ip {
+                names: vec![],
+                error: Some(e),
+                ..ip.clone()
+            },
+        }
+    }
 }
 
 /// Enum for selecting the different types of currently supported resolvers.
@@ -81,7 +221,7 @@ pub enum ResType {
     Fake,
     /// Returns name == ip
     Null,
-    /// The real thing, encapsulating `lookup_addr()`
+    /// The real thing, encapsulating `getnameinfo()`
     Real,
 }
 
@@ -110,10 +250,21 @@ impl Resolver for NullResolver {
     /// Implement the `Resolver` trait.
     ///
     #[inline]
-    fn solve(&self, ip: &Ip) -> Ip {
-        Ip {
-            ip: ip.ip,
-            name: ip.ip.to_string(),
+    fn solve(&self, ip: &Ip) -> Result<Ip, SolveError> {
+        Ok(Ip {
+            names: vec![ip.ip.to_string()],
+            error: None,
+            ..ip.clone()
+        })
+    }
+
+    /// Names are addresses, so is the answer.
+    ///
+    #[inline]
+    fn forward(&self, name: &str) -> Result<Vec<IpAddr>, SolveError> {
+        match name.parse::<IpAddr>() {
+            Ok(ip) => Ok(vec![ip]),
+            Err(_) => Err(SolveError::NotFound),
         }
     }
 }
@@ -124,34 +275,186 @@ impl Debug for NullResolver {
     }
 }
 
-/// This is the Fake resolver, for the moment it returns `some.host.invalid`  for all IP.
+/// One answer of the `FakeResolver` table.
+///
+type Answer = Result<Vec<String>, SolveError>;
+
+/// One `[[host]]` entry of a TOML table.
+///
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawHost {
+    ip: IpAddr,
+    #[serde(default)]
+    names: Vec<String>,
+    error: Option<String>,
+}
+
+/// The whole TOML file.
+///
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawHosts {
+    #[serde(default)]
+    host: Vec<RawHost>,
+}
+
+/// This is the Fake resolver, it returns `some.host.invalid` for all IP unless it was loaded with a
+/// table of preset answers.
+///
+/// Tables come either in the `/etc/hosts` format, one IP followed by its names per line with `#`
+/// starting comments and `NXDOMAIN` or `SERVFAIL` instead of the names for failures:
+///
+/// ```text
+/// 192.0.2.1     mx1.example.net www.example.net
+/// 192.0.2.2     NXDOMAIN
+/// 198.51.100.1  SERVFAIL
+/// ```
+///
+/// or in TOML (files ending in `.toml`), the error being either `nxdomain` or `tempfail`:
+///
+/// ```toml
+/// [[host]]
+/// ip = "192.0.2.1"
+/// names = ["mx1.example.net", "www.example.net"]
 ///
-pub struct FakeResolver();
+/// [[host]]
+/// ip = "192.0.2.2"
+/// error = "nxdomain"
+/// ```
+///
+/// IPs not in the table have no name and names resolve to the IPs they are listed for.
+///
+#[derive(Default)]
+pub struct FakeResolver {
+    table: Option<HashMap<IpAddr, Answer>>,
+}
 
 impl FakeResolver {
     /// Returns one instance.
     ///
     #[inline]
     pub(crate) fn init() -> Self {
-        FakeResolver {}
+        FakeResolver::default()
+    }
+
+    /// Load a table of answers from a file, see above for the formats.
+    ///
+    /// Example:
+    /// ```
+    /// # use dmarc_rs::ip::Ip;
+    /// # use dmarc_rs::resolver::{FakeResolver, Solver};
+    /// let res = Solver::new(FakeResolver::load("testdata/hosts").unwrap());
+    ///
+    /// let ip = res.solve(&Ip::new("192.0.2.10")).unwrap();
+    /// assert_eq!("mx1.example.net", ip.name());
+    /// ```
+    ///
+    pub fn load<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let s = fs::read_to_string(path).map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+        let r = match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => FakeResolver::from_toml(&s),
+            _ => FakeResolver::from_hosts(&s),
+        };
+        r.map_err(|e| anyhow!("{}: {}", path.display(), e))
+    }
+
+    /// Read a table in the `/etc/hosts` format.
+    ///
+    pub fn from_hosts(s: &str) -> anyhow::Result<Self> {
+        let mut table = HashMap::new();
+
+        for (n, line) in s.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default();
+            let mut fields = line.split_whitespace();
+            let ip = match fields.next() {
+                Some(ip) => ip
+                    .parse::<IpAddr>()
+                    .map_err(|e| anyhow!("line {}: {}: {}", n + 1, ip, e))?,
+                None => continue,
+            };
+            let names: Vec<String> = fields.map(|f| f.to_owned()).collect();
+            let answer = match names.as_slice() {
+                [] => return Err(anyhow!("line {}: no name for {}", n + 1, ip)),
+                [e] if e == "NXDOMAIN" => Err(SolveError::NotFound),
+                [e] if e == "SERVFAIL" => Err(SolveError::TempFail("SERVFAIL".into())),
+                _ => Ok(names),
+            };
+            table.insert(ip, answer);
+        }
+        Ok(FakeResolver { table: Some(table) })
+    }
+
+    /// Read a table in TOML.
+    ///
+    pub fn from_toml(s: &str) -> anyhow::Result<Self> {
+        let raw: RawHosts = toml::from_str(s)?;
+
+        let table = raw
+            .host
+            .into_iter()
+            .map(|h| {
+                let answer = match (h.error.as_deref(), h.names.is_empty()) {
+                    (None, false) => Ok(h.names),
+                    (Some("nxdomain"), true) => Err(SolveError::NotFound),
+                    (Some("tempfail"), true) => Err(SolveError::TempFail("SERVFAIL".into())),
+                    (None, true) => return Err(anyhow!("{}: no name nor error", h.ip)),
+                    (Some(e), true) => return Err(anyhow!("{}: unknown error {}", h.ip, e)),
+                    (Some(_), false) => return Err(anyhow!("{}: both names and error", h.ip)),
+                };
+                Ok((h.ip, answer))
+            })
+            .collect::<anyhow::Result<HashMap<_, _>>>()?;
+        Ok(FakeResolver { table: Some(table) })
     }
 }
 
 impl Resolver for FakeResolver {
     /// Implement the `Resolver` trait.
     ///
-    #[inline]
-    fn solve(&self, ip: &Ip) -> Ip {
-        Ip {
-            ip: ip.ip,
-            name: "some.host.invalid".to_string(),
+    fn solve(&self, ip: &Ip) -> Result<Ip, SolveError> {
+        let names = match &self.table {
+            Some(t) => t
+                .get(&ip.ip)
+                .cloned()
+                .unwrap_or(Err(SolveError::NotFound))?,
+            None => vec!["some.host.invalid".to_string()],
+        };
+        Ok(Ip {
+            names,
+            error: None,
+            ..ip.clone()
+        })
+    }
+
+    /// Look for the name in the table, `some.host.invalid` does not exist.
+    ///
+    fn forward(&self, name: &str) -> Result<Vec<IpAddr>, SolveError> {
+        let mut l: Vec<IpAddr> = self
+            .table
+            .iter()
+            .flatten()
+            .filter(|(_, a)| match a {
+                Ok(names) => names.iter().any(|n| n.eq_ignore_ascii_case(name)),
+                Err(_) => false,
+            })
+            .map(|(ip, _)| *ip)
+            .collect();
+        l.sort();
+        match l.is_empty() {
+            true => Err(SolveError::NotFound),
+            false => Ok(l),
         }
     }
 }
 
 impl Debug for FakeResolver {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.write_str("fakeresolver with some.host.invalid")
+        match &self.table {
+            Some(t) => write!(f, "fakeresolver with {} entries", t.len()),
+            None => f.write_str("fakeresolver with some.host.invalid"),
+        }
     }
 }
 
@@ -172,20 +475,162 @@ impl Resolver for RealResolver {
     /// Implement the `Resolver` trait.
     ///
     #[inline]
-    fn solve(&self, ip: &Ip) -> Ip {
-        Ip {
-            ip: ip.ip,
-            name: lookup_addr(&ip.ip).unwrap(),
-        }
+    fn solve(&self, ip: &Ip) -> Result<Ip, SolveError> {
+        Ok(Ip {
+            names: vec![ptr_lookup(&ip.ip)?],
+            error: None,
+            ..ip.clone()
+        })
+    }
+
+    /// Implement the `Resolver` trait.
+    ///
+    #[inline]
+    fn forward(&self, name: &str) -> Result<Vec<IpAddr>, SolveError> {
+        host_lookup(name).map_err(lookup_error)
     }
 }
 
 impl Debug for RealResolver {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        f.write_str("realresolver using lookup_addr")
+        f.write_str("realresolver using getnameinfo")
     }
 }
 
+/// This one wraps another resolver to verify its names with forward-confirmed reverse DNS.
+///
+pub struct FcrdnsResolver {
+    inner: Solver,
+}
+
+impl Resolver for FcrdnsResolver {
+    /// Resolve with the inner resolver then check whether one of the names points back to the IP.
+    ///
+    /// Failures of the forward lookups make the IP unverified, not the whole resolution fail.
+    /// IPs without names are not checked, neither are IPs "named" after an address like the null
+    /// resolver does.
+    ///
+    fn solve(&self, ip: &Ip) -> Result<Ip, SolveError> {
+        let ip = self.inner.solve(ip)?;
+        if ip.names.iter().all(|n| n.parse::<IpAddr>().is_ok()) {
+            return Ok(ip);
+        }
+        let verified = ip.names.iter().any(|n| match self.inner.forward(n) {
+            Ok(l) => l.contains(&ip.ip),
+            Err(_) => false,
+        });
+        Ok(Ip {
+            verified: Some(verified),
+            ..ip
+        })
+    }
+
+    /// Implement the `Resolver` trait.
+    ///
+    #[inline]
+    fn forward(&self, name: &str) -> Result<Vec<IpAddr>, SolveError> {
+        self.inner.forward(name)
+    }
+}
+
+impl Debug for FcrdnsResolver {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("fcrdnsresolver")
+    }
+}
+
+/// Wrap a `Solver` to verify the names it returns with forward-confirmed reverse DNS.
+///
+/// Example:
+/// ```rust
+/// # use dmarc_rs::ip::Ip;
+/// # use dmarc_rs::resolver::{fcrdns, FakeResolver, Solver};
+/// let hosts = FakeResolver::from_hosts("192.0.2.1 mx1.example.net\n").unwrap();
+/// let res = fcrdns(Solver::new(hosts));
+///
+/// let ip = res.solve(&Ip::new("192.0.2.1")).unwrap();
+/// assert_eq!(Some(true), ip.verified);
+/// ```
+///
+pub fn fcrdns(inner: Solver) -> Solver {
+    Solver(Arc::new(FcrdnsResolver { inner }))
+}
+
+/// This one tries several resolvers in turn until one of them gives an answer.
+///
+pub struct ChainResolver {
+    stages: Vec<Solver>,
+}
+
+impl ChainResolver {
+    /// Keep the first successful answer of `f` over the stages.
+    ///
+    /// When every stage fails, a temporary failure wins over "not found" so that an IP is not
+    /// declared without name (and cached as such) because a nameserver did not answer.
+    ///
+    fn first<T, F>(&self, f: F) -> Result<T, SolveError>
+    where
+        F: Fn(&Solver) -> Result<T, SolveError>,
+    {
+        let mut err = SolveError::NotFound;
+        for res in &self.stages {
+            match f(res) {
+                Ok(r) => return Ok(r),
+                Err(e @ (SolveError::TempFail(_) | SolveError::Timeout)) => {
+                    if err == SolveError::NotFound {
+                        err = e;
+                    }
+                }
+                Err(SolveError::NotFound) => (),
+            }
+        }
+        Err(err)
+    }
+}
+
+impl Resolver for ChainResolver {
+    /// Return the names given by the first stage having any.
+    ///
+    fn solve(&self, ip: &Ip) -> Result<Ip, SolveError> {
+        self.first(|res| match res.solve(ip)? {
+            ip if ip.names.is_empty() => Err(SolveError::NotFound),
+            ip => Ok(ip),
+        })
+    }
+
+    /// Return the addresses given by the first stage having any.
+    ///
+    fn forward(&self, name: &str) -> Result<Vec<IpAddr>, SolveError> {
+        self.first(|res| match res.forward(name)? {
+            l if l.is_empty() => Err(SolveError::NotFound),
+            l => Ok(l),
+        })
+    }
+}
+
+impl Debug for ChainResolver {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "chainresolver {} stages", self.stages.len())
+    }
+}
+
+/// Chain several `Solver`, each IP going through them in order until one finds a name.
+///
+/// Example:
+/// ```rust
+/// # use dmarc_rs::ip::Ip;
+/// # use dmarc_rs::resolver::{chain, res_init, FakeResolver, ResType, Solver};
+/// let hosts = FakeResolver::from_hosts("192.0.2.10 mx1.example.net\n").unwrap();
+/// let res = chain(vec![Solver::new(hosts), res_init(ResType::Null)]);
+///
+/// assert_eq!("mx1.example.net", res.solve(&Ip::new("192.0.2.10")).unwrap().name());
+/// assert_eq!("192.0.2.20", res.solve(&Ip::new("192.0.2.20")).unwrap().name());
+/// ```
+///
+pub fn chain(stages: Vec<Solver>) -> Solver {
+    Solver(Arc::new(ChainResolver { stages }))
+}
+
 /// Create an instance of the Solver type corresponding to one of the resolvers.
 ///
 /// Before using any of these resolver you have to instantiate one of them through `res_init()`.
@@ -198,10 +643,11 @@ impl Debug for RealResolver {
 /// let res = res_init(ResType::Real);
 ///
 /// let ip = Ip::new("1.1.1.1");
-/// // returns an IP
-/// let ip = res.solve(&ip);
-///
-/// println!("{:?}", ip.name);
+/// // returns an IP or why it could not be resolved
+/// match res.solve(&ip) {
+///     Ok(ip) => println!("{}", ip.name()),
+///     Err(e) => println!("{}", e),
+/// }
 /// // ==> should print "one.one.one.one"
 /// ```
 ///
@@ -214,9 +660,96 @@ pub fn res_init(t: ResType) -> Solver {
     }
 }
 
+/// Resolvers shared by the tests of the modules built on top of this one.
+///
+#[cfg(test)]
+pub(crate) mod stubs {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Count the lookups, NXDOMAIN for 192.0.2.0/24, SERVFAIL for 198.51.100.0/24.
+    ///
+    #[derive(Clone, Default)]
+    pub(crate) struct Counting(pub(crate) Arc<AtomicUsize>);
+
+    impl Counting {
+        /// Number of lookups so far.
+        ///
+        pub(crate) fn count(&self) -> usize {
+            self.0.load(Ordering::SeqCst)
+        }
+    }
+
+    impl Resolver for Counting {
+        fn solve(&self, ip: &Ip) -> Result<Ip, SolveError> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            match ip.ip.to_string() {
+                s if s.starts_with("192.0.2.") => Err(SolveError::NotFound),
+                s if s.starts_with("198.51.100.") => Err(SolveError::TempFail("SERVFAIL".into())),
+                _ => Ok(Ip {
+                    names: vec!["mx.example.net".into()],
+                    ..ip.clone()
+                }),
+            }
+        }
+
+        fn forward(&self, _name: &str) -> Result<Vec<IpAddr>, SolveError> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Ok(vec!["203.0.113.1".parse().unwrap()])
+        }
+    }
+
+    /// Resolver failing with the given reason.
+    ///
+    pub(crate) struct Failing(pub(crate) &'static str);
+
+    impl Resolver for Failing {
+        fn solve(&self, _ip: &Ip) -> Result<Ip, SolveError> {
+            Err(SolveError::TempFail(self.0.into()))
+        }
+
+        fn forward(&self, _name: &str) -> Result<Vec<IpAddr>, SolveError> {
+            Err(SolveError::TempFail(self.0.into()))
+        }
+    }
+
+    /// Resolver never answering in time.
+    ///
+    pub(crate) struct TimingOut;
+
+    impl Resolver for TimingOut {
+        fn solve(&self, _ip: &Ip) -> Result<Ip, SolveError> {
+            Err(SolveError::Timeout)
+        }
+
+        fn forward(&self, _name: &str) -> Result<Vec<IpAddr>, SolveError> {
+            Err(SolveError::Timeout)
+        }
+    }
+
+    /// Resolver panicking on 192.0.2.0/24, names are IPs otherwise.
+    ///
+    pub(crate) struct Panicking;
+
+    impl Resolver for Panicking {
+        fn solve(&self, ip: &Ip) -> Result<Ip, SolveError> {
+            match ip.ip.to_string() {
+                s if s.starts_with("192.0.2.") => panic!("bad resolver"),
+                s => Ok(Ip {
+                    names: vec![s],
+                    ..ip.clone()
+                }),
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use super::stubs::Failing;
     use super::*;
+    use crate::iplist::IpList;
     use std::any::{Any, TypeId};
 
     use rstest::rstest;
@@ -236,7 +769,7 @@ mod tests {
         let a = res_init(ResType::Null);
 
         let ip = Ip::new("1.1.1.1");
-        assert_eq!("1.1.1.1", a.solve(&ip).name);
+        assert_eq!("1.1.1.1", a.solve(&ip).unwrap().name());
     }
 
     #[test]
@@ -244,7 +777,7 @@ mod tests {
         let a = res_init(ResType::Real);
 
         let ip = Ip::new("1.1.1.1");
-        assert_eq!("foo.bar.invalid", a.solve(&ip).name);
+        assert_eq!("foo.bar.invalid", a.solve(&ip).unwrap().name());
     }
 
     #[test]
@@ -252,6 +785,183 @@ mod tests {
         let ipl = IpList::from([("1.1.1.1", "")]);
         let res = res_init(ResType::Fake);
 
-        assert_eq!("some.host.invalid", res.solve(&ipl[0]).name);
+        assert_eq!("some.host.invalid", res.solve(&ipl[0]).unwrap().name());
+    }
+
+    #[rstest]
+    #[case("192.0.2.10", Ok(vec!["mx1.example.net"]))]
+    #[case("2001:db8:1:2::25", Ok(vec!["mx6.example.net", "relay.example.net"]))]
+    #[case("198.51.100.7", Err(SolveError::NotFound))]
+    #[case("198.51.100.8", Err(SolveError::TempFail("SERVFAIL".into())))]
+    #[case("203.0.113.1", Err(SolveError::NotFound))]
+    fn test_fake_load(#[case] ip: &str, #[case] r: Result<Vec<&str>, SolveError>) {
+        let r = r.map(|l| l.into_iter().map(String::from).collect::<Vec<_>>());
+        for f in ["testdata/hosts", "testdata/hosts.toml"] {
+            let res = Solver::new(FakeResolver::load(f).unwrap());
+
+            let names = res.solve(&Ip::new(ip)).map(|ip| ip.names);
+            assert_eq!(r, names, "{}", f);
+        }
+    }
+
+    #[test]
+    fn test_fake_forward() {
+        let res = Solver::new(FakeResolver::load("testdata/hosts").unwrap());
+
+        assert_eq!(
+            Ok(vec!["192.0.2.10".parse::<IpAddr>().unwrap()]),
+            res.forward("MX1.example.net")
+        );
+        assert_eq!(
+            Err(SolveError::NotFound),
+            res.forward("spoofed.example.com")
+        );
+
+        let res = fcrdns(res);
+        assert_eq!(
+            Some(true),
+            res.solve(&Ip::new("192.0.2.10")).unwrap().verified
+        );
+    }
+
+    #[rstest]
+    #[case("192.0.2.1")]
+    #[case("192.0.2.333 foo.example.net")]
+    #[case("foo.example.net 192.0.2.1")]
+    fn test_fake_hosts_nok(#[case] s: &str) {
+        assert!(FakeResolver::from_hosts(s).is_err());
+    }
+
+    #[rstest]
+    #[case("[[host]]\nip = \"192.0.2.1\"\n")]
+    #[case("[[host]]\nip = \"192.0.2.1\"\nerror = \"refused\"\n")]
+    #[case("[[host]]\nip = \"192.0.2.1\"\nerror = \"nxdomain\"\nnames = [\"a\"]\n")]
+    #[case("[[host]]\nip = \"192.0.2.1\"\nname = \"a\"\n")]
+    fn test_fake_toml_nok(#[case] s: &str) {
+        assert!(FakeResolver::from_toml(s).is_err());
+    }
+
+    #[test]
+    fn test_fake_load_nonexistent() {
+        assert!(FakeResolver::load("/nonexistent").is_err());
+    }
+
+    #[test]
+    fn test_ptr_answer() {
+        let ip = "192.0.2.1".parse::<IpAddr>().unwrap();
+
+        assert_eq!(
+            Ok("foo.example.net".to_string()),
+            ptr_answer(&ip, Ok("foo.example.net".into()))
+        );
+        assert_eq!(
+            Err(SolveError::NotFound),
+            ptr_answer(&ip, Ok("192.0.2.1".into()))
+        );
+        let e = LookupError::from(io::Error::new(io::ErrorKind::TimedOut, "timeout"));
+        assert_eq!(Err(SolveError::Timeout), ptr_answer(&ip, Err(e)));
+        let e = LookupError::from(io::Error::other("EAI_AGAIN"));
+        assert_eq!(
+            Err(SolveError::TempFail("EAI_AGAIN".into())),
+            ptr_answer(&ip, Err(e))
+        );
+    }
+
+    #[test]
+    fn test_solve_or_mark() {
+        let res = Solver(Arc::new(Failing("SERVFAIL")));
+        let ip = res.solve_or_mark(&Ip::try_from(("192.0.2.1", "stale.example.net")).unwrap());
+        assert!(ip.names.is_empty());
+        assert_eq!(Some(SolveError::TempFail("SERVFAIL".into())), ip.error);
+        assert_eq!("temporary failure: SERVFAIL", ip.error.unwrap().to_string());
+    }
+
+    #[rstest]
+    #[case(ResType::Real, "1.1.1.1", Some(true))]
+    #[case(ResType::Real, "192.0.2.1", Some(false))]
+    #[case(ResType::Null, "192.0.2.1", None)]
+    #[case(ResType::Fake, "192.0.2.1", Some(false))]
+    fn test_fcrdns(#[case] t: ResType, #[case] ip: &str, #[case] r: Option<bool>) {
+        let res = fcrdns(res_init(t));
+
+        let ip = res.solve(&Ip::new(ip)).unwrap();
+        assert_eq!(r, ip.verified);
+    }
+
+    #[test]
+    fn test_chain() {
+        let hosts = FakeResolver::load("testdata/hosts").unwrap();
+        let res = chain(vec![Solver::new(hosts), res_init(ResType::Null)]);
+
+        assert_eq!(
+            "mx1.example.net",
+            res.solve(&Ip::new("192.0.2.10")).unwrap().name()
+        );
+        // NXDOMAIN and SERVFAIL in the table both go to the next stage
+        assert_eq!(
+            "198.51.100.7",
+            res.solve(&Ip::new("198.51.100.7")).unwrap().name()
+        );
+        assert_eq!(
+            "198.51.100.8",
+            res.solve(&Ip::new("198.51.100.8")).unwrap().name()
+        );
+        assert_eq!(
+            vec!["192.0.2.20".parse::<IpAddr>().unwrap()],
+            res.forward("mx2.example.net").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_chain_errors() {
+        let hosts = Solver::new(FakeResolver::load("testdata/hosts").unwrap());
+
+        let res = chain(vec![hosts.clone(), Solver(Arc::new(Failing("SERVFAIL")))]);
+        assert_eq!(
+            Err(SolveError::TempFail("SERVFAIL".into())),
+            res.solve(&Ip::new("192.0.2.99"))
+        );
+
+        let res = chain(vec![hosts.clone(), hosts]);
+        assert_eq!(Err(SolveError::NotFound), res.solve(&Ip::new("192.0.2.99")));
+        assert_eq!(Err(SolveError::NotFound), chain(vec![]).forward("foo"));
+    }
+
+    #[test]
+    fn test_fcrdns_several_names() {
+        struct Multi;
+
+        impl Resolver for Multi {
+            fn solve(&self, ip: &Ip) -> Result<Ip, SolveError> {
+                Ok(Ip {
+                    names: vec!["spoofed.example.com".into(), "mx.example.net".into()],
+                    ..ip.clone()
+                })
+            }
+
+            fn forward(&self, name: &str) -> Result<Vec<IpAddr>, SolveError> {
+                match name {
+                    "mx.example.net" => Ok(vec!["192.0.2.1".parse().unwrap()]),
+                    _ => Err(SolveError::NotFound),
+                }
+            }
+        }
+
+        let res = fcrdns(Solver(Arc::new(Multi)));
+        assert_eq!(
+            Some(true),
+            res.solve(&Ip::new("192.0.2.1")).unwrap().verified
+        );
+        assert_eq!(
+            Some(false),
+            res.solve(&Ip::new("192.0.2.2")).unwrap().verified
+        );
+    }
+
+    #[test]
+    fn test_fcrdns_failure() {
+        let res = fcrdns(Solver(Arc::new(Failing("SERVFAIL"))));
+
+        assert!(res.solve(&Ip::new("192.0.2.1")).is_err());
     }
 }
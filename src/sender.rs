@@ -0,0 +1,362 @@
+//! Module implementing known-sender classification.
+//!
+//! A rules file maps source IPs to named services ("Google Workspace", "our outbound relays",
+//! etc.) so that reports show who is sending on your behalf instead of raw addresses.  The file
+//! is in TOML, each `[[sender]]` table giving a name and any number of criteria:
+//!
+//! - `cidr`: networks the source IP must be in,
+//! - `ptr`: suffixes of one of the resolved names (`google.com` matches `mail-ot1.google.com` and
+//!   `google.com` itself),
+//! - `regex`: regular expressions one of the resolved names must match,
+//! - `dkim`: domains (or parent domains) of a **passing** DKIM signature (the `d=` parameter).
+//!
+//! A sender matches if any of its criteria does and senders are tried in the order of the file,
+//! the first match wins.  Names and domains are compared without regard to case.
+//!
+//! Example:
+//! ```
+//! use dmarc_rs::ip::Ip;
+//! use dmarc_rs::sender::Senders;
+//!
+//! let rules: Senders = r#"
+//! [[sender]]
+//! name = "Our relays"
+//! cidr = ["192.0.2.0/24"]
+//!
+//! [[sender]]
+//! name = "Google Workspace"
+//! ptr = ["google.com"]
+//! dkim = ["google.com"]
+//! "#.parse().unwrap();
+//!
+//! let ip = Ip::try_from(("192.0.2.25", "")).unwrap();
+//! assert_eq!(Some("Our relays"), rules.lookup(&ip, &[]));
+//! ```
+//!
+
+// Std library
+//
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+// Our crates
+//
+use crate::cidr::Cidr;
+use crate::ip::Ip;
+use crate::types::{DKIMResult, Record};
+
+// External crates
+//
+use anyhow::{anyhow, Result};
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+
+/// One `[[sender]]` entry as found in the file.
+///
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawSender {
+    name: String,
+    #[serde(default)]
+    cidr: Vec<String>,
+    #[serde(default)]
+    ptr: Vec<String>,
+    #[serde(default)]
+    regex: Vec<String>,
+    #[serde(default)]
+    dkim: Vec<String>,
+}
+
+/// The whole file.
+///
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawFile {
+    #[serde(default)]
+    sender: Vec<RawSender>,
+}
+
+/// A known sender with its compiled criteria.
+///
+#[derive(Clone, Debug)]
+pub struct Sender {
+    /// Label displayed for matching records
+    pub name: String,
+    /// Networks
+    cidr: Vec<Cidr>,
+    /// Name suffixes, lowercase and without leading dot
+    ptr: Vec<String>,
+    /// Name patterns
+    regex: Vec<Regex>,
+    /// DKIM signing domains, lowercase
+    dkim: Vec<String>,
+}
+
+impl Sender {
+    /// Check one IP and its passing DKIM domains against our criteria.
+    ///
+    fn matches(&self, ip: &Ip, dkim: &[&str]) -> bool {
+        let names: Vec<String> = ip
+            .names
+            .iter()
+            .map(|n| n.trim_end_matches('.').to_lowercase())
+            .collect();
+
+        self.cidr.iter().any(|c| c.contains(&ip.ip))
+            || names
+                .iter()
+                .any(|n| self.ptr.iter().any(|s| in_domain(n, s)))
+            || names
+                .iter()
+                .any(|n| self.regex.iter().any(|r| r.is_match(n)))
+            || dkim
+                .iter()
+                .any(|d| self.dkim.iter().any(|s| in_domain(&d.to_lowercase(), s)))
+    }
+}
+
+/// A set of known senders, in order.
+///
+#[derive(Clone, Debug, Default)]
+pub struct Senders {
+    list: Vec<Sender>,
+}
+
+impl Senders {
+    /// Create an empty set, nothing will match.
+    ///
+    #[inline]
+    pub fn new() -> Self {
+        Senders::default()
+    }
+
+    /// Load a rules file.
+    ///
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let s = fs::read_to_string(path).map_err(|e| anyhow!("{}: {}", path.display(), e))?;
+        s.parse().map_err(|e| anyhow!("{}: {}", path.display(), e))
+    }
+
+    /// Number of senders.
+    ///
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    /// Is there any sender?
+    ///
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    /// Find the first sender matching an IP (with any of its resolved names) and the domains of
+    /// the passing DKIM signatures.
+    ///
+    pub fn lookup(&self, ip: &Ip, dkim: &[&str]) -> Option<&str> {
+        self.list
+            .iter()
+            .find(|s| s.matches(ip, dkim))
+            .map(|s| s.name.as_str())
+    }
+
+    /// Find the first sender matching a record, `ip` being its (resolved) source IP.
+    ///
+    pub fn classify(&self, ip: &Ip, r: &Record) -> Option<&str> {
+        let dkim: Vec<&str> = r
+            .auth_results
+            .iter()
+            .filter_map(|a| a.dkim.as_ref())
+            .flatten()
+            .filter(|d| matches!(d.result, DKIMResult::pass))
+            .map(|d| d.domain.as_str())
+            .collect();
+        self.lookup(ip, &dkim)
+    }
+}
+
+/// Parse the content of a rules file.
+///
+impl FromStr for Senders {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let raw: RawFile = toml::from_str(s)?;
+
+        let list = raw
+            .sender
+            .into_iter()
+            .map(|r| {
+                let cidr = r
+                    .cidr
+                    .iter()
+                    .map(|c| c.parse::<Cidr>())
+                    .collect::<Result<Vec<_>>>()
+                    .map_err(|e| anyhow!("sender {}: {}", r.name, e))?;
+                let regex = r
+                    .regex
+                    .iter()
+                    .map(|s| RegexBuilder::new(s).case_insensitive(true).build())
+                    .collect::<std::result::Result<Vec<_>, _>>()
+                    .map_err(|e| anyhow!("sender {}: {}", r.name, e))?;
+                Ok(Sender {
+                    cidr,
+                    ptr: r.ptr.iter().map(|s| domain(s)).collect(),
+                    regex,
+                    dkim: r.dkim.iter().map(|s| domain(s)).collect(),
+                    name: r.name,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Senders { list })
+    }
+}
+
+/// Normalize a domain from the rules file.
+///
+fn domain(s: &str) -> String {
+    s.trim().trim_matches('.').to_lowercase()
+}
+
+/// Is `name` equal to `dom` or one of its subdomains?
+///
+fn in_domain(name: &str, dom: &str) -> bool {
+    match name.strip_suffix(dom) {
+        Some("") => true,
+        Some(rest) => rest.ends_with('.'),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{load, EXAMPLE};
+    use crate::types::Feedback;
+
+    use rstest::rstest;
+
+    const RULES: &str = r#"
+[[sender]]
+name = "Our relays"
+cidr = ["192.0.2.0/24", "2001:db8:1::/48"]
+
+[[sender]]
+name = "Google Workspace"
+ptr = [".google.com"]
+dkim = ["google.com"]
+
+[[sender]]
+name = "Mailchimp"
+regex = ['^mail\d+\.mcsv\.net$']
+dkim = ["mcsv.net"]
+"#;
+
+    #[rstest]
+    #[case("192.0.2.10", "", &[], Some("Our relays"))]
+    #[case("2001:db8:1:2::25", "", &[], Some("Our relays"))]
+    #[case("198.51.100.7", "mail-ot1.google.com", &[], Some("Google Workspace"))]
+    #[case("198.51.100.7", "Google.com.", &[], Some("Google Workspace"))]
+    #[case("198.51.100.7", "notgoogle.com", &[], None)]
+    #[case("198.51.100.7", "MAIL12.mcsv.net", &[], Some("Mailchimp"))]
+    #[case("198.51.100.7", "mail.mcsv.net", &[], None)]
+    #[case("198.51.100.7", "", &["news.mcsv.net"], Some("Mailchimp"))]
+    #[case("198.51.100.7", "", &["example.net"], None)]
+    #[case("192.0.2.10", "mail-ot1.google.com", &[], Some("Our relays"))]
+    fn test_lookup(
+        #[case] ip: &str,
+        #[case] name: &str,
+        #[case] dkim: &[&str],
+        #[case] r: Option<&str>,
+    ) {
+        let rules: Senders = RULES.parse().unwrap();
+        assert_eq!(r, rules.lookup(&Ip::try_from((ip, name)).unwrap(), dkim));
+    }
+
+    #[rstest]
+    #[case("[[sender]]\nname = \"x\"\ncidr = [\"192.0.2.0/33\"]\n")]
+    #[case("[[sender]]\nname = \"x\"\nregex = [\"(\"]\n")]
+    #[case("[[sender]]\nname = \"x\"\nfoo = [\"bar\"]\n")]
+    #[case("[[sender]]\ncidr = [\"192.0.2.0/24\"]\n")]
+    fn test_parse_nok(#[case] s: &str) {
+        assert!(s.parse::<Senders>().is_err());
+    }
+
+    #[test]
+    fn test_parse_nok_cidr() {
+        let e = "[[sender]]\nname = \"x\"\ncidr = [\"192.0.2.0/33\"]\n"
+            .parse::<Senders>()
+            .unwrap_err();
+        assert_eq!(
+            "sender x: Invalid IPv4 prefix length 33 in 192.0.2.0/33",
+            e.to_string()
+        );
+    }
+
+    #[test]
+    fn test_lookup_names() {
+        let rules: Senders = RULES.parse().unwrap();
+        let ip = Ip::try_from((
+            "198.51.100.7",
+            ["mx.example.org", "mail-ot1.google.com"].as_slice(),
+        ))
+        .unwrap();
+
+        assert_eq!(Some("Google Workspace"), rules.lookup(&ip, &[]));
+    }
+
+    #[test]
+    fn test_empty() {
+        let rules: Senders = "".parse().unwrap();
+
+        assert!(rules.is_empty());
+        assert_eq!(None, rules.lookup(&Ip::new("192.0.2.1"), &[]));
+    }
+
+    #[test]
+    fn test_classify() {
+        let fb: Feedback = load(EXAMPLE);
+        let rules = Senders::load("testdata/senders.toml").unwrap();
+
+        let r: Vec<Option<&str>> = fb[0]
+            .record
+            .iter()
+            .map(|r| {
+                rules.classify(
+                    &Ip::try_from((r.row.source_ip.to_string().as_str(), "")).unwrap(),
+                    r,
+                )
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                Some("example.net signed"),
+                None,
+                Some("example.net signed"),
+                Some("IPv6 relays"),
+                None
+            ],
+            r
+        );
+    }
+
+    #[test]
+    fn test_load() {
+        let rules = Senders::load("testdata/senders.toml").unwrap();
+        assert_eq!(3, rules.len());
+
+        assert!(Senders::load("/nonexistent").is_err());
+    }
+
+    #[rstest]
+    #[case("google.com", "google.com", true)]
+    #[case("mx.google.com", "google.com", true)]
+    #[case("notgoogle.com", "google.com", false)]
+    #[case("com", "google.com", false)]
+    fn test_in_domain(#[case] name: &str, #[case] dom: &str, #[case] r: bool) {
+        assert_eq!(r, in_domain(name, dom));
+    }
+}
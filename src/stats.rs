@@ -0,0 +1,297 @@
+//! Module computing authentication pass-rate statistics over reports.
+//!
+//! All numbers are weighted by the `count` field of each row, i.e. they are expressed in number
+//! of messages and not in number of records.  We compute:
+//!
+//! - the DMARC pass rate (DKIM *or* SPF aligned pass, from `policy_evaluated`),
+//! - the raw DKIM and SPF pass rates (from `auth_results`),
+//! - the share of messages that got quarantined or rejected.
+//!
+//! These are accumulated per reporter (`org_name`), per `header_from` domain and overall.
+//!
+//! Example:
+//! ```
+//! # use dmarc_rs::stats::Summary;
+//! # use dmarc_rs::types::Feedback;
+//! let reports: Feedback = vec![];
+//!
+//! let sum: Summary = reports.iter().collect();
+//! assert_eq!(0, sum.overall.total);
+//! ```
+//!
+
+// Std library
+//
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+// Our crates
+//
+use crate::types::{DKIMResult, DMARCResult, Disposition, Record, Report, SPFResult};
+
+/// Count-weighted counters for a set of records.
+///
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Stats {
+    /// Total number of messages
+    pub total: u64,
+    /// Messages passing DMARC (aligned DKIM or aligned SPF)
+    pub dmarc_pass: u64,
+    /// Messages with at least one passing DKIM signature
+    pub dkim_pass: u64,
+    /// Messages with at least one passing SPF check
+    pub spf_pass: u64,
+    /// Messages quarantined
+    pub quarantine: u64,
+    /// Messages rejected
+    pub reject: u64,
+}
+
+impl Stats {
+    /// Create an empty set of counters.
+    ///
+    #[inline]
+    pub fn new() -> Self {
+        Stats::default()
+    }
+
+    /// Account for one record, using its `count` as weight.
+    ///
+    pub fn add(&mut self, r: &Record) {
+        let count = u64::from(r.row.count);
+        let pe = &r.row.policy_evaluated;
+
+        self.total += count;
+        if matches!(pe.dkim, DMARCResult::pass) || matches!(pe.spf, DMARCResult::pass) {
+            self.dmarc_pass += count;
+        }
+        let dkim = r.auth_results.iter().any(|a| match &a.dkim {
+            Some(l) => l.iter().any(|d| matches!(d.result, DKIMResult::pass)),
+            None => false,
+        });
+        if dkim {
+            self.dkim_pass += count;
+        }
+        let spf = r
+            .auth_results
+            .iter()
+            .any(|a| a.spf.iter().any(|s| matches!(s.result, SPFResult::pass)));
+        if spf {
+            self.spf_pass += count;
+        }
+        match pe.disposition {
+            Disposition::quarantine => self.quarantine += count,
+            Disposition::reject => self.reject += count,
+            Disposition::none => (),
+        }
+    }
+
+    /// Add the counters from another set.
+    ///
+    pub fn merge(&mut self, other: &Stats) {
+        self.total += other.total;
+        self.dmarc_pass += other.dmarc_pass;
+        self.dkim_pass += other.dkim_pass;
+        self.spf_pass += other.spf_pass;
+        self.quarantine += other.quarantine;
+        self.reject += other.reject;
+    }
+
+    /// Percentage of `n` over the total, 0 if there is nothing.
+    ///
+    fn rate(&self, n: u64) -> f64 {
+        match self.total {
+            0 => 0.0,
+            t => (n as f64 * 100.0) / t as f64,
+        }
+    }
+
+    /// DMARC pass rate in percent.
+    ///
+    #[inline]
+    pub fn dmarc_rate(&self) -> f64 {
+        self.rate(self.dmarc_pass)
+    }
+
+    /// DKIM pass rate in percent.
+    ///
+    #[inline]
+    pub fn dkim_rate(&self) -> f64 {
+        self.rate(self.dkim_pass)
+    }
+
+    /// SPF pass rate in percent.
+    ///
+    #[inline]
+    pub fn spf_rate(&self) -> f64 {
+        self.rate(self.spf_pass)
+    }
+
+    /// Share of quarantined messages in percent.
+    ///
+    #[inline]
+    pub fn quarantine_rate(&self) -> f64 {
+        self.rate(self.quarantine)
+    }
+
+    /// Share of rejected messages in percent.
+    ///
+    #[inline]
+    pub fn reject_rate(&self) -> f64 {
+        self.rate(self.reject)
+    }
+}
+
+/// Display one line of statistics, without the key.
+///
+impl Display for Stats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:>8} {:>6.1}% {:>6.1}% {:>6.1}% {:>6.1}% {:>6.1}%",
+            self.total,
+            self.dmarc_rate(),
+            self.dkim_rate(),
+            self.spf_rate(),
+            self.quarantine_rate(),
+            self.reject_rate()
+        )
+    }
+}
+
+/// Statistics broken down per reporter, per `header_from` domain and overall.
+///
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Summary {
+    /// Everything
+    pub overall: Stats,
+    /// Per reporting organisation
+    pub reporters: BTreeMap<String, Stats>,
+    /// Per `header_from` domain
+    pub domains: BTreeMap<String, Stats>,
+}
+
+impl Summary {
+    /// Create an empty summary.
+    ///
+    #[inline]
+    pub fn new() -> Self {
+        Summary::default()
+    }
+
+    /// Account for all the records of one report.
+    ///
+    pub fn add_report(&mut self, rep: &Report) {
+        let org = &rep.report_metadata.org_name;
+
+        for r in rep.record.iter() {
+            let mut s = Stats::new();
+            s.add(r);
+
+            self.overall.merge(&s);
+            self.reporters.entry(org.to_owned()).or_default().merge(&s);
+            self.domains
+                .entry(r.identifiers.header_from.to_owned())
+                .or_default()
+                .merge(&s);
+        }
+    }
+}
+
+/// Build a `Summary` from an iterator of reports.
+///
+impl<'a> FromIterator<&'a Report> for Summary {
+    fn from_iter<T: IntoIterator<Item = &'a Report>>(iter: T) -> Self {
+        let mut sum = Summary::new();
+        for rep in iter {
+            sum.add_report(rep);
+        }
+        sum
+    }
+}
+
+/// Display the whole summary as three tables.
+///
+impl Display for Summary {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let header = format!(
+            "{:<30} {:>8} {:>7} {:>7} {:>7} {:>7} {:>7}",
+            "", "Count", "DMARC", "DKIM", "SPF", "Quar.", "Reject"
+        );
+
+        writeln!(f, "Per reporter:")?;
+        writeln!(f, "{}", header)?;
+        for (k, s) in self.reporters.iter() {
+            writeln!(f, "{:<30} {}", k, s)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "Per domain:")?;
+        writeln!(f, "{}", header)?;
+        for (k, s) in self.domains.iter() {
+            writeln!(f, "{:<30} {}", k, s)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "{:<30} {}", "Overall", self.overall)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{load, GOOGLE};
+    use crate::types::Feedback;
+
+    #[test]
+    fn test_stats_empty() {
+        let s = Stats::new();
+
+        assert_eq!(0, s.total);
+        assert_eq!(0.0, s.dmarc_rate());
+    }
+
+    #[test]
+    fn test_stats_add() {
+        let fb: Feedback = load(GOOGLE);
+        let mut s = Stats::new();
+
+        fb[0].record.iter().for_each(|r| s.add(r));
+        assert_eq!(2, s.total);
+        assert_eq!(0, s.dmarc_pass);
+        assert_eq!(0, s.dkim_pass);
+        assert_eq!(2, s.spf_pass);
+        assert_eq!(100.0, s.spf_rate());
+        assert_eq!(0.0, s.reject_rate());
+    }
+
+    #[test]
+    fn test_stats_merge() {
+        let mut a = Stats {
+            total: 4,
+            dmarc_pass: 2,
+            ..Default::default()
+        };
+        let b = Stats {
+            total: 4,
+            dmarc_pass: 4,
+            reject: 1,
+            ..Default::default()
+        };
+
+        a.merge(&b);
+        assert_eq!(8, a.total);
+        assert_eq!(75.0, a.dmarc_rate());
+        assert_eq!(12.5, a.reject_rate());
+    }
+
+    #[test]
+    fn test_summary() {
+        let fb: Feedback = load(GOOGLE);
+        let sum: Summary = fb.iter().collect();
+
+        assert_eq!(2, sum.overall.total);
+        assert_eq!(1, sum.reporters.len());
+        assert_eq!(2, sum.reporters["google.com"].total);
+        assert_eq!(2, sum.domains["keltia.net"].spf_pass);
+        assert!(sum.to_string().contains("Overall"));
+    }
+}
@@ -0,0 +1,26 @@
+//! Fixtures shared by the tests of the library and of `dmarc-cat`.
+//!
+//! The binary includes this file with `#[path]` so the helpers do not name any type of the
+//! library, the caller gives the type to load.
+//!
+
+// Std library
+//
+use std::fs;
+
+// External crates
+//
+use serde::de::DeserializeOwned;
+
+/// Report from Google with 2 records.
+pub const GOOGLE: &str = "testdata/google.com!keltia.net!1538438400!1538524799.xml";
+
+/// Report for example.net with 5 records from documentation IPs.
+pub const EXAMPLE: &str = "testdata/example.com!example.net!1538438400!1538524799.xml";
+
+/// Load one of the XML reports above.
+///
+pub fn load<T: DeserializeOwned>(file: &str) -> T {
+    let xml = fs::read_to_string(file).unwrap();
+    serde_xml_rs::from_str(&xml).unwrap()
+}
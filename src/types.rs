@@ -10,10 +10,10 @@ use std::net::IpAddr;
 
 // External crates
 //
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 /// Date range.
-#[derive(Debug, Default, Deserialize)]
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct DateRange {
     /// Start of date period
     pub begin: u32,
@@ -22,7 +22,7 @@ pub struct DateRange {
 }
 
 /// Report metadata.
-#[derive(Debug, Default, Deserialize)]
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct ReportMetadata {
     /// Organisation name
     pub org_name: String,
@@ -40,7 +40,7 @@ pub struct ReportMetadata {
 
 /// Alignment (strict or relaxed) for DKIM and SPF.
 #[allow(non_camel_case_types)]
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub enum Alignment {
     r,
     s,
@@ -48,7 +48,7 @@ pub enum Alignment {
 
 /// The policy actions specified by p and sp in the DMARC record.
 #[allow(non_camel_case_types)]
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub enum Disposition {
     none,
     quarantine,
@@ -56,7 +56,7 @@ pub enum Disposition {
 }
 
 /// The DMARC policy that applied to the messages in this report, as published in the DNS
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct PolicyPublished {
     /// The domain at which the DMARC record was found.
     pub domain: String,
@@ -76,7 +76,7 @@ pub struct PolicyPublished {
 
 /// The DMARC-aligned authentication result
 #[allow(non_camel_case_types)]
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub enum DMARCResult {
     fail,
     pass,
@@ -84,7 +84,7 @@ pub enum DMARCResult {
 
 /// Reasons that may affect DMARC disposition or execution thereof
 #[allow(non_camel_case_types)]
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub enum PolicyOverride {
     forwarded,
     sampled_out,
@@ -96,7 +96,7 @@ pub enum PolicyOverride {
 
 /// How do we allow report generators to include new classes of override reasons if they
 /// want to be more specific than "other"?
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct PolicyOverrideReason {
     /// Type of override
     #[serde(rename = "type")]
@@ -106,7 +106,7 @@ pub struct PolicyOverrideReason {
 }
 
 /// Taking into account everything else in the record, the results of applying DMARC.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct PolicyEvaluated {
     /// Action taken
     pub disposition: Disposition,
@@ -119,7 +119,7 @@ pub struct PolicyEvaluated {
 }
 
 /// Row for each IP address
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Row {
     /// The connecting IP
     pub source_ip: IpAddr,
@@ -130,7 +130,7 @@ pub struct Row {
 }
 
 /// Row for each IP address
-#[derive(Debug, Default, Deserialize)]
+#[derive(Debug, Default, Deserialize, Serialize)]
 pub struct Identifier {
     /// The envelope recipient domain.
     pub envelope_to: Option<String>,
@@ -142,7 +142,7 @@ pub struct Identifier {
 
 /// DKIM verification result, according to RFC 7001 Section 2.6.1.
 #[allow(non_camel_case_types)]
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub enum DKIMResult {
     none,
     pass,
@@ -154,7 +154,7 @@ pub enum DKIMResult {
 }
 
 /// The DKIM Authentication result.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct DKIMAuthResult {
     /// The "d=" parameter in the signature.
     pub domain: String,
@@ -168,7 +168,7 @@ pub struct DKIMAuthResult {
 
 /// SPF domain scope.
 #[allow(non_camel_case_types)]
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub enum SPFDomainScope {
     helo,
     mfrom,
@@ -176,7 +176,7 @@ pub enum SPFDomainScope {
 
 /// The SPF result.
 #[allow(non_camel_case_types)]
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub enum SPFResult {
     none,
     neutral,
@@ -188,7 +188,7 @@ pub enum SPFResult {
 }
 
 /// The SPF Authentication result.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct SPFAuthResult {
     /// The checked domain.
     pub domain: String,
@@ -199,7 +199,7 @@ pub struct SPFAuthResult {
 }
 
 /// This element contains DKIM and SPF results, uninterpreted with respect to DMARC.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct AuthResult {
     /// There may be no DKIM signatures, or multiple DKIM signatures.
     pub dkim: Option<Vec<DKIMAuthResult>>,
@@ -209,7 +209,7 @@ pub struct AuthResult {
 
 /// This element contains all the authentication results that were evaluated by the
 /// receiving system for the given set of messages.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Record {
     /// Data about the specific record/IP
     pub row: Row,
@@ -223,7 +223,7 @@ pub struct Record {
 ///
 /// XXX in dmarc.xsd, this is a sequence (i.e. there could be several reports in a file) so
 /// this is a single report.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Report {
     /// Version of DMARC format
     pub version: f32,
@@ -264,6 +264,6 @@ mod tests {
             "noreply-dmarc-support@google.com",
             &item[0].report_metadata.email
         );
-        assert_eq!(2, (&item[0].record).len())
+        assert_eq!(2, item[0].record.len())
     }
 }
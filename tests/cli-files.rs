@@ -1,4 +1,5 @@
 use assert_cmd::Command;
+use predicates::prelude::*;
 
 const BIN: &str = "dmarc-cat";
 
@@ -7,3 +8,585 @@ fn test_unknown_file() {
     let mut cmd = Command::cargo_bin(BIN).unwrap();
     cmd.arg("/nonexistent").assert().failure();
 }
+
+#[test]
+fn test_stats() {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("-S")
+        .arg("testdata/google.com!keltia.net!1538438400!1538524799.xml")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("keltia.net"));
+}
+
+#[test]
+fn test_template_builtin() {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("-N")
+        .arg("--template")
+        .arg("oneline")
+        .arg("testdata/google.com!keltia.net!1538438400!1538524799.xml")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("google.com keltia.net p=none"));
+}
+
+#[test]
+fn test_template_unknown() {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("--template")
+        .arg("/nonexistent")
+        .arg("testdata/google.com!keltia.net!1538438400!1538524799.xml")
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_color_always() {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("-N")
+        .arg("--color")
+        .arg("always")
+        .arg("testdata/google.com!keltia.net!1538438400!1538524799.xml")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\x1b[33m"));
+}
+
+#[test]
+fn test_pipe_is_plain() {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("-N")
+        .arg("testdata/google.com!keltia.net!1538438400!1538524799.xml")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\x1b[").not());
+}
+
+#[test]
+fn test_sort_top() {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("-N")
+        .arg("--sort")
+        .arg("ip")
+        .arg("--order")
+        .arg("desc")
+        .arg("--top")
+        .arg("1")
+        .arg("testdata/google.com!keltia.net!1538438400!1538524799.xml")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("others"));
+}
+
+#[test]
+fn test_group() {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("-N")
+        .arg("-g")
+        .arg("--prefix6")
+        .arg("32")
+        .arg("testdata/example.com!example.net!1538438400!1538524799.xml")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("192.0.2.0/24"))
+        .stdout(predicate::str::contains("2001:db8::/32"));
+}
+
+#[test]
+fn test_group_asn() {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("-N")
+        .arg("--asn-db")
+        .arg("testdata/ip2asn-test.tsv")
+        .arg("--group-by")
+        .arg("asn")
+        .arg("testdata/example.com!example.net!1538438400!1538524799.xml")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("AS Name"))
+        .stdout(predicate::str::contains("AS64496"))
+        .stdout(predicate::str::contains("EXAMPLE-AS-3"));
+}
+
+#[test]
+fn test_asn_db_nonexistent() {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("--asn-db")
+        .arg("/nonexistent.mmdb")
+        .arg("testdata/example.com!example.net!1538438400!1538524799.xml")
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_fcrdns() {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("--resolve-special")
+        .arg("-N")
+        .arg("--fcrdns")
+        .arg("testdata/example.com!example.net!1538438400!1538524799.xml")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("FCrDNS"))
+        .stdout(predicate::str::contains(" pass "));
+}
+
+#[test]
+fn test_resolver_file() {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("--resolve-special")
+        .arg("--resolver")
+        .arg("file:testdata/hosts")
+        .arg("testdata/example.com!example.net!1538438400!1538524799.xml")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("mx1.example.net"))
+        .stdout(predicate::str::contains("mx6.example.net (+1)"))
+        .stdout(predicate::str::contains("(no PTR)"));
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn test_async() {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("--resolve-special")
+        .arg("--async")
+        .arg("--deadline")
+        .arg("30")
+        .arg("--resolver")
+        .arg("file:testdata/hosts")
+        .arg("testdata/example.com!example.net!1538438400!1538524799.xml")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("mx1.example.net"))
+        .stdout(predicate::str::contains("mx6.example.net (+1)"));
+}
+
+#[cfg(feature = "async")]
+#[test]
+fn test_async_nameserver_unreachable() {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("--async")
+        .arg("--nameserver")
+        .arg("127.0.0.1:1")
+        .arg("--dns-timeout")
+        .arg("100")
+        .arg("--dns-retries")
+        .arg("0")
+        .arg("testdata/google.com!keltia.net!1538438400!1538524799.xml")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("(DNS failure)"));
+}
+
+#[test]
+fn test_resolver_chain() {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("--resolve-special")
+        .arg("--resolver")
+        .arg("hosts:testdata/hosts,null")
+        .arg("testdata/example.com!example.net!1538438400!1538524799.xml")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("mx1.example.net"))
+        .stdout(predicate::str::contains(
+            "2001:db8:1:ff::26 2001:db8:1:ff::26",
+        ));
+}
+
+#[test]
+fn test_resolver_chain_invalid() {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("--resolver")
+        .arg("null,system")
+        .arg("testdata/example.com!example.net!1538438400!1538524799.xml")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "null can only be the last resolver",
+        ));
+}
+
+#[test]
+fn test_resolver_chain_cache() {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("--resolver")
+        .arg("cache,system")
+        .arg("testdata/example.com!example.net!1538438400!1538524799.xml")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("needs --cache"));
+}
+
+#[test]
+fn test_special() {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("--resolver")
+        .arg("file:testdata/hosts")
+        .arg("testdata/example.com!example.net!1538438400!1538524799.xml")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "192.0.2.10        (documentation)",
+        ))
+        .stdout(predicate::str::contains("mx1.example.net").not());
+}
+
+#[test]
+fn test_export_ips() {
+    let file = std::env::temp_dir().join(format!("dmarc-cat-ips-{}.csv", std::process::id()));
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("--resolve-special")
+        .arg("--resolver")
+        .arg("file:testdata/hosts")
+        .arg("--export-ips")
+        .arg(&file)
+        .arg("testdata/example.com!example.net!1538438400!1538524799.xml")
+        .assert()
+        .success();
+    let s = std::fs::read_to_string(&file).unwrap();
+    let _ = std::fs::remove_file(&file);
+    assert_eq!(
+        "ip,name\n192.0.2.10,mx1.example.net\n192.0.2.20,mx2.example.net\n198.51.100.7,\n\
+         2001:db8:1:2::25,mx6.example.net\n2001:db8:1:ff::26,\n",
+        s
+    );
+}
+
+#[test]
+fn test_dnsbl() {
+    let file = std::env::temp_dir().join(format!("dmarc-cat-dnsbl-{}", std::process::id()));
+    std::fs::write(
+        &file,
+        "192.0.2.10 mx1.example.net\n127.0.0.2 10.2.0.192.bl.example\n",
+    )
+    .unwrap();
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    let out = cmd
+        .arg("--resolve-special")
+        .arg("--resolver")
+        .arg(format!("hosts:{}", file.display()))
+        .arg("--dnsbl")
+        .arg("bl.example")
+        .arg("testdata/example.com!example.net!1538438400!1538524799.xml")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("DNSBL"))
+        .stdout(predicate::str::contains("mx1.example.net"))
+        .get_output()
+        .stdout
+        .clone();
+    let _ = std::fs::remove_file(&file);
+
+    // The blocklist is not asked through the PTR resolvers
+    let s = String::from_utf8(out).unwrap();
+    assert!(!s.contains("bl.example"), "{}", s);
+}
+
+#[test]
+fn test_dnsbl_noresolve() {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("-N")
+        .arg("--dnsbl")
+        .arg("bl.example")
+        .arg("testdata/example.com!example.net!1538438400!1538524799.xml")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("cannot be used with"));
+}
+
+#[test]
+fn test_allowlist() {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("-N")
+        .arg("--allowlist")
+        .arg("testdata/allowlist.txt")
+        .arg("testdata/example.com!example.net!1538438400!1538524799.xml")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("192.0.2.20"))
+        .stdout(predicate::str::contains("192.0.2.10").not())
+        .stdout(predicate::str::contains("2001:db8:1:2::25").not());
+}
+
+#[test]
+fn test_allowlist_invalid() {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("-N")
+        .arg("--allowlist")
+        .arg("testdata/hosts")
+        .arg("testdata/example.com!example.net!1538438400!1538524799.xml")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid IP address"));
+}
+
+#[test]
+fn test_resolver_invalid() {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("--resolver")
+        .arg("file:/nonexistent")
+        .arg("testdata/example.com!example.net!1538438400!1538524799.xml")
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_nameserver_unreachable() {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("--nameserver")
+        .arg("127.0.0.1:1")
+        .arg("--dns-timeout")
+        .arg("100")
+        .arg("--dns-retries")
+        .arg("0")
+        .arg("testdata/google.com!keltia.net!1538438400!1538524799.xml")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("(DNS failure)"));
+}
+
+#[test]
+fn test_nameserver_invalid() {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("--nameserver")
+        .arg("ns.example.net")
+        .arg("testdata/google.com!keltia.net!1538438400!1538524799.xml")
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_progress() {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("-v")
+        .arg("--resolver")
+        .arg("hosts:testdata/hosts")
+        .arg("testdata/google.com!keltia.net!1538438400!1538524799.xml")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "resolving 2 unique IPs out of 2 records",
+        ))
+        .stderr(predicate::str::contains("resolved 1/2 unique IPs"))
+        .stderr(predicate::str::contains("resolved 2/2 unique IPs"));
+}
+
+#[test]
+fn test_dns_rate() {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("-v")
+        .arg("--dns-rate")
+        .arg("100")
+        .arg("--dns-burst")
+        .arg("1")
+        .arg("--resolver")
+        .arg("dns:127.0.0.1:1")
+        .arg("--dns-timeout")
+        .arg("100")
+        .arg("--dns-retries")
+        .arg("0")
+        .arg("testdata/google.com!keltia.net!1538438400!1538524799.xml")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("queries throttled"));
+}
+
+#[test]
+fn test_dns_metrics() {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("-v")
+        .arg("--resolver")
+        .arg("dns:127.0.0.1:1")
+        .arg("--dns-timeout")
+        .arg("100")
+        .arg("--dns-retries")
+        .arg("0")
+        .arg("testdata/google.com!keltia.net!1538438400!1538524799.xml")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("DNS: 2 lookups"))
+        .stderr(predicate::str::contains("DNS latency:"));
+}
+
+#[test]
+fn test_dns_rate_invalid() {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("--dns-rate")
+        .arg("0")
+        .arg("testdata/google.com!keltia.net!1538438400!1538524799.xml")
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_many_jobs() {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("--resolve-special")
+        .arg("--resolver")
+        .arg("file:testdata/hosts")
+        .arg("-j")
+        .arg("64")
+        .arg("testdata/example.com!example.net!1538438400!1538524799.xml")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("mx1.example.net"))
+        .stderr(predicate::str::contains("192.0.2").not());
+}
+
+#[test]
+fn test_no_jobs() {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("-N")
+        .arg("-j")
+        .arg("0")
+        .arg("testdata/example.com!example.net!1538438400!1538524799.xml")
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_cache_clear() {
+    let cache = std::env::temp_dir().join(format!("dmarc-cat-cache-{}.json", std::process::id()));
+    std::fs::write(&cache, "{\"version\":1}").unwrap();
+
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("--cache")
+        .arg(&cache)
+        .arg("--cache-clear")
+        .assert()
+        .success();
+    assert!(!cache.exists());
+}
+
+#[test]
+fn test_cache_chain_null() {
+    let cache = std::env::temp_dir().join(format!("dmarc-cat-null-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&cache);
+
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("--cache")
+        .arg(&cache)
+        .arg("--resolver")
+        .arg("hosts:testdata/hosts,null")
+        .arg("testdata/google.com!keltia.net!1538438400!1538524799.xml")
+        .assert()
+        .success();
+
+    // The IPs given back by null are not names to keep
+    let s = std::fs::read_to_string(&cache).unwrap();
+    assert!(!s.contains("[\"217.70.183.200\"]"), "{}", s);
+    assert!(s.contains("\"217.70.183.200\""), "{}", s);
+    std::fs::remove_file(&cache).unwrap();
+}
+
+#[test]
+fn test_cache_only_null() {
+    let cache = std::env::temp_dir().join(format!("dmarc-cat-only-{}.json", std::process::id()));
+    let _ = std::fs::remove_file(&cache);
+
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("--cache")
+        .arg(&cache)
+        .arg("--resolver")
+        .arg("null")
+        .arg("testdata/google.com!keltia.net!1538438400!1538524799.xml")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("--cache is not used"));
+
+    // Nothing was looked up, so nothing is kept
+    let s = std::fs::read_to_string(&cache).unwrap_or_default();
+    assert!(!s.contains("217.70.183.200"), "{}", s);
+    let _ = std::fs::remove_file(&cache);
+}
+
+#[test]
+fn test_cache_invalid() {
+    let cache = std::env::temp_dir().join(format!("dmarc-cat-bad-{}.json", std::process::id()));
+    std::fs::write(&cache, "garbage").unwrap();
+
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("-N")
+        .arg("--cache")
+        .arg(&cache)
+        .arg("testdata/example.com!example.net!1538438400!1538524799.xml")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("starting with an empty cache"));
+    std::fs::remove_file(&cache).unwrap();
+}
+
+#[test]
+fn test_sender_rules() {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("-N")
+        .arg("--sender-rules")
+        .arg("testdata/senders.toml")
+        .arg("--sender")
+        .arg("IPv6 relays")
+        .arg("testdata/example.com!example.net!1538438400!1538524799.xml")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Reports(1):"))
+        .stdout(predicate::str::contains("Sender"))
+        .stdout(predicate::str::contains("2001:db8:1:2::25"));
+}
+
+#[test]
+fn test_sender_rules_invalid() {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("--sender-rules")
+        .arg("testdata/ip2asn-test.tsv")
+        .arg("testdata/example.com!example.net!1538438400!1538524799.xml")
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_sender_rules_nonexistent() {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("--sender-rules")
+        .arg("/nonexistent")
+        .arg("testdata/example.com!example.net!1538438400!1538524799.xml")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("/nonexistent: "));
+}
+
+#[test]
+fn test_group_invalid_prefix() {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("-g")
+        .arg("--prefix4")
+        .arg("33")
+        .arg("testdata/example.com!example.net!1538438400!1538524799.xml")
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_fcrdns_noresolve() {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    let out = cmd
+        .arg("-N")
+        .arg("--fcrdns")
+        .arg("--resolve-special")
+        .arg("testdata/example.com!example.net!1538438400!1538524799.xml")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("FCrDNS"))
+        .get_output()
+        .stdout
+        .clone();
+    // IP, name (the IP itself) then the count, no FCrDNS result
+    let s = String::from_utf8(out).unwrap();
+    let row: Vec<&str> = s
+        .lines()
+        .find(|l| l.starts_with("192.0.2.10 "))
+        .unwrap()
+        .split_whitespace()
+        .collect();
+    assert_eq!(vec!["192.0.2.10", "192.0.2.10", "10"], row[..3]);
+}
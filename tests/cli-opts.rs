@@ -32,3 +32,33 @@ fn test_invalid_type_ok() {
     let mut cmd = Command::cargo_bin(BIN).unwrap();
     cmd.arg("-t").arg("txt").assert().success();
 }
+
+#[test]
+fn test_invalid_color() {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("--color").arg("sometimes").assert().failure();
+}
+
+#[test]
+fn test_sender_without_rules() {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("--sender")
+        .arg("unknown")
+        .arg("testdata/example.com!example.net!1538438400!1538524799.xml")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("--sender-rules"));
+}
+
+#[test]
+fn test_nameserver_with_resolver() {
+    let mut cmd = Command::cargo_bin(BIN).unwrap();
+    cmd.arg("--resolver")
+        .arg("null")
+        .arg("--nameserver")
+        .arg("127.0.0.1")
+        .arg("testdata/example.com!example.net!1538438400!1538524799.xml")
+        .assert()
+        .failure()
+        .stderr(predicates::str::contains("cannot be used with"));
+}